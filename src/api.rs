@@ -1,4 +1,19 @@
 pub mod common;
+pub mod config;
+pub mod fsck;
+pub mod gc;
+pub mod ident;
+pub mod ignore;
+pub mod index;
+pub mod lockfile;
 pub mod objects;
+pub mod pathspec;
+pub mod reachable;
+pub mod ref_filter;
 pub mod reflog;
+pub mod refs;
+pub mod repack;
+pub mod repository;
+pub mod revision;
 pub mod tree;
+pub mod worktree;
@@ -1,2 +1,6 @@
 pub mod user;
 pub mod datetime;
+pub mod error;
+pub mod regex;
+pub mod varint;
+pub mod wildmatch;
@@ -1,7 +1,12 @@
 use std::fmt::Display;
+use std::io;
+use std::str::FromStr;
 
-use chrono::{DateTime, FixedOffset, Local, Offset, TimeZone};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone};
 
+use super::error::invalid_data;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Timezone {
     tz_sec: i32
 }
@@ -15,13 +20,17 @@ impl Timezone {
         Self::from_sec(offset.fix().local_minus_utc())
     }
 
-    pub fn to_chrono_offset(self) -> FixedOffset {
+    pub fn to_chrono_offset(&self) -> FixedOffset {
         if self.tz_sec >= 0 {
             FixedOffset::east(self.tz_sec)
         } else {
             FixedOffset::west(-self.tz_sec)
         }
     }
+
+    pub fn sec(&self) -> i32 {
+        self.tz_sec
+    }
 }
 
 impl Display for Timezone {
@@ -35,12 +44,33 @@ impl Display for Timezone {
     }
 }
 
+impl FromStr for Timezone {
+    type Err = io::Error;
+
+    // "+0900" や "-0130" の形式
+    fn from_str(s: &str) -> io::Result<Self> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 5 || !(bytes[0] == b'+' || bytes[0] == b'-') || !bytes[1..].iter().all(u8::is_ascii_digit) {
+            return Err(invalid_data(format!("invalid timezone: {:?}", s)));
+        }
+        let hours: i32 = s[1..3].parse().unwrap();
+        let minutes: i32 = s[3..5].parse().unwrap();
+        let sec = (hours * 60 + minutes) * 60;
+        Ok(Self::from_sec(if bytes[0] == b'-' { -sec } else { sec }))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Timestamp {
     epoch: i64,
     timezone: Timezone,
 }
 
 impl Timestamp {
+    pub fn new(epoch: i64, timezone: Timezone) -> Self {
+        Self { epoch, timezone }
+    }
+
     pub fn now() -> Self {
         Self::from_datetime(Local::now())
     }
@@ -50,7 +80,19 @@ impl Timestamp {
             epoch: datetime.timestamp(),
             timezone: Timezone::from_chrono_offset(datetime.offset().fix()),
         }
-  }
+    }
+
+    pub fn to_datetime(&self) -> DateTime<FixedOffset> {
+        self.timezone.to_chrono_offset().timestamp(self.epoch, 0)
+    }
+
+    pub fn epoch(&self) -> i64 {
+        self.epoch
+    }
+
+    pub fn timezone(&self) -> Timezone {
+        self.timezone
+    }
 }
 
 impl Display for Timestamp {
@@ -58,3 +100,181 @@ impl Display for Timestamp {
         write!(f, "{} {}", self.epoch, self.timezone)
     }
 }
+
+impl FromStr for Timestamp {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (epoch, timezone) = match s.split_once(' ') {
+            Some(pair) => pair,
+            None => return Err(invalid_data(format!("invalid timestamp: {:?}", s))),
+        };
+        let epoch = epoch.parse().map_err(|_| invalid_data(format!("invalid timestamp: {:?}", s)))?;
+        Ok(Self::new(epoch, timezone.parse()?))
+    }
+}
+
+// git log --date=<format> などで使う日付の表示形式
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DateFormat {
+    Default,
+    Iso,
+    IsoStrict,
+    Rfc,
+    Short,
+    Raw,
+    Unix,
+    Relative,
+    // "format:<strftime>"
+    Custom(String),
+}
+
+impl FromStr for DateFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "default" => Ok(DateFormat::Default),
+            "iso" | "iso8601" => Ok(DateFormat::Iso),
+            "iso-strict" | "iso8601-strict" => Ok(DateFormat::IsoStrict),
+            "rfc" | "rfc2822" => Ok(DateFormat::Rfc),
+            "short" => Ok(DateFormat::Short),
+            "raw" => Ok(DateFormat::Raw),
+            "unix" => Ok(DateFormat::Unix),
+            "relative" => Ok(DateFormat::Relative),
+            _ => match s.strip_prefix("format:") {
+                Some(format) => Ok(DateFormat::Custom(format.to_string())),
+                None => Err(invalid_data(format!("unknown date format {}", s))),
+            },
+        }
+    }
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    if n == 1 { format!("{} {}", n, unit) } else { format!("{} {}s", n, unit) }
+}
+
+// git の show_date_relative と同じ丸め方で "3 days ago" のように表す
+fn relative_date(epoch: i64, now: i64) -> String {
+    if epoch > now {
+        return "in the future".to_string();
+    }
+    let mut diff = now - epoch;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+impl Timestamp {
+    // 記録されたタイムゾーンでの時刻として表示する
+    pub fn format(&self, format: &DateFormat) -> io::Result<String> {
+        use std::fmt::Write;
+
+        let datetime = self.to_datetime();
+        let pattern = match format {
+            DateFormat::Default => "%a %b %-d %H:%M:%S %Y %z",
+            DateFormat::Iso => "%Y-%m-%d %H:%M:%S %z",
+            DateFormat::IsoStrict => "%Y-%m-%dT%H:%M:%S%:z",
+            DateFormat::Rfc => "%a, %-d %b %Y %H:%M:%S %z",
+            DateFormat::Short => "%Y-%m-%d",
+            DateFormat::Raw => return Ok(self.to_string()),
+            DateFormat::Unix => return Ok(self.epoch.to_string()),
+            DateFormat::Relative => return Ok(relative_date(self.epoch, Timestamp::now().epoch())),
+            DateFormat::Custom(pattern) => pattern,
+        };
+        // 不正な strftime の指定はエラーにする (to_string では panic する)
+        let mut out = String::new();
+        write!(out, "{}", datetime.format(pattern)).map_err(|_| invalid_data(format!("invalid date format: {}", pattern)))?;
+        Ok(out)
+    }
+}
+
+fn unit_seconds(unit: &str) -> Option<i64> {
+    match unit.trim_end_matches('s') {
+        "second" | "sec" => Some(1),
+        "minute" | "min" => Some(60),
+        "hour" => Some(60 * 60),
+        "day" => Some(24 * 60 * 60),
+        "week" => Some(7 * 24 * 60 * 60),
+        "month" => Some(30 * 24 * 60 * 60),
+        "year" => Some(365 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+// git の approxidate のうちよく使われる形式 ("now", "2.weeks.ago", "2021-09-16 00:00:00", "@1631017871" など) を
+// エポック秒に変換する
+pub fn parse_approxidate(s: &str, now: i64) -> io::Result<i64> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+    match lower.as_str() {
+        "now" => return Ok(now),
+        "all" => return Ok(i64::MAX),
+        "never" | "false" => return Ok(0),
+        "yesterday" => return Ok(now - 24 * 60 * 60),
+        _ => {},
+    }
+
+    let digits = s.strip_prefix('@').unwrap_or(s);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return digits.parse().map_err(|_| invalid_data(format!("invalid date: {:?}", s)));
+    }
+
+    let words: Vec<&str> = lower.split(['.', ' ']).filter(|w| !w.is_empty()).collect();
+    if words.len() == 3 && words[2] == "ago" {
+        if let (Ok(n), Some(unit)) = (words[0].parse::<i64>(), unit_seconds(words[1])) {
+            return Ok(now - n * unit);
+        }
+    }
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.timestamp());
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc2822(s) {
+        return Ok(datetime.timestamp());
+    }
+    if let Ok(datetime) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z") {
+        return Ok(datetime.timestamp());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"].iter() {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(s, format) {
+            if let Some(datetime) = Local.from_local_datetime(&naive).earliest() {
+                return Ok(datetime.timestamp());
+            }
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        if let Some(datetime) = Local.from_local_datetime(&date.and_hms(0, 0, 0)).earliest() {
+            return Ok(datetime.timestamp());
+        }
+    }
+
+    Err(invalid_data(format!("invalid date: {:?}", s)))
+}
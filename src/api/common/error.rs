@@ -0,0 +1,5 @@
+use std::io;
+
+pub fn invalid_data<E>(error: E) -> io::Error where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,260 @@
+use std::io;
+
+use super::error::invalid_data;
+
+// POSIX 拡張正規表現 (ERE) を regex クレートの構文に書き換えて照合する
+// git は REG_NEWLINE なしで regcomp するので、'.' は改行にも一致し、^ と $ は文字列の先頭と末尾にだけ一致する
+
+const CLASS_NAMES: [&str; 12] = [
+    "alpha", "digit", "alnum", "upper", "lower", "space", "blank", "punct", "xdigit", "cntrl", "print", "graph",
+];
+
+fn error(message: &str) -> io::Error {
+    invalid_data(format!("invalid regular expression: {}", message))
+}
+
+// regex クレートでは特別な意味を持つ文字をブラケットの中で文字そのものとして書く
+fn push_class_char(out: &mut String, c: char) {
+    if matches!(c, '\\' | '[' | ']' | '-' | '^' | '&' | '~') {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+struct Translator<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    out: String,
+}
+
+impl Translator<'_> {
+    fn translate(&mut self) -> io::Result<()> {
+        // 直前に繰り返しの対象になるものがあるか
+        let mut has_atom = false;
+        let mut depth = 0;
+        while let Some(c) = self.chars.next() {
+            match c {
+                '[' => {
+                    self.translate_class()?;
+                    has_atom = true;
+                    continue;
+                },
+                '\\' => {
+                    let escaped = self.chars.next().ok_or_else(|| error("trailing backslash (\\)"))?;
+                    match escaped {
+                        // GNU の拡張
+                        'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                            self.out.push('\\');
+                            self.out.push(escaped);
+                        },
+                        '<' => self.out.push_str(r"\b{start}"),
+                        '>' => self.out.push_str(r"\b{end}"),
+                        '`' => self.out.push_str(r"\A"),
+                        '\'' => self.out.push_str(r"\z"),
+                        _ => self.out.push_str(&regex::escape(escaped.encode_utf8(&mut [0; 4]))),
+                    }
+                    has_atom = !matches!(escaped, 'b' | 'B' | '<' | '>' | '`' | '\'');
+                    continue;
+                },
+                '*' | '+' | '?' if !has_atom => return Err(error("invalid preceding regular expression")),
+                '{' if !has_atom => return Err(error("invalid preceding regular expression")),
+                '{' => {
+                    let bound = self.parse_bound()?;
+                    self.out.push_str(&bound);
+                    continue;
+                },
+                '}' => self.out.push_str(r"\}"),
+                '(' => {
+                    depth += 1;
+                    self.out.push(c);
+                },
+                // 対応する '(' のない ')' は文字そのもの
+                ')' if depth == 0 => self.out.push_str(r"\)"),
+                ')' => {
+                    depth -= 1;
+                    self.out.push(c);
+                },
+                _ => self.out.push(c),
+            }
+            has_atom = !matches!(c, '(' | '|' | '^');
+        }
+        Ok(())
+    }
+
+    // "{m}"・"{m,}"・"{m,n}"・"{,n}" の形の繰り返しの回数
+    fn parse_bound(&mut self) -> io::Result<String> {
+        let mut rest = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c) => rest.push(c),
+                None => return Err(error("unmatched \\{")),
+            }
+        }
+        let invalid = || error("invalid content of \\{\\}");
+        let number = |s: &str| s.parse::<u32>().map_err(|_| invalid());
+        match rest.split_once(',') {
+            Some((min, "")) => Ok(format!("{{{},}}", number(min)?)),
+            Some((min, max)) => {
+                let min = if min.is_empty() { 0 } else { number(min)? };
+                let max = number(max)?;
+                if max < min {
+                    return Err(invalid());
+                }
+                Ok(format!("{{{},{}}}", min, max))
+            },
+            None => Ok(format!("{{{}}}", number(&rest)?)),
+        }
+    }
+
+    // ブラケット式を書き換える。']' は先頭 (または '^' の直後) なら文字そのもの、'-' は末尾なら文字そのもの
+    fn translate_class(&mut self) -> io::Result<()> {
+        let unmatched = || error("unmatched [, [^, [:, [., or [=");
+        self.out.push('[');
+        if self.chars.peek() == Some(&'^') {
+            self.chars.next();
+            self.out.push('^');
+        }
+        let mut first = true;
+        loop {
+            let c = self.chars.next().ok_or_else(unmatched)?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let from = if c == '[' && matches!(self.chars.peek(), Some(':') | Some('.') | Some('=')) {
+                let kind = self.chars.next().unwrap();
+                let mut name = String::new();
+                loop {
+                    let c = self.chars.next().ok_or_else(unmatched)?;
+                    if c == kind && self.chars.peek() == Some(&']') {
+                        self.chars.next();
+                        break;
+                    }
+                    name.push(c);
+                }
+                if kind == ':' {
+                    if !CLASS_NAMES.contains(&name.as_str()) {
+                        return Err(error("invalid character class"));
+                    }
+                    self.out.push_str(&format!("[:{}:]", name));
+                    continue;
+                }
+                // 照合要素 [.x.] と等価クラス [=x=] は 1 文字のものだけを扱う
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(error("invalid collation character")),
+                }
+            } else {
+                c
+            };
+            // "a-z" のような範囲 ("-" が末尾にあれば文字そのもの)
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && !matches!(lookahead.next(), Some(']') | None) {
+                self.chars.next();
+                let to = self.chars.next().ok_or_else(unmatched)?;
+                if to < from {
+                    return Err(error("invalid range end"));
+                }
+                push_class_char(&mut self.out, from);
+                self.out.push('-');
+                push_class_char(&mut self.out, to);
+            } else {
+                push_class_char(&mut self.out, from);
+            }
+        }
+        self.out.push(']');
+        Ok(())
+    }
+}
+
+pub struct Regex {
+    regex: regex::Regex,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> io::Result<Self> {
+        let mut translator = Translator { chars: pattern.chars().peekable(), out: String::from("(?s)") };
+        translator.translate()?;
+        let regex = regex::Regex::new(&translator.out).map_err(|e| {
+            // 複数行の説明の最後の行 ("error: ...") だけを使う
+            let message = e.to_string();
+            let message = message.lines().last().unwrap_or("").trim_start_matches("error: ").to_string();
+            error(&message)
+        })?;
+        Ok(Self { regex })
+    }
+
+    // text のどこかに一致する部分があるか
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(matches("foo|bar", "xbarx"));
+        assert!(matches("^(foo|bar)$", "foo"));
+        assert!(!matches("^(foo|bar)$", "foobar"));
+        assert!(matches("a(|b)c", "ac"));
+    }
+
+    #[test]
+    fn bounds() {
+        assert!(matches("^a{2}$", "aa"));
+        assert!(!matches("^a{2}$", "aaa"));
+        assert!(matches("^a{2,}$", "aaaa"));
+        assert!(matches("^a{1,3}b$", "aaab"));
+        assert!(!matches("^a{1,3}b$", "aaaab"));
+        assert!(matches("^a{,2}$", ""));
+        assert!(!matches("^a{,2}$", "aaa"));
+        assert!(matches(r"^a\{2\}$", "a{2}"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("^[[:digit:]]+$", "0123"));
+        assert!(!matches("^[[:digit:]]+$", "01a"));
+        assert!(matches("^[[:alpha:][:space:]]+$", "ab c"));
+        assert!(matches("^[^[:upper:]]$", "a"));
+        assert!(matches("^[]a]+$", "]a]"));
+        assert!(matches("^[^]]$", "x"));
+        assert!(matches("^[a-c-]+$", "a-b"));
+        // ブラケットの中の '\' や '[' は文字そのもの
+        assert!(matches(r"^[\]+$", r"\\"));
+        assert!(matches("^[a[]+$", "[a"));
+        assert!(matches("^[&&~~]$", "&"));
+        assert!(matches("^[[.-.]]$", "-"));
+    }
+
+    #[test]
+    fn anchors_and_dot() {
+        assert!(matches("^abc", "abcdef"));
+        assert!(!matches("^bcd", "abcdef"));
+        assert!(matches("def$", "abcdef"));
+        assert!(!matches("^b", "a\nb"));
+        assert!(matches("a.b", "a\nb"));
+        assert!(matches(r"a\.b", "a.b"));
+        assert!(!matches(r"a\.b", "axb"));
+        assert!(matches(r"\<word\>", "a word here"));
+        assert!(!matches(r"\<word\>", "swordfish"));
+        // 対応する '(' のない ')' は文字そのもの
+        assert!(matches("a)", "a)"));
+    }
+
+    #[test]
+    fn errors() {
+        for pattern in ["(", "[a", "[[:foo:]]", "[z-a]", "*a", "a|*", "^*", r"a\", "{1}", "a{", "a{x}", "a{2,1}", "x{2"] {
+            let error = Regex::new(pattern).err().unwrap_or_else(|| panic!("{:?} should be rejected", pattern));
+            assert!(error.to_string().starts_with("invalid regular expression: "), "{}", error);
+        }
+    }
+}
@@ -1,10 +1,33 @@
 use std::fmt::Display;
+use std::io;
 
+use super::datetime::Timestamp;
+use super::error::invalid_data;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct User {
     pub name: String,
     pub email: String,
 }
 
+impl User {
+    // "Name <email> 1631017871 +0900" の形式の行を User と Timestamp に分解する
+    pub fn parse_with_timestamp(s: &str) -> io::Result<(Self, Timestamp)> {
+        let invalid = || invalid_data(format!("invalid identity: {:?}", s));
+
+        let email_start = s.find('<').ok_or_else(invalid)?;
+        let email_end = email_start + s[email_start..].find('>').ok_or_else(invalid)?;
+
+        let user = Self {
+            name: s[..email_start].trim_end().to_string(),
+            email: s[email_start + 1..email_end].to_string(),
+        };
+        let timestamp = s[email_end + 1..].trim_start().parse()?;
+
+        Ok((user, timestamp))
+    }
+}
+
 impl Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} <{}>", self.name, self.email)
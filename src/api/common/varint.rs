@@ -0,0 +1,36 @@
+use std::io;
+
+use super::error::invalid_data;
+
+// index v4 のパス圧縮や packfile の OFS_DELTA で使われる可変長整数
+// (後続バイトがある場合に 1 を足してから 7bit シフトする形式)
+pub fn encode_offset_varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+// (値, 読んだバイト数) を返す
+pub fn decode_offset_varint(bytes: &[u8]) -> io::Result<(u64, usize)> {
+    let mut iter = bytes.iter();
+    let truncated = || invalid_data("truncated variable-length integer");
+
+    let mut c = *iter.next().ok_or_else(truncated)?;
+    let mut value = (c & 0x7f) as u64;
+    let mut used = 1;
+    while c & 0x80 != 0 {
+        c = *iter.next().ok_or_else(truncated)?;
+        used += 1;
+        if value >= u64::MAX >> 7 {
+            return Err(invalid_data("variable-length integer overflow"));
+        }
+        value = ((value + 1) << 7) | (c & 0x7f) as u64;
+    }
+    Ok((value, used))
+}
@@ -0,0 +1,153 @@
+// git の wildmatch 相当のグロブ照合
+// pathname が true の場合、'*' や '?' は '/' にマッチせず、"**" のみがディレクトリをまたぐ
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    match_bytes(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn match_bytes(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let star_start = p;
+                while p < pattern.len() && pattern[p] == b'*' {
+                    p += 1;
+                }
+                let double = p - star_start >= 2;
+                let at_boundary = star_start == 0 || pattern[star_start - 1] == b'/';
+
+                if pathname && double && at_boundary {
+                    if p == pattern.len() {
+                        return true;
+                    }
+                    if pattern[p] == b'/' {
+                        // "**/" は 0 個以上のディレクトリにマッチする
+                        let rest = &pattern[p + 1..];
+                        if match_bytes(rest, &text[t..], pathname) {
+                            return true;
+                        }
+                        return (t..text.len())
+                            .filter(|&i| text[i] == b'/')
+                            .any(|i| match_bytes(rest, &text[i + 1..], pathname));
+                    }
+                }
+
+                let rest = &pattern[p..];
+                let mut i = t;
+                loop {
+                    if match_bytes(rest, &text[i..], pathname) {
+                        return true;
+                    }
+                    if i == text.len() || (pathname && text[i] == b'/') {
+                        return false;
+                    }
+                    i += 1;
+                }
+            },
+            b'?' => {
+                if t == text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            },
+            b'[' => {
+                if t == text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                match match_class(&pattern[p + 1..], text[t]) {
+                    Some((matched, used)) => {
+                        if !matched {
+                            return false;
+                        }
+                        p += 1 + used;
+                        t += 1;
+                    },
+                    // 閉じられていない '[' は文字として扱う
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    },
+                }
+            },
+            b'\\' if p + 1 < pattern.len() => {
+                if t == text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            },
+            c => {
+                if t == text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            },
+        }
+    }
+
+    t == text.len()
+}
+
+// '[' の直後から文字クラスを解釈し、(マッチしたか, ']' までに消費したバイト数) を返す
+fn match_class(class: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(class.first(), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        let mut start = class[i];
+        if start == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        if start == b'[' && class.get(i + 1) == Some(&b':') {
+            if let Some(end) = class[i + 2..].windows(2).position(|w| w == b":]") {
+                let name = &class[i + 2..i + 2 + end];
+                matched |= match name {
+                    b"alnum" => c.is_ascii_alphanumeric(),
+                    b"alpha" => c.is_ascii_alphabetic(),
+                    b"digit" => c.is_ascii_digit(),
+                    b"lower" => c.is_ascii_lowercase(),
+                    b"upper" => c.is_ascii_uppercase(),
+                    b"space" => c.is_ascii_whitespace(),
+                    b"punct" => c.is_ascii_punctuation(),
+                    b"xdigit" => c.is_ascii_hexdigit(),
+                    _ => false,
+                };
+                i += 2 + end + 2;
+                continue;
+            }
+        }
+
+        if start == b'\\' && i + 1 < class.len() {
+            i += 1;
+            start = class[i];
+        }
+        if class.get(i + 1) == Some(&b'-') && i + 2 < class.len() && class[i + 2] != b']' {
+            let mut end = class[i + 2];
+            let mut used = 3;
+            if end == b'\\' && i + 3 < class.len() {
+                end = class[i + 3];
+                used = 4;
+            }
+            matched |= start <= c && c <= end;
+            i += used;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+    None
+}
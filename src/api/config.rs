@@ -0,0 +1,739 @@
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::common::error::invalid_data;
+use super::common::regex::Regex;
+use super::common::wildmatch::wildmatch;
+use super::lockfile::LockFile;
+use super::refs::{RefTarget, read_ref};
+use super::repository::Repository;
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+}
+
+impl Display for ConfigScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::Worktree => "worktree",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// "section.subsection.key" 形式のキー (section と key は小文字に正規化) と値の組
+// "key" だけで "=" のない行の値は None になる
+#[derive(Clone, Debug)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: Option<String>,
+    pub origin: Option<PathBuf>,
+    pub scope: Option<ConfigScope>,
+}
+
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+// ファイル中の位置 (バイト単位) 付きで読んだ要素。書き換えのときに使う
+enum Event {
+    // "section" または "section.subsection" (section は小文字)
+    Section { name: String, start: usize, end: usize },
+    Entry { key: String, value: Option<String>, start: usize, end: usize },
+}
+
+fn is_key_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-'
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+    path: Option<&'a Path>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self) -> io::Error {
+        match self.path {
+            Some(path) => invalid_data(format!("bad config line {} in file {}", self.line, path.display())),
+            None => invalid_data(format!("bad config line {}", self.line)),
+        }
+    }
+
+    // "\r\n" は "\n" として読む。ファイルの終わりは None
+    fn next_char(&mut self) -> Option<u8> {
+        let c = *self.src.get(self.pos)?;
+        self.pos += 1;
+        if c == b'\r' && self.src.get(self.pos) == Some(&b'\n') {
+            self.pos += 1;
+            self.line += 1;
+            return Some(b'\n');
+        }
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.src[..pos].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next_char() {
+            if c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    // 値の前後の空白は除き、引用符の外の空白の並びは空白文字として残す
+    fn parse_value(&mut self) -> io::Result<String> {
+        let mut value = Vec::new();
+        let mut quote = false;
+        let mut comment = false;
+        let mut space = 0;
+        loop {
+            let c = match self.next_char() {
+                Some(b'\n') | None if quote => return Err(self.error()),
+                Some(b'\n') | None => break,
+                Some(c) => c,
+            };
+            if comment {
+                continue;
+            }
+            if c.is_ascii_whitespace() && !quote {
+                if !value.is_empty() {
+                    space += 1;
+                }
+                continue;
+            }
+            if !quote && (c == b';' || c == b'#') {
+                comment = true;
+                continue;
+            }
+            value.extend(std::iter::repeat_n(b' ', space));
+            space = 0;
+            match c {
+                b'\\' => match self.next_char() {
+                    Some(b'\n') => {},
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'b') => value.push(b'\x08'),
+                    Some(b'n') => value.push(b'\n'),
+                    Some(c @ b'\\') | Some(c @ b'"') => value.push(c),
+                    _ => return Err(self.error()),
+                },
+                b'"' => quote = !quote,
+                c => value.push(c),
+            }
+        }
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    // "[section]"、"[section \"subsection\"]"、古い形式の "[section.subsection]" を読む
+    fn parse_section(&mut self) -> io::Result<String> {
+        let mut name = String::new();
+        loop {
+            match self.next_char() {
+                Some(b']') => return Ok(name),
+                Some(c) if c == b' ' || c == b'\t' => break,
+                Some(c) if is_key_char(c) || c == b'.' => name.push(c.to_ascii_lowercase() as char),
+                _ => return Err(self.error()),
+            }
+        }
+        while let Some(b' ') | Some(b'\t') = self.peek() {
+            self.next_char();
+        }
+        if self.next_char() != Some(b'"') {
+            return Err(self.error());
+        }
+        let mut subsection = Vec::new();
+        loop {
+            match self.next_char() {
+                Some(b'"') => break,
+                Some(b'\\') => match self.next_char() {
+                    Some(b'\n') | None => return Err(self.error()),
+                    Some(c) => subsection.push(c),
+                },
+                Some(b'\n') | None => return Err(self.error()),
+                Some(c) => subsection.push(c),
+            }
+        }
+        if self.next_char() != Some(b']') {
+            return Err(self.error());
+        }
+        Ok(format!("{}.{}", name, String::from_utf8_lossy(&subsection)))
+    }
+
+    fn parse(mut self) -> io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+        let mut section = None;
+        if self.src.starts_with(b"\xef\xbb\xbf") {
+            self.pos = 3;
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_whitespace() {
+                self.next_char();
+                continue;
+            }
+            if c == b'#' || c == b';' {
+                self.skip_line();
+                continue;
+            }
+            let line_start = self.line_start(self.pos);
+            let start = if self.src[line_start..self.pos].iter().all(|c| c.is_ascii_whitespace()) { line_start } else { self.pos };
+            if c == b'[' {
+                self.next_char();
+                let name = self.parse_section()?;
+                // 同じ行に続きがなければ、行末までをヘッダとする
+                while let Some(b' ') | Some(b'\t') | Some(b'\r') = self.peek() {
+                    self.next_char();
+                }
+                if let Some(b'\n') | Some(b'#') | Some(b';') = self.peek() {
+                    self.skip_line();
+                }
+                let end = self.pos;
+                section = Some(name.clone());
+                events.push(Event::Section { name, start, end });
+                continue;
+            }
+            if !c.is_ascii_alphabetic() {
+                return Err(self.error());
+            }
+            let mut name = String::new();
+            while let Some(c) = self.peek().filter(|&c| is_key_char(c)) {
+                name.push(c.to_ascii_lowercase() as char);
+                self.next_char();
+            }
+            while let Some(b' ') | Some(b'\t') = self.peek() {
+                self.next_char();
+            }
+            let value = match self.next_char() {
+                Some(b'\n') | None => None,
+                Some(b'=') => Some(self.parse_value()?),
+                _ => return Err(self.error()),
+            };
+            let section = section.as_ref().ok_or_else(|| self.error())?;
+            events.push(Event::Entry { key: format!("{}.{}", section, name), value, start, end: self.pos });
+        }
+        Ok(events)
+    }
+}
+
+fn parse_events(content: &str, path: Option<&Path>) -> io::Result<Vec<Event>> {
+    Parser { src: content.as_bytes(), pos: 0, line: 1, path }.parse()
+}
+
+// キーを section、subsection、変数名に分ける (大文字小文字はそのまま)
+struct KeyParts<'a> {
+    section: &'a str,
+    subsection: Option<&'a str>,
+    name: &'a str,
+}
+
+impl KeyParts<'_> {
+    fn parse(key: &str) -> io::Result<KeyParts<'_>> {
+        let (section, rest) = key.split_once('.').ok_or_else(|| invalid_data(format!("key does not contain a section: {}", key)))?;
+        let (subsection, name) = match rest.rsplit_once('.') {
+            Some((subsection, name)) => (Some(subsection), name),
+            None => (None, rest),
+        };
+        if name.is_empty() {
+            return Err(invalid_data(format!("key does not contain variable name: {}", key)));
+        }
+        let valid_section = !section.is_empty() && section.bytes().all(is_key_char);
+        let valid_name = name.bytes().all(is_key_char) && name.as_bytes()[0].is_ascii_alphabetic();
+        if !valid_section || !valid_name || subsection.is_some_and(|subsection| subsection.contains('\n')) {
+            return Err(invalid_data(format!("invalid key: {}", key)));
+        }
+        Ok(KeyParts { section, subsection, name })
+    }
+
+    fn section_name(&self) -> String {
+        match self.subsection {
+            Some(subsection) => format!("{}.{}", self.section.to_ascii_lowercase(), subsection),
+            None => self.section.to_ascii_lowercase(),
+        }
+    }
+
+    fn normalized(&self) -> String {
+        format!("{}.{}", self.section_name(), self.name.to_ascii_lowercase())
+    }
+}
+
+pub fn normalize_key(key: &str) -> io::Result<String> {
+    Ok(KeyParts::parse(key)?.normalized())
+}
+
+// "true"/"yes"/"on"、"false"/"no"/"off"/""、または整数を真偽値として読む
+// "key" だけの行 (値が None) は true
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let value = match value {
+        Some(value) => value,
+        None => return Some(true),
+    };
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        _ => parse_int(value).map(|n| n != 0),
+    }
+}
+
+// 末尾の "k"・"m"・"g" は 1024 の累乗倍を表す
+pub fn parse_int(value: &str) -> Option<i64> {
+    let (digits, factor) = match value.as_bytes().last()?.to_ascii_lowercase() {
+        b'k' => (&value[..value.len() - 1], 1 << 10),
+        b'm' => (&value[..value.len() - 1], 1 << 20),
+        b'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+// 先頭の "~/" をホームディレクトリに置き換える
+pub fn expand_path(value: &str) -> io::Result<PathBuf> {
+    let rest = match value.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return Ok(PathBuf::from(value)),
+    };
+    if !(rest.is_empty() || rest.starts_with('/')) {
+        return Err(invalid_data(format!("failed to expand user dir in: '{}'", value)));
+    }
+    let home = env::var_os("HOME").ok_or_else(|| invalid_data(format!("failed to expand user dir in: '{}'", value)))?;
+    Ok(PathBuf::from(format!("{}{}", home.to_string_lossy(), rest)))
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+fn global_files() -> Vec<PathBuf> {
+    if let Some(path) = env_path("GIT_CONFIG_GLOBAL") {
+        return vec![path];
+    }
+    let home = match env_path("HOME") {
+        Some(home) => home,
+        None => return Vec::new(),
+    };
+    let xdg = env_path("XDG_CONFIG_HOME").unwrap_or_else(|| home.join(".config"));
+    vec![xdg.join("git").join("config"), home.join(".gitconfig")]
+}
+
+fn system_file() -> Option<PathBuf> {
+    if env::var("GIT_CONFIG_NOSYSTEM").ok().and_then(|value| parse_bool(Some(&value))) == Some(true) {
+        return None;
+    }
+    Some(env_path("GIT_CONFIG_SYSTEM").unwrap_or_else(|| PathBuf::from("/etc/gitconfig")))
+}
+
+fn worktree_config_enabled(repo: &Repository) -> io::Result<bool> {
+    let local = Config::load_file(&repo.common_dir().join("config"), Some(ConfigScope::Local), Some(repo), false)?;
+    Ok(local.get_bool("extensions.worktreeConfig")?.unwrap_or(false))
+}
+
+// 書き込み先のファイル
+pub fn scope_path(repo: Option<&Repository>, scope: ConfigScope) -> io::Result<PathBuf> {
+    let no_repo = || io::Error::new(io::ErrorKind::NotFound, "not in a git directory");
+    match scope {
+        ConfigScope::System => system_file().ok_or_else(|| invalid_data("system config is disabled by GIT_CONFIG_NOSYSTEM")),
+        ConfigScope::Global => {
+            let files = global_files();
+            // ~/.gitconfig がなく XDG の設定ファイルだけがあればそちらに書く
+            match files.as_slice() {
+                [xdg, home] if !home.exists() && xdg.exists() => Ok(xdg.clone()),
+                [.., path] => Ok(path.clone()),
+                [] => Err(invalid_data("$HOME not set")),
+            }
+        },
+        ConfigScope::Local => Ok(repo.ok_or_else(no_repo)?.common_dir().join("config")),
+        ConfigScope::Worktree => {
+            let repo = repo.ok_or_else(no_repo)?;
+            match worktree_config_enabled(repo)? {
+                true => Ok(repo.git_dir().join("config.worktree")),
+                false => Ok(repo.common_dir().join("config")),
+            }
+        },
+    }
+}
+
+fn scope_files(repo: Option<&Repository>, scope: ConfigScope) -> io::Result<Vec<PathBuf>> {
+    Ok(match (scope, repo) {
+        (ConfigScope::System, _) => system_file().into_iter().collect(),
+        (ConfigScope::Global, _) => global_files(),
+        (ConfigScope::Local, Some(repo)) => vec![repo.common_dir().join("config")],
+        (ConfigScope::Worktree, Some(repo)) if worktree_config_enabled(repo)? => vec![repo.git_dir().join("config.worktree")],
+        _ => Vec::new(),
+    })
+}
+
+// "includeIf.<condition>.path" の条件を評価する
+fn include_condition(condition: &str, file: &Path, repo: Option<&Repository>) -> io::Result<bool> {
+    let repo = match repo {
+        Some(repo) => repo,
+        None => return Ok(false),
+    };
+    if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let branch = match read_ref(repo, "HEAD")? {
+            Some(RefTarget::Symbolic(target)) => match target.strip_prefix("refs/heads/") {
+                Some(branch) => branch.to_string(),
+                None => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+        let pattern = if pattern.ends_with('/') { format!("{}**", pattern) } else { pattern.to_string() };
+        return Ok(wildmatch(&pattern, &branch, true));
+    }
+
+    let (pattern, icase) = match condition.strip_prefix("gitdir:") {
+        Some(pattern) => (pattern, false),
+        None => match condition.strip_prefix("gitdir/i:") {
+            Some(pattern) => (pattern, true),
+            None => return Ok(false),
+        },
+    };
+    let mut pattern = expand_path(pattern)?.to_string_lossy().into_owned();
+    if let Some(rest) = pattern.strip_prefix("./") {
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        pattern = format!("{}/{}", dir.to_string_lossy(), rest);
+    } else if !pattern.starts_with('/') {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    // git ディレクトリは書かれたままのパスと実際のパスの両方で比べる
+    let mut candidates = vec![repo.git_dir().to_string_lossy().into_owned()];
+    if let Ok(real) = fs::canonicalize(repo.git_dir()) {
+        candidates.push(real.to_string_lossy().into_owned());
+    }
+    Ok(candidates.iter().any(|git_dir| match icase {
+        true => wildmatch(&pattern.to_lowercase(), &git_dir.to_lowercase(), true),
+        false => wildmatch(&pattern, git_dir, true),
+    }))
+}
+
+struct Loader<'a> {
+    repo: Option<&'a Repository>,
+    includes: bool,
+    entries: Vec<ConfigEntry>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self, path: &Path, scope: Option<ConfigScope>, depth: usize) -> io::Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for event in parse_events(&content, Some(path))? {
+            let (key, value) = match event {
+                Event::Entry { key, value, .. } => (key, value),
+                Event::Section { .. } => continue,
+            };
+            let include = self.includes && match key.strip_prefix("includeif.").and_then(|key| key.strip_suffix(".path")) {
+                Some(condition) => include_condition(condition, path, self.repo)?,
+                None => key == "include.path",
+            };
+            self.entries.push(ConfigEntry { key: key.clone(), value: value.clone(), origin: Some(path.to_path_buf()), scope });
+            if !include {
+                continue;
+            }
+            let value = value.ok_or_else(|| invalid_data(format!("missing value for '{}'", key)))?;
+            // 相対パスは読み込み元のファイルからの位置とする
+            let mut target = expand_path(&value)?;
+            if target.is_relative() {
+                target = path.parent().unwrap_or_else(|| Path::new("")).join(target);
+            }
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Err(invalid_data(format!(
+                    "exceeded maximum include depth ({}) while including\n\t{}\nfrom\n\t{}",
+                    MAX_INCLUDE_DEPTH, target.display(), path.display(),
+                )));
+            }
+            self.load(&target, scope, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn parse(content: &str) -> io::Result<Self> {
+        let entries = parse_events(content, None)?.into_iter().filter_map(|event| match event {
+            Event::Entry { key, value, .. } => Some(ConfigEntry { key, value, origin: None, scope: None }),
+            Event::Section { .. } => None,
+        }).collect();
+        Ok(Self { entries })
+    }
+
+    // 1 つのファイルを読む。includes が true なら include.path と includeIf も読む
+    pub fn load_file(path: &Path, scope: Option<ConfigScope>, repo: Option<&Repository>, includes: bool) -> io::Result<Self> {
+        let mut loader = Loader { repo, includes, entries: Vec::new() };
+        loader.load(path, scope, 0)?;
+        Ok(Self { entries: loader.entries })
+    }
+
+    // scope のファイルを読む。includes の意味は load_file と同じ
+    pub fn load_scope(repo: Option<&Repository>, scope: ConfigScope, includes: bool) -> io::Result<Self> {
+        let mut loader = Loader { repo, includes, entries: Vec::new() };
+        for path in scope_files(repo, scope)? {
+            loader.load(&path, Some(scope), 0)?;
+        }
+        Ok(Self { entries: loader.entries })
+    }
+
+    // system、global、local、worktree の順に読み、後のものほど優先する
+    pub fn load(repo: Option<&Repository>) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for scope in [ConfigScope::System, ConfigScope::Global, ConfigScope::Local, ConfigScope::Worktree] {
+            entries.extend(Self::load_scope(repo, scope, true)?.entries);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    pub fn get_entries<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a ConfigEntry> + 'a {
+        let key = normalize_key(key).ok();
+        self.entries.iter().filter(move |entry| Some(&entry.key) == key.as_ref())
+    }
+
+    // 複数の値がある場合は最後のものを返す。値のない "key" だけの行は "true" とみなす
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_entries(key).last().map(|entry| entry.value.as_deref().unwrap_or("true"))
+    }
+
+    pub fn get_bool(&self, key: &str) -> io::Result<Option<bool>> {
+        let entry = match self.get_entries(key).last() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match parse_bool(entry.value.as_deref()) {
+            Some(value) => Ok(Some(value)),
+            None => Err(invalid_data(format!("bad boolean config value '{}' for '{}'", entry.value.as_deref().unwrap_or(""), key))),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> io::Result<Option<i64>> {
+        let entry = match self.get_entries(key).last() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let value = entry.value.as_deref().ok_or_else(|| invalid_data(format!("missing value for '{}'", key)))?;
+        match parse_int(value) {
+            Some(value) => Ok(Some(value)),
+            None => Err(invalid_data(format!("bad numeric config value '{}' for '{}': invalid unit", value, key))),
+        }
+    }
+}
+
+// --get などに渡す値の正規表現。"!" で始まれば一致しないものを選ぶ
+pub struct ValuePattern {
+    regex: Regex,
+    negate: bool,
+}
+
+impl ValuePattern {
+    pub fn parse(pattern: &str) -> io::Result<Self> {
+        match pattern.strip_prefix('!') {
+            Some(pattern) => Ok(Self { regex: Regex::new(pattern)?, negate: true }),
+            None => Ok(Self { regex: Regex::new(pattern)?, negate: false }),
+        }
+    }
+
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        value.is_some_and(|value| self.regex.is_match(value)) != self.negate
+    }
+}
+
+fn quote_value(value: &str) -> String {
+    let quote = value.starts_with(' ') || value.ends_with(' ') || value.contains(';') || value.contains('#');
+    let mut out = String::new();
+    if quote {
+        out.push('"');
+    }
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c => out.push(c),
+        }
+    }
+    if quote {
+        out.push('"');
+    }
+    out
+}
+
+fn section_header(key: &KeyParts) -> String {
+    match key.subsection {
+        Some(subsection) => format!("[{} \"{}\"]\n", key.section, subsection.replace('\\', "\\\\").replace('"', "\\\"")),
+        None => format!("[{}]\n", key.section),
+    }
+}
+
+// 書式やコメントを残したまま 1 つの設定ファイルを書き換える
+// open でロックを取り、commit で書き出す
+pub struct ConfigFile {
+    lock: LockFile,
+    content: String,
+}
+
+impl ConfigFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let lock = LockFile::acquire(path)?;
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { lock, content })
+    }
+
+    fn events(&self) -> io::Result<Vec<Event>> {
+        parse_events(&self.content, Some(self.lock.path()))
+    }
+
+    // key に一致し、値が pattern に合うエントリの位置と、それが属するセクションの番号
+    fn find(&self, events: &[Event], key: &str, pattern: Option<&ValuePattern>) -> io::Result<Vec<(usize, usize, Option<usize>)>> {
+        let key = normalize_key(key)?;
+        let mut section = None;
+        let mut found = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            match event {
+                Event::Section { .. } => section = Some(i),
+                Event::Entry { key: entry_key, value, start, end } => {
+                    if *entry_key == key && pattern.is_none_or(|pattern| pattern.matches(value.as_deref())) {
+                        found.push((*start, *end, section));
+                    }
+                },
+            }
+        }
+        Ok(found)
+    }
+
+    pub fn count(&self, key: &str, pattern: Option<&ValuePattern>) -> io::Result<usize> {
+        Ok(self.find(&self.events()?, key, pattern)?.len())
+    }
+
+    // deletions の範囲を除き、insertion の位置に文字列を入れた内容に置き換える
+    fn rewrite(&mut self, mut deletions: Vec<(usize, usize)>, mut insertion: Option<(usize, String)>) {
+        deletions.sort();
+        let mut out = String::new();
+        let mut pos = 0;
+        for (start, end) in deletions {
+            if insertion.as_ref().is_some_and(|(at, _)| *at <= start) {
+                let (at, text) = insertion.take().unwrap();
+                out.push_str(&self.content[pos..at]);
+                out.push_str(&text);
+                pos = at;
+            }
+            if start > pos {
+                out.push_str(&self.content[pos..start]);
+            }
+            pos = pos.max(end);
+        }
+        if let Some((at, text)) = insertion {
+            out.push_str(&self.content[pos..at]);
+            out.push_str(&text);
+            pos = at;
+        }
+        out.push_str(&self.content[pos..]);
+        self.content = out;
+    }
+
+    // section の最後のエントリの直後 (なければヘッダの直後) に新しい行を入れる。section がなければ末尾に作る
+    pub fn add(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let parts = KeyParts::parse(key)?;
+        let section_name = parts.section_name();
+        let line = format!("\t{} = {}\n", parts.name, quote_value(value));
+
+        let mut position = None;
+        let mut in_section = false;
+        for event in self.events()? {
+            match event {
+                Event::Section { name, end, .. } => {
+                    in_section = name == section_name;
+                    if in_section {
+                        position = Some(end);
+                    }
+                },
+                Event::Entry { end, .. } if in_section => position = Some(end),
+                Event::Entry { .. } => {},
+            }
+        }
+        let needs_newline = |pos: usize| pos > 0 && pos == self.content.len() && !self.content.ends_with('\n');
+        let text = match position {
+            Some(pos) if needs_newline(pos) => (pos, format!("\n{}", line)),
+            Some(pos) => (pos, line),
+            None if needs_newline(self.content.len()) => (self.content.len(), format!("\n{}{}", section_header(&parts), line)),
+            None => (self.content.len(), format!("{}{}", section_header(&parts), line)),
+        };
+        self.rewrite(Vec::new(), Some(text));
+        Ok(())
+    }
+
+    // 一致するエントリをすべて除き、最後のものがあった位置に新しい値を置く。一致するものがなければ追加する
+    pub fn set(&mut self, key: &str, value: &str, pattern: Option<&ValuePattern>) -> io::Result<()> {
+        let parts = KeyParts::parse(key)?;
+        let found = self.find(&self.events()?, key, pattern)?;
+        let last = match found.last() {
+            Some(&(start, _, _)) => start,
+            None => return self.add(key, value),
+        };
+        let line = format!("\t{} = {}\n", parts.name, quote_value(value));
+        self.rewrite(found.iter().map(|&(start, end, _)| (start, end)).collect(), Some((last, line)));
+        Ok(())
+    }
+
+    // 一致するエントリをすべて除く。空になったセクションはヘッダも除く
+    pub fn unset(&mut self, key: &str, pattern: Option<&ValuePattern>) -> io::Result<usize> {
+        let events = self.events()?;
+        let found = self.find(&events, key, pattern)?;
+        let mut deletions: Vec<(usize, usize)> = found.iter().map(|&(start, end, _)| (start, end)).collect();
+
+        let mut sections: Vec<usize> = found.iter().filter_map(|&(_, _, section)| section).collect();
+        sections.dedup();
+        for i in sections {
+            let (start, end) = match events[i] {
+                Event::Section { start, end, .. } => (start, end),
+                Event::Entry { .. } => continue,
+            };
+            let body_end = events[i + 1..].iter().find_map(|event| match event {
+                Event::Section { start, .. } => Some(*start),
+                Event::Entry { .. } => None,
+            }).unwrap_or(self.content.len());
+            let remaining = (end..body_end).filter(|pos| !deletions.iter().any(|&(start, end)| start <= *pos && *pos < end));
+            if remaining.map(|pos| self.content.as_bytes()[pos]).all(|c| c.is_ascii_whitespace()) {
+                deletions.push((start, end));
+            }
+        }
+        self.rewrite(deletions, None);
+        Ok(found.len())
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        self.lock.write_all(self.content.as_bytes())?;
+        self.lock.commit()
+    }
+}
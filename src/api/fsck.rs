@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use sha1::{Digest, Sha1};
+
+use super::common::error::invalid_data;
+use super::objects::base::ObjectType;
+use super::objects::io::{HASH_SIZE, Hash, ObjectReader, list_loose_objects};
+use super::objects::tree::{MODE_EXECUTABLE, MODE_FILE, MODE_GITLINK, MODE_SYMLINK, MODE_TREE, Mode, compare_entry_names};
+use super::reachable::{index_blobs, named_root_objects};
+use super::repository::Repository;
+
+// Display は git fsck と同じ 1 行の表示。スクリプトからは種類ごとに形式の決まった machine_line を使う
+pub enum FsckProblem {
+    // pack ファイル自体が壊れている
+    BadPack { path: PathBuf, message: String },
+    // 展開できない、ヘッダが壊れている、内容のハッシュが名前と一致しないなど
+    Corrupt { hash: Hash, message: String },
+    // 内容の形式が正しくない。id は git fsck のメッセージ ID
+    Invalid { hash: Hash, obj_type: ObjectType, id: &'static str, message: String },
+    // 到達可能なオブジェクトが存在しないオブジェクトを参照している
+    BrokenLink { from: Hash, from_type: ObjectType, to: Hash, to_type: ObjectType },
+    Missing { hash: Hash, obj_type: ObjectType },
+    // ref・reflog・index が存在しないオブジェクトを指している
+    BadRef { name: String, hash: Hash },
+    // dangling はほかのどのオブジェクトからも参照されていないもの
+    Unreachable { hash: Hash, obj_type: ObjectType, dangling: bool },
+}
+
+impl FsckProblem {
+    pub fn is_error(&self) -> bool {
+        !matches!(self, FsckProblem::Unreachable { .. })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FsckProblem::BadPack { .. } => "badpack",
+            FsckProblem::Corrupt { .. } => "corrupt",
+            FsckProblem::Invalid { .. } => "invalid",
+            FsckProblem::BrokenLink { .. } => "broken-link",
+            FsckProblem::Missing { .. } => "missing",
+            FsckProblem::BadRef { .. } => "badref",
+            FsckProblem::Unreachable { dangling: true, .. } => "dangling",
+            FsckProblem::Unreachable { dangling: false, .. } => "unreachable",
+        }
+    }
+
+    // "<種類>\t<オブジェクトの型>\t<ID>\t<詳細>" の形式。型が分からないものは "-"、ID は pack ならそのパス
+    pub fn machine_line(&self) -> String {
+        let (obj_type, id, detail) = match self {
+            FsckProblem::BadPack { path, message } => ("-".to_string(), path.display().to_string(), message.clone()),
+            FsckProblem::Corrupt { hash, message } => ("-".to_string(), hash.to_string(), message.clone()),
+            FsckProblem::Invalid { hash, obj_type, id, message } => (obj_type.to_string(), hash.to_string(), format!("{}: {}", id, message)),
+            FsckProblem::BrokenLink { from, from_type, to, to_type } => (from_type.to_string(), from.to_string(), format!("{} {}", to_type, to)),
+            FsckProblem::Missing { hash, obj_type } => (obj_type.to_string(), hash.to_string(), String::new()),
+            FsckProblem::BadRef { name, hash } => ("-".to_string(), hash.to_string(), name.clone()),
+            FsckProblem::Unreachable { hash, obj_type, .. } => (obj_type.to_string(), hash.to_string(), String::new()),
+        };
+        // 詳細に改行やタブが含まれても 1 行に収める
+        let detail = detail.replace(['\t', '\n'], " ");
+        format!("{}\t{}\t{}\t{}", self.kind(), obj_type, id, detail)
+    }
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckProblem::BadPack { path, message } => write!(f, "error: {}: {}", path.display(), message),
+            FsckProblem::Corrupt { hash, message } => write!(f, "error: {}: object corrupt: {}", hash, message),
+            FsckProblem::Invalid { hash, obj_type, id, message } => {
+                write!(f, "error in {} {}: {}: {}", obj_type, hash, id, message)
+            },
+            FsckProblem::BrokenLink { from, from_type, to, to_type } => {
+                write!(f, "broken link from {} {} to {} {}", from_type, from, to_type, to)
+            },
+            FsckProblem::Missing { hash, obj_type } => write!(f, "missing {} {}", obj_type, hash),
+            FsckProblem::BadRef { name, hash } => write!(f, "error: {}: invalid sha1 pointer {}", name, hash),
+            FsckProblem::Unreachable { hash, obj_type, dangling } => {
+                write!(f, "{} {} {}", if *dangling { "dangling" } else { "unreachable" }, obj_type, hash)
+            },
+        }
+    }
+}
+
+type Issue = (&'static str, String);
+// 参照先のハッシュと、参照元が想定している型
+type Links = Vec<(Hash, ObjectType)>;
+
+fn parse_hex_hash(value: &[u8]) -> Option<Hash> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+// "Name <email> 1234567890 +0900" の形式を確認する (git の fsck_ident と同じ順序で検査する)
+fn check_ident(ident: &[u8]) -> Option<Issue> {
+    let issue = |id: &'static str, message: &str| Some((id, message.to_string()));
+    if ident.first() == Some(&b'<') {
+        return issue("missingNameBeforeEmail", "invalid author/committer line - missing space before email");
+    }
+    let lt = match ident.iter().position(|&b| b == b'<') {
+        Some(lt) => lt,
+        None => return issue("missingEmail", "invalid author/committer line - missing email"),
+    };
+    if ident[lt - 1] != b' ' {
+        return issue("missingSpaceBeforeEmail", "invalid author/committer line - missing space before email");
+    }
+    let gt = match ident[lt + 1..].iter().position(|&b| b == b'>' || b == b'<') {
+        Some(pos) if ident[lt + 1 + pos] == b'>' => lt + 1 + pos,
+        _ => return issue("badEmail", "invalid author/committer line - bad email"),
+    };
+    let rest = &ident[gt + 1..];
+    let rest = match rest.strip_prefix(b" ") {
+        Some(rest) => rest,
+        None => return issue("missingSpaceBeforeDate", "invalid author/committer line - missing space before date"),
+    };
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return issue("badDate", "invalid author/committer line - bad date");
+    }
+    if digits > 1 && rest[0] == b'0' {
+        return issue("zeroPaddedDate", "invalid author/committer line - zero-padded date");
+    }
+    let tz = &rest[digits..];
+    let valid_tz = tz.len() == 6 && tz[0] == b' ' && (tz[1] == b'+' || tz[1] == b'-') && tz[2..].iter().all(|b| b.is_ascii_digit());
+    if !valid_tz {
+        return issue("badTimezone", "invalid author/committer line - bad time zone");
+    }
+    None
+}
+
+// ヘッダ部分の行 (空行まで)
+fn header_lines(body: &[u8]) -> impl Iterator<Item = &[u8]> {
+    body.split(|&b| b == b'\n').take_while(|line| !line.is_empty())
+}
+
+fn check_commit(body: &[u8]) -> (Links, Option<Issue>) {
+    let mut links = Vec::new();
+    let mut lines = header_lines(body).peekable();
+
+    let tree = match lines.next().and_then(|line| line.strip_prefix(b"tree ")) {
+        Some(tree) => tree,
+        None => return (links, Some(("missingTree", "invalid format - expected 'tree' line".to_string()))),
+    };
+    match parse_hex_hash(tree) {
+        Some(hash) => links.push((hash, ObjectType::Tree)),
+        None => return (links, Some(("badTreeSha1", "invalid 'tree' line format - bad sha1".to_string()))),
+    }
+    while let Some(parent) = lines.peek().and_then(|line| line.strip_prefix(b"parent ")) {
+        match parse_hex_hash(parent) {
+            Some(hash) => links.push((hash, ObjectType::Commit)),
+            None => return (links, Some(("badParentSha1", "invalid 'parent' line format - bad sha1".to_string()))),
+        }
+        lines.next();
+    }
+    for (key, id) in [(&b"author "[..], "missingAuthor"), (&b"committer "[..], "missingCommitter")].iter() {
+        let ident = match lines.next().and_then(|line| line.strip_prefix(*key)) {
+            Some(ident) => ident,
+            None => {
+                let name = String::from_utf8_lossy(&key[..key.len() - 1]).into_owned();
+                return (links, Some((id, format!("invalid format - expected '{}' line", name))));
+            },
+        };
+        if let Some(issue) = check_ident(ident) {
+            return (links, Some(issue));
+        }
+    }
+    (links, None)
+}
+
+fn check_tag(body: &[u8]) -> (Links, Option<Issue>) {
+    let mut links = Vec::new();
+    let mut lines = header_lines(body);
+
+    let object = match lines.next().and_then(|line| line.strip_prefix(b"object ")) {
+        Some(object) => object,
+        None => return (links, Some(("missingObject", "invalid format - expected 'object' line".to_string()))),
+    };
+    let object = match parse_hex_hash(object) {
+        Some(hash) => hash,
+        None => return (links, Some(("badObjectSha1", "invalid 'object' line format - bad sha1".to_string()))),
+    };
+    let obj_type = match lines.next().and_then(|line| line.strip_prefix(b"type ")) {
+        Some(obj_type) => obj_type,
+        None => return (links, Some(("missingTypeEntry", "invalid format - expected 'type' line".to_string()))),
+    };
+    match std::str::from_utf8(obj_type).ok().and_then(|t| t.parse().ok()) {
+        Some(obj_type) => links.push((object, obj_type)),
+        None => return (links, Some(("badType", "invalid 'type' value".to_string()))),
+    }
+    if !lines.next().is_some_and(|line| line.starts_with(b"tag ")) {
+        return (links, Some(("missingTagEntry", "invalid format - expected 'tag' line".to_string())));
+    }
+    // tagger は古いタグにはないので任意
+    if let Some(tagger) = lines.next().and_then(|line| line.strip_prefix(b"tagger ")) {
+        if let Some(issue) = check_ident(tagger) {
+            return (links, Some(issue));
+        }
+    }
+    (links, None)
+}
+
+fn check_tree(body: &[u8]) -> (Links, Option<Issue>) {
+    let mut links = Vec::new();
+    let mut issue: Option<Issue> = None;
+    let mut prev: Option<(&[u8], bool)> = None;
+    let mut rest = body;
+
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ');
+        let nul = rest.iter().position(|&b| b == 0);
+        let (space, nul) = match (space, nul) {
+            (Some(space), Some(nul)) if space < nul && rest.len() >= nul + 1 + HASH_SIZE => (space, nul),
+            _ => return (links, Some(("badTree", "cannot be parsed as a tree".to_string()))),
+        };
+        let mode = match Mode::parse(&rest[..space]) {
+            Ok(mode) => mode,
+            Err(_) => return (links, Some(("badTree", "cannot be parsed as a tree".to_string()))),
+        };
+        let name = &rest[space + 1..nul];
+        let hash = Hash::from_bytes(&rest[nul + 1..nul + 1 + HASH_SIZE]).unwrap();
+        rest = &rest[nul + 1 + HASH_SIZE..];
+
+        // 最初に見つかった問題だけを報告する
+        let found = if ![MODE_FILE, MODE_EXECUTABLE, MODE_SYMLINK, MODE_TREE, MODE_GITLINK].contains(&mode) {
+            Some(("badFilemode", "contains bad file modes"))
+        } else if name.is_empty() {
+            Some(("emptyName", "contains empty pathname"))
+        } else if name.contains(&b'/') {
+            Some(("fullPathname", "contains full pathnames"))
+        } else if name == b"." {
+            Some(("hasDot", "contains '.'"))
+        } else if name == b".." {
+            Some(("hasDotdot", "contains '..'"))
+        } else if name.eq_ignore_ascii_case(b".git") {
+            Some(("hasDotgit", "contains '.git'"))
+        } else {
+            prev.and_then(|(prev_name, prev_is_tree)| {
+                match compare_entry_names(prev_name, prev_is_tree, name, mode.is_tree()) {
+                    std::cmp::Ordering::Less if prev_name != name => None,
+                    std::cmp::Ordering::Greater => Some(("treeNotSorted", "not properly sorted")),
+                    _ => Some(("duplicateEntries", "contains duplicate file entries")),
+                }
+            })
+        };
+        if issue.is_none() {
+            issue = found.map(|(id, message)| (id, message.to_string()));
+        }
+        prev = Some((name, mode.is_tree()));
+
+        // サブモジュールのコミットはこのリポジトリにはない
+        if mode != MODE_GITLINK {
+            links.push((hash, if mode.is_tree() { ObjectType::Tree } else { ObjectType::Blob }));
+        }
+    }
+    (links, issue)
+}
+
+struct Checker<'a> {
+    repo: &'a Repository,
+    objects: HashMap<Hash, ObjectType>,
+    links: HashMap<Hash, Links>,
+    problems: Vec<FsckProblem>,
+}
+
+impl Checker<'_> {
+    // 内容をハッシュし直して名前と比べてから、型ごとの形式を検査する
+    fn check_object(&mut self, hash: Hash, obj_type: ObjectType, size: u64, mut body: impl Read) -> io::Result<()> {
+        let mut hasher = Sha1::new();
+        write!(hasher, "{} {}\0", obj_type, size)?;
+        let mut content = Vec::new();
+        // blob は形式の検査が不要なので、大きなものでもメモリに載せずにハッシュする
+        if obj_type == ObjectType::Blob {
+            io::copy(&mut body, &mut hasher)?;
+        } else {
+            body.read_to_end(&mut content)?;
+            hasher.update(&content);
+        }
+        let actual = Hash::from_bytes(hasher.finalize().as_slice())?;
+        if actual != hash {
+            self.problems.push(FsckProblem::Corrupt { hash, message: format!("hash mismatch (content hashes to {})", actual) });
+            return Ok(());
+        }
+
+        let (links, issue) = match obj_type {
+            ObjectType::Blob => (Vec::new(), None),
+            ObjectType::Tree => check_tree(&content),
+            ObjectType::Commit => check_commit(&content),
+            ObjectType::Tag => check_tag(&content),
+        };
+        if let Some((id, message)) = issue {
+            self.problems.push(FsckProblem::Invalid { hash, obj_type, id, message });
+        }
+        self.objects.insert(hash, obj_type);
+        self.links.entry(hash).or_default().extend(links);
+        Ok(())
+    }
+
+    fn check_loose_objects(&mut self) -> io::Result<()> {
+        for hash in list_loose_objects(self.repo)? {
+            // ObjectReader は loose オブジェクトを先に探す
+            let result = ObjectReader::new(self.repo, &hash.to_string()).and_then(|reader| {
+                let (obj_type, size) = (reader.object_type(), reader.size());
+                self.check_object(hash, obj_type, size, reader)
+            });
+            if let Err(e) = result {
+                self.problems.push(FsckProblem::Corrupt { hash, message: e.to_string() });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_packs(&mut self) -> io::Result<()> {
+        for pack in self.repo.packs()?.iter() {
+            if let Err(e) = pack.verify_checksum() {
+                self.problems.push(FsckProblem::BadPack { path: pack.pack_path().to_path_buf(), message: e.to_string() });
+            }
+            for entry in pack.index().entries() {
+                let (hash, _) = entry?;
+                // loose と pack や複数の pack に同じオブジェクトがあれば、最初に正しく読めたものだけを調べる
+                if self.objects.contains_key(&hash) {
+                    continue;
+                }
+                let result = pack.read_object(self.repo, &hash).and_then(|object| {
+                    let (obj_type, body) = object.ok_or_else(|| invalid_data("object listed in the pack index is not in the pack"))?;
+                    self.check_object(hash, obj_type, body.len() as u64, &body[..])
+                });
+                if let Err(e) = result {
+                    self.problems.push(FsckProblem::Corrupt { hash, message: e.to_string() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 到達可能なオブジェクトからの参照先が存在するかを調べる
+    // 到達不可能なオブジェクトの参照先がなくても、消えてよいものなので問題にしない
+    fn check_links(&mut self, reachable: &HashSet<Hash>) {
+        let mut missing = BTreeMap::new();
+        let mut sources: Vec<&Hash> = self.links.keys().filter(|hash| reachable.contains(*hash)).collect();
+        sources.sort();
+        for from in sources {
+            for &(to, to_type) in self.links[from].iter() {
+                if !self.objects.contains_key(&to) {
+                    let from_type = self.objects[from];
+                    self.problems.push(FsckProblem::BrokenLink { from: *from, from_type, to, to_type });
+                    missing.insert(to, to_type);
+                }
+            }
+        }
+        for (hash, obj_type) in missing {
+            self.problems.push(FsckProblem::Missing { hash, obj_type });
+        }
+    }
+
+    // HEAD・ref・reflog・index から到達できるオブジェクトを返す
+    fn check_roots(&mut self) -> io::Result<HashSet<Hash>> {
+        let mut roots = named_root_objects(self.repo)?;
+        roots.extend(index_blobs(self.repo)?.into_iter().map(|(path, hash)| (format!("index entry {}", path), hash)));
+
+        let mut reachable = HashSet::new();
+        let mut stack = Vec::new();
+        for (name, hash) in roots {
+            if self.objects.contains_key(&hash) {
+                stack.push(hash);
+            } else {
+                self.problems.push(FsckProblem::BadRef { name, hash });
+            }
+        }
+        while let Some(hash) = stack.pop() {
+            if !reachable.insert(hash) {
+                continue;
+            }
+            if let Some(links) = self.links.get(&hash) {
+                stack.extend(links.iter().map(|(to, _)| *to).filter(|to| self.objects.contains_key(to)));
+            }
+        }
+        Ok(reachable)
+    }
+
+    fn check_unreachable(&mut self, reachable: &HashSet<Hash>) {
+        let referenced: HashSet<Hash> = self.links.values().flatten().map(|(to, _)| *to).collect();
+        let mut unreachable: Vec<(&Hash, &ObjectType)> = self.objects.iter()
+            .filter(|(hash, _)| !reachable.contains(*hash))
+            .collect();
+        unreachable.sort_by_key(|(hash, _)| **hash);
+        for (hash, obj_type) in unreachable {
+            let dangling = !referenced.contains(hash);
+            self.problems.push(FsckProblem::Unreachable { hash: *hash, obj_type: *obj_type, dangling });
+        }
+    }
+}
+
+// すべての loose・pack 内のオブジェクトを検証し、参照のつながりを調べる
+pub fn fsck(repo: &Repository) -> io::Result<Vec<FsckProblem>> {
+    let mut checker = Checker { repo, objects: HashMap::new(), links: HashMap::new(), problems: Vec::new() };
+    checker.check_loose_objects()?;
+    checker.check_packs()?;
+    let reachable = checker.check_roots()?;
+    checker.check_links(&reachable);
+    checker.check_unreachable(&reachable);
+    Ok(checker.problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_line_format() {
+        let hash: Hash = "ce013625030ba8dba906f756967f9e9ca394464a".parse().unwrap();
+        let tree: Hash = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+        let cases = [
+            (FsckProblem::Corrupt { hash, message: "bad\tdata\n".to_string() }, "corrupt\t-\tce013625030ba8dba906f756967f9e9ca394464a\tbad data "),
+            (
+                FsckProblem::BrokenLink { from: tree, from_type: ObjectType::Tree, to: hash, to_type: ObjectType::Blob },
+                "broken-link\ttree\t4b825dc642cb6eb9a060e54bf8d69288fbee4904\tblob ce013625030ba8dba906f756967f9e9ca394464a",
+            ),
+            (FsckProblem::BadRef { name: "HEAD".to_string(), hash }, "badref\t-\tce013625030ba8dba906f756967f9e9ca394464a\tHEAD"),
+            (
+                FsckProblem::Unreachable { hash, obj_type: ObjectType::Blob, dangling: true },
+                "dangling\tblob\tce013625030ba8dba906f756967f9e9ca394464a\t",
+            ),
+        ];
+        for (problem, expected) in cases.iter() {
+            assert_eq!(problem.machine_line(), *expected);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+use std::fs::{self, remove_file};
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use super::objects::base::ObjectType;
+use super::objects::io::{Hash, ObjectReader, ObjectWriter, list_loose_objects, loose_object_path, remove_loose_object};
+use super::objects::pack_writer::PackOptions;
+use super::reachable::reachable_objects;
+use super::reflog::{expire_reflog, list_reflogs};
+use super::refs::pack_refs;
+use super::repack::{prune_packed, repack};
+use super::repository::Repository;
+
+// 書き込み途中で中断された一時ファイルの接頭辞
+const TEMP_PREFIXES: [&str; 3] = ["tmp_obj_", "tmp_pack_", "tmp_idx_"];
+
+pub struct GcOptions {
+    // これより古い reflog のエントリを消す
+    pub reflog_expire: i64,
+    // これより古い到達不可能な loose オブジェクトを消す (None なら prune しない)
+    pub prune_expire: Option<i64>,
+    pub pack_options: PackOptions,
+}
+
+fn mtime(path: &Path) -> io::Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64))
+}
+
+fn reachable_set(repo: &Repository) -> io::Result<HashSet<Hash>> {
+    Ok(reachable_objects(repo)?.into_iter().map(|object| object.hash).collect())
+}
+
+fn remove_stale_temp_files(dir: &Path, expire: i64) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let is_temp = path.file_name()
+            .is_some_and(|name| TEMP_PREFIXES.iter().any(|prefix| name.to_string_lossy().starts_with(prefix)));
+        if is_temp && mtime(&path)? <= expire {
+            remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// 到達不可能で、更新日時が expire 以前の loose オブジェクトを削除し、(ハッシュ, 型) を返す
+// (書き込み中の別プロセスのオブジェクトを消さないよう猶予期間を設ける。壊れていて型が読めないものも消す)
+pub fn prune(repo: &Repository, expire: i64, dry_run: bool) -> io::Result<Vec<(Hash, Option<ObjectType>)>> {
+    let reachable = reachable_set(repo)?;
+    let mut pruned = Vec::new();
+    for hash in list_loose_objects(repo)? {
+        if reachable.contains(&hash) || mtime(&loose_object_path(repo, &hash))? > expire {
+            continue;
+        }
+        let obj_type = ObjectReader::new(repo, &hash.to_string()).ok().map(|reader| reader.object_type());
+        if !dry_run {
+            remove_loose_object(repo, &hash)?;
+        }
+        pruned.push((hash, obj_type));
+    }
+
+    if !dry_run {
+        let objects_dir = repo.objects_dir();
+        remove_stale_temp_files(&objects_dir, expire)?;
+        remove_stale_temp_files(&objects_dir.join("pack"), expire)?;
+        prune_packed(repo)?;
+    }
+    Ok(pruned)
+}
+
+// repack -a -d で消えないよう、pack にしかない到達不可能なオブジェクトを loose にしておく (repack -A 相当)
+// どうせ prune される古い pack のものは取り出さない
+fn loosen_unreachable_objects(repo: &Repository, prune_expire: Option<i64>) -> io::Result<()> {
+    let reachable = reachable_set(repo)?;
+    for pack in repo.packs()?.iter() {
+        if prune_expire.is_some_and(|expire| mtime(pack.pack_path()).is_ok_and(|t| t <= expire)) {
+            continue;
+        }
+        for entry in pack.index().entries() {
+            let (hash, _) = entry?;
+            if reachable.contains(&hash) || loose_object_path(repo, &hash).is_file() {
+                continue;
+            }
+            let reader = ObjectReader::new(repo, &hash.to_string())?;
+            ObjectWriter::write_loose_stream(repo, reader.object_type(), reader.size(), reader)?;
+        }
+    }
+    Ok(())
+}
+
+// ref をまとめ、reflog を整理し、すべてを 1 つの pack にまとめてから不要なオブジェクトを消す
+pub fn gc(repo: &Repository, options: &GcOptions) -> io::Result<()> {
+    pack_refs(repo)?;
+    for refname in list_reflogs(repo)? {
+        expire_reflog(repo, &refname, options.reflog_expire)?;
+    }
+    loosen_unreachable_objects(repo, options.prune_expire)?;
+    repack(repo, true, true, &options.pack_options)?;
+    if let Some(expire) = options.prune_expire {
+        prune(repo, expire, false)?;
+    }
+    Ok(())
+}
+
+// git と同様に objects/17 の数から loose オブジェクトの総数を見積もる
+pub fn too_many_loose_objects(repo: &Repository, limit: usize) -> io::Result<bool> {
+    if limit == 0 {
+        return Ok(false);
+    }
+    let count = match fs::read_dir(repo.objects_dir().join("17")) {
+        Ok(entries) => entries.filter(|entry| {
+            entry.as_ref().is_ok_and(|entry| entry.file_name().len() == 38)
+        }).count(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    Ok(count > limit.div_ceil(256))
+}
+
+pub fn too_many_packs(repo: &Repository, limit: usize) -> io::Result<bool> {
+    if limit == 0 {
+        return Ok(false);
+    }
+    let packs = repo.packs()?;
+    // .keep が置かれた pack は repack の対象にならないので数えない
+    let count = packs.iter().filter(|pack| !pack.pack_path().with_extension("keep").exists()).count();
+    Ok(count > limit)
+}
@@ -0,0 +1,65 @@
+use std::env;
+use std::io;
+
+use super::common::datetime::{Timestamp, Timezone, parse_approxidate};
+use super::common::user::User;
+use super::config::Config;
+
+#[derive(Clone, Copy)]
+pub enum IdentRole {
+    Author,
+    Committer,
+}
+
+impl IdentRole {
+    fn env_prefix(&self) -> &str {
+        match self {
+            IdentRole::Author => "GIT_AUTHOR",
+            IdentRole::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            IdentRole::Author => "Author",
+            IdentRole::Committer => "Committer",
+        }
+    }
+}
+
+// GIT_AUTHOR_DATE などの日付 ("<epoch> <tz>" やISO 8601 など) をパースする
+pub fn parse_ident_date(s: &str) -> io::Result<Timestamp> {
+    let s = s.trim();
+    if let Ok(timestamp) = s.trim_start_matches('@').parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+    let epoch = parse_approxidate(s, Timestamp::now().epoch())?;
+    let timezone = Timezone::from_chrono_offset(*Timestamp::now().to_datetime().offset());
+    Ok(Timestamp::new(epoch, timezone))
+}
+
+// 環境変数 (GIT_AUTHOR_NAME など)、設定 (user.name / user.email) の順に名前と日時を決める
+pub fn get_ident(config: &Config, role: IdentRole) -> io::Result<(User, Timestamp)> {
+    let prefix = role.env_prefix();
+    let lookup = |name: &str, key: &str| {
+        env::var(format!("{}_{}", prefix, name)).ok()
+            .or_else(|| config.get(key).map(|value| value.to_string()))
+    };
+
+    let name = lookup("NAME", "user.name");
+    let email = lookup("EMAIL", "user.email").or_else(|| env::var("EMAIL").ok());
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) if !name.is_empty() => (name, email),
+        _ => return Err(io::Error::other(format!(
+            "{} identity unknown: please set user.name and user.email (or {}_NAME and {}_EMAIL)",
+            role.label(), prefix, prefix,
+        ))),
+    };
+
+    let timestamp = match env::var(format!("{}_DATE", prefix)) {
+        Ok(date) => parse_ident_date(&date)?,
+        Err(_) => Timestamp::now(),
+    };
+
+    Ok((User { name, email }, timestamp))
+}
@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::common::wildmatch::wildmatch;
+use super::config::{Config, expand_path};
+use super::repository::Repository;
+
+struct IgnorePattern {
+    pattern: String,
+    // パターンが書かれていた .gitignore のディレクトリ (ワークツリーからの相対パス、ルートは "")
+    base: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnorePattern {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // エスケープされていない末尾の空白は無視する
+        let mut line = line.to_string();
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line.pop();
+        }
+
+        let (negated, mut pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, line),
+        };
+        if pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern.remove(0);
+        }
+
+        let dir_only = pattern.ends_with('/');
+        if dir_only {
+            pattern.pop();
+        }
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/').to_string();
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self { pattern, base: base.to_string(), negated, dir_only, anchored })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(&self.base).and_then(|rest| rest.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+
+        if self.anchored {
+            wildmatch(&self.pattern, relative, true)
+        } else {
+            let basename = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, basename, true)
+        }
+    }
+}
+
+fn parse_patterns(content: &str, base: &str) -> Vec<IgnorePattern> {
+    content.lines().filter_map(|line| IgnorePattern::parse(line, base)).collect()
+}
+
+// .gitignore / .git/info/exclude / core.excludesFile による無視判定
+pub struct IgnoreRules {
+    work_tree: PathBuf,
+    // コマンドラインで指定されたパターン (最も優先度が高い)
+    command_line: Vec<IgnorePattern>,
+    excludes: Vec<IgnorePattern>,
+    // 各ディレクトリから読むファイルの名前 (None なら読まない)
+    per_dir_name: Option<String>,
+    per_dir: RefCell<HashMap<String, Vec<IgnorePattern>>>,
+}
+
+fn read_patterns(path: &Path) -> io::Result<Vec<IgnorePattern>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse_patterns(&content, "")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// core.excludesFile がなければ $XDG_CONFIG_HOME/git/ignore (未設定なら ~/.config/git/ignore)
+fn excludes_file(repo: &Repository) -> io::Result<Option<PathBuf>> {
+    if let Some(path) = Config::load(Some(repo))?.get("core.excludesfile") {
+        return expand_path(path).map(Some);
+    }
+    let xdg = env::var_os("XDG_CONFIG_HOME").filter(|value| !value.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    Ok(xdg.map(|xdg| xdg.join("git").join("ignore")))
+}
+
+impl IgnoreRules {
+    // 何も無視しない
+    pub fn new(repo: &Repository) -> Self {
+        Self {
+            work_tree: repo.work_tree().map(|p| p.to_path_buf()).unwrap_or_default(),
+            command_line: Vec::new(),
+            excludes: Vec::new(),
+            per_dir_name: None,
+            per_dir: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // git の標準のルール: core.excludesFile、info/exclude、各ディレクトリの .gitignore の順に優先度が高くなる
+    pub fn load(repo: &Repository) -> io::Result<Self> {
+        let mut rules = Self::new(repo);
+        rules.add_standard(repo)?;
+        Ok(rules)
+    }
+
+    // ls-files --exclude-standard
+    pub fn add_standard(&mut self, repo: &Repository) -> io::Result<()> {
+        if let Some(path) = excludes_file(repo)? {
+            self.add_exclude_file(&path)?;
+        }
+        self.add_exclude_file(&repo.common_dir().join("info").join("exclude"))?;
+        self.set_per_directory(".gitignore");
+        Ok(())
+    }
+
+    // ls-files --exclude=<pattern>
+    pub fn add_pattern(&mut self, pattern: &str) {
+        self.command_line.extend(IgnorePattern::parse(pattern, ""));
+    }
+
+    // ls-files --exclude-from=<file>。後から加えたものほど優先度が高い
+    pub fn add_exclude_file(&mut self, path: &Path) -> io::Result<()> {
+        self.excludes.extend(read_patterns(path)?);
+        Ok(())
+    }
+
+    // ls-files --exclude-per-directory=<file>
+    pub fn set_per_directory(&mut self, name: &str) {
+        self.per_dir_name = Some(name.to_string());
+        self.per_dir.borrow_mut().clear();
+    }
+
+    // パターンが一つもなければ何も無視しない
+    pub fn is_empty(&self) -> bool {
+        self.command_line.is_empty() && self.excludes.is_empty() && self.per_dir_name.is_none()
+    }
+
+    fn load_dir(&self, dir: &str) {
+        if self.per_dir.borrow().contains_key(dir) {
+            return;
+        }
+        let patterns = match &self.per_dir_name {
+            Some(name) => {
+                let path = self.work_tree.join(dir).join(name);
+                fs::read_to_string(path).map(|content| parse_patterns(&content, dir)).unwrap_or_default()
+            },
+            None => Vec::new(),
+        };
+        self.per_dir.borrow_mut().insert(dir.to_string(), patterns);
+    }
+
+    // 後に評価されるものほど優先度が高い: core.excludesFile、info/exclude、ルートの .gitignore、より深い .gitignore、コマンドラインの順
+    fn match_path(&self, path: &str, is_dir: bool) -> Option<bool> {
+        if let Some(pattern) = self.command_line.iter().rev().find(|pattern| pattern.matches(path, is_dir)) {
+            return Some(!pattern.negated);
+        }
+        let mut dirs = vec![String::new()];
+        let mut prefix = String::new();
+        let components: Vec<&str> = path.split('/').collect();
+        for component in components[..components.len() - 1].iter() {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(component);
+            dirs.push(prefix.clone());
+        }
+
+        let per_dir = {
+            for dir in dirs.iter() {
+                self.load_dir(dir);
+            }
+            self.per_dir.borrow()
+        };
+        for dir in dirs.iter().rev() {
+            if let Some(pattern) = per_dir[dir].iter().rev().find(|pattern| pattern.matches(path, is_dir)) {
+                return Some(!pattern.negated);
+            }
+        }
+        self.excludes.iter().rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+
+    // 親ディレクトリが無視されている場合、その中のファイルも無視される
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let mut end = 0;
+        while let Some(pos) = path[end..].find('/') {
+            end += pos;
+            if self.match_path(&path[..end], true) == Some(true) {
+                return true;
+            }
+            end += 1;
+        }
+        self.match_path(path, is_dir) == Some(true)
+    }
+}
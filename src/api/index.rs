@@ -0,0 +1,377 @@
+use std::fs::{self, Metadata};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+
+use sha1::{Digest, Sha1};
+
+use super::common::error::invalid_data;
+use super::common::varint::{decode_offset_varint, encode_offset_varint};
+use super::lockfile::LockFile;
+use super::objects::io::{HASH_SIZE, Hash};
+use super::objects::tree::Mode;
+use super::repository::Repository;
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const HEADER_SIZE: usize = 12;
+const NAME_MASK: u16 = 0x0fff;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime_sec: u32,
+    pub ctime_nsec: u32,
+    pub mtime_sec: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    // index には下位 32bit だけを記録する
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            ctime_sec: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime_sec: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    pub mode: Mode,
+    pub hash: Hash,
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: String, mode: Mode, hash: Hash, stat: StatData) -> Self {
+        Self {
+            stat,
+            mode,
+            hash,
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path,
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = (self.path.len().min(NAME_MASK as usize) as u16) | ((self.stage as u16 & 0x3) << FLAG_STAGE_SHIFT);
+        if self.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if self.is_extended() {
+            flags |= FLAG_EXTENDED;
+        }
+        flags
+    }
+
+    fn extended_flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.skip_worktree {
+            flags |= EXTENDED_SKIP_WORKTREE;
+        }
+        if self.intent_to_add {
+            flags |= EXTENDED_INTENT_TO_ADD;
+        }
+        flags
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexExtension {
+    pub signature: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+pub struct Index {
+    pub version: u32,
+    entries: Vec<IndexEntry>,
+    extensions: Vec<IndexExtension>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid_data("index file is truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+
+    // index が存在しない場合は空の index を返す
+    pub fn read(repo: &Repository) -> io::Result<Self> {
+        match fs::read(repo.index_path()) {
+            Ok(data) => Self::parse(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE + HASH_SIZE {
+            return Err(invalid_data("index file is too short"));
+        }
+        let (content, checksum) = data.split_at(data.len() - HASH_SIZE);
+        if Sha1::digest(content).as_slice() != checksum {
+            return Err(invalid_data("index file checksum mismatch"));
+        }
+
+        let mut cursor = Cursor { data: content, pos: 0 };
+        if cursor.take(4)? != SIGNATURE {
+            return Err(invalid_data("index file has bad signature"));
+        }
+        let version = cursor.u32()?;
+        if !(2..=4).contains(&version) {
+            return Err(invalid_data(format!("unsupported index version: {}", version)));
+        }
+        let count = cursor.u32()?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut prev_path: Vec<u8> = Vec::new();
+        for _ in 0..count {
+            let entry = Self::parse_entry(&mut cursor, version, &mut prev_path)?;
+            entries.push(entry);
+        }
+
+        let mut extensions = Vec::new();
+        while cursor.pos < content.len() {
+            let mut signature = [0u8; 4];
+            signature.copy_from_slice(cursor.take(4)?);
+            let size = cursor.u32()? as usize;
+            let data = cursor.take(size)?.to_vec();
+            if !signature[0].is_ascii_uppercase() {
+                return Err(invalid_data(format!(
+                    "unsupported index extension: {}", String::from_utf8_lossy(&signature)
+                )));
+            }
+            extensions.push(IndexExtension { signature, data });
+        }
+
+        Ok(Self { version, entries, extensions })
+    }
+
+    fn parse_entry(cursor: &mut Cursor, version: u32, prev_path: &mut Vec<u8>) -> io::Result<IndexEntry> {
+        let start = cursor.pos;
+        let stat = StatData {
+            ctime_sec: cursor.u32()?,
+            ctime_nsec: cursor.u32()?,
+            mtime_sec: cursor.u32()?,
+            mtime_nsec: cursor.u32()?,
+            dev: cursor.u32()?,
+            ino: cursor.u32()?,
+            uid: 0,
+            gid: 0,
+            size: 0,
+        };
+        let mode = Mode(cursor.u32()?);
+        let stat = StatData { uid: cursor.u32()?, gid: cursor.u32()?, size: cursor.u32()?, ..stat };
+        let hash = Hash::from_bytes(cursor.take(HASH_SIZE)?)?;
+        let flags = cursor.u16()?;
+        let extended_flags = if flags & FLAG_EXTENDED != 0 {
+            if version < 3 {
+                return Err(invalid_data("extended index entry flags in version 2 index"));
+            }
+            cursor.u16()?
+        } else {
+            0
+        };
+
+        let path = if version >= 4 {
+            let (strip, used) = decode_offset_varint(&cursor.data[cursor.pos..])?;
+            cursor.take(used)?;
+            let strip = strip as usize;
+            if strip > prev_path.len() {
+                return Err(invalid_data("malformed prefix-compressed path in index"));
+            }
+            let rest = &cursor.data[cursor.pos..];
+            let nul = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid_data("unterminated path in index"))?;
+            let mut path = prev_path[..prev_path.len() - strip].to_vec();
+            path.extend_from_slice(cursor.take(nul + 1)?.split_last().unwrap().1);
+            path
+        } else {
+            let rest = &cursor.data[cursor.pos..];
+            let nul = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid_data("unterminated path in index"))?;
+            let path = cursor.take(nul)?.to_vec();
+            // パスの後ろは 8 バイト境界まで NUL で埋められている (最低 1 バイト)
+            let entry_len = cursor.pos - start;
+            let padded_len = (entry_len + 8) & !7;
+            cursor.take(padded_len - entry_len)?;
+            path
+        };
+        *prev_path = path.clone();
+
+        Ok(IndexEntry {
+            stat,
+            mode,
+            hash,
+            stage: ((flags >> FLAG_STAGE_SHIFT) & 0x3) as u8,
+            assume_valid: flags & FLAG_ASSUME_VALID != 0,
+            skip_worktree: extended_flags & EXTENDED_SKIP_WORKTREE != 0,
+            intent_to_add: extended_flags & EXTENDED_INTENT_TO_ADD != 0,
+            path: String::from_utf8(path).map_err(|_| invalid_data("index path is not valid UTF-8"))?,
+        })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+        let mut version = self.version;
+        if version == 2 && self.entries.iter().any(|entry| entry.is_extended()) {
+            version = 3;
+        }
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SIGNATURE);
+        buf.extend_from_slice(&version.to_be_bytes());
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut prev_path: &[u8] = &[];
+        for entry in self.entries.iter() {
+            let start = buf.len();
+            let stat = &entry.stat;
+            for value in [stat.ctime_sec, stat.ctime_nsec, stat.mtime_sec, stat.mtime_nsec, stat.dev, stat.ino,
+                          entry.mode.0, stat.uid, stat.gid, stat.size].iter() {
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+            buf.extend_from_slice(entry.hash.as_bytes());
+            buf.extend_from_slice(&entry.flags().to_be_bytes());
+            if entry.is_extended() {
+                buf.extend_from_slice(&entry.extended_flags().to_be_bytes());
+            }
+
+            let path = entry.path.as_bytes();
+            if version >= 4 {
+                let common = prev_path.iter().zip(path.iter()).take_while(|(a, b)| a == b).count();
+                buf.extend_from_slice(&encode_offset_varint((prev_path.len() - common) as u64));
+                buf.extend_from_slice(&path[common..]);
+                buf.push(0);
+            } else {
+                buf.extend_from_slice(path);
+                let entry_len = buf.len() - start;
+                let padded_len = (entry_len + 8) & !7;
+                buf.resize(start + padded_len, 0);
+            }
+            prev_path = path;
+        }
+
+        for extension in self.extensions.iter() {
+            buf.extend_from_slice(&extension.signature);
+            buf.extend_from_slice(&(extension.data.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&extension.data);
+        }
+
+        let checksum = Sha1::digest(&buf);
+        writer.write_all(&buf)?;
+        writer.write_all(checksum.as_slice())
+    }
+
+    pub fn write(&self, repo: &Repository) -> io::Result<()> {
+        let mut lock = LockFile::acquire(&repo.index_path())?;
+        self.write_to(&mut lock)?;
+        lock.commit()
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn extensions(&self) -> &[IndexExtension] {
+        &self.extensions
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            entry.path.as_bytes().cmp(path.as_bytes()).then(entry.stage.cmp(&stage))
+        })
+    }
+
+    pub fn entry(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.position(path, stage).ok().map(|pos| &self.entries[pos])
+    }
+
+    // エントリの追加・削除で内容が変わるため、木構造のキャッシュなどは破棄する
+    fn invalidate_cache(&mut self) {
+        self.extensions.retain(|extension| &extension.signature != b"TREE" && &extension.signature != b"UNTR");
+    }
+
+    // 同じパスの既存エントリ (コンフリクト中のものを含む) を置き換える
+    pub fn add(&mut self, entry: IndexEntry) {
+        self.invalidate_cache();
+        if entry.stage == 0 {
+            self.entries.retain(|e| e.path != entry.path);
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(pos) => self.entries[pos] = entry,
+            Err(pos) => self.entries.insert(pos, entry),
+        }
+    }
+
+    // 指定したパスの全ステージのエントリを削除する
+    pub fn remove(&mut self, path: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if self.entries.len() != len {
+            self.invalidate_cache();
+            true
+        } else {
+            false
+        }
+    }
+}
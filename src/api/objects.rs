@@ -3,3 +3,8 @@ pub mod base;
 pub mod blob;
 pub mod tree;
 pub mod commit;
+pub mod tag;
+pub mod object;
+pub mod delta;
+pub mod pack;
+pub mod pack_writer;
@@ -1,4 +1,47 @@
-use std::io::Write;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::api::common::error::invalid_data;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectType {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ObjectType::Blob => "blob",
+            ObjectType::Tree => "tree",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+        }
+    }
+}
+
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ObjectType {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "blob" => Ok(ObjectType::Blob),
+            "tree" => Ok(ObjectType::Tree),
+            "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
+            _ => Err(invalid_data(format!("unknown object type: {:?}", s))),
+        }
+    }
+}
 
 pub trait ObjectBase {
     fn obj_type(&self) -> &str;
@@ -17,3 +60,41 @@ pub trait ObjectBase {
 
     fn write_body_to<W>(&self, writer: &mut W) -> std::io::Result<()> where W: Write;
 }
+
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 実際に書き出して本体のサイズを求める (手計算では書き出し処理とずれやすいヘッダ形式のオブジェクト向け)
+pub fn serialized_body_size<T>(object: &T) -> usize where T: ObjectBase + ?Sized {
+    let mut counter = ByteCounter(0);
+    object.write_body_to(&mut counter).expect("writing to a byte counter never fails");
+    counter.0
+}
+
+// write_header_to が書き出す "<type> <size>\0" を読み取り、型・本体のサイズ・ヘッダの長さを返す
+pub fn parse_header(raw: &[u8]) -> io::Result<(ObjectType, usize, usize)> {
+    let nul = raw.iter().position(|&b| b == 0)
+        .ok_or_else(|| invalid_data("object header is not terminated"))?;
+    let header = std::str::from_utf8(&raw[..nul])
+        .map_err(|_| invalid_data("object header is not valid UTF-8"))?;
+
+    let (obj_type, size) = header.split_once(' ')
+        .ok_or_else(|| invalid_data(format!("malformed object header: {:?}", header)))?;
+    let obj_type = obj_type.parse()?;
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_digit()) || (size.len() > 1 && size.starts_with('0')) {
+        return Err(invalid_data(format!("malformed object size: {:?}", size)));
+    }
+    let size = size.parse().map_err(|_| invalid_data(format!("malformed object size: {:?}", size)))?;
+
+    Ok((obj_type, size, nul + 1))
+}
@@ -14,12 +14,16 @@ impl BlobObject {
         }
     }
 
-    pub fn from_path(path: &String) -> std::io::Result<Self> {
+    pub fn from_path(path: &str) -> std::io::Result<Self> {
         let mut f = File::open(path)?;
         let mut buf = Vec::new();
         f.read_to_end(&mut buf)?;
         Ok(Self::new(buf))
     }
+
+    pub fn parse(body: Vec<u8>) -> std::io::Result<Self> {
+        Ok(Self::new(body))
+    }
 }
 
 impl ObjectBase for BlobObject {
@@ -1,41 +1,240 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
 
 use crate::api::common::datetime::Timestamp;
+use crate::api::common::error::invalid_data;
 use crate::api::common::user::User;
 
-use super::base::ObjectBase;
-use super::io::{Hash, STR_HASH_LEN};
+use super::base::{ObjectBase, serialized_body_size};
+use super::io::Hash;
 
-pub struct CommitObject<'a> {
+pub struct CommitObject {
     pub tree_hash: Hash,
-    pub parent_hash: Hash,
-    pub author: &'a User,
-    pub author_timestamp: &'a Timestamp,
-    pub committer: &'a User,
-    pub commit_timestamp: &'a Timestamp,
-    pub message: String,
+    pub parents: Vec<Hash>,
+    pub author: User,
+    pub author_timestamp: Timestamp,
+    pub committer: User,
+    pub commit_timestamp: Timestamp,
+    pub encoding: Option<String>,
+    pub mergetags: Vec<String>,
+    pub gpgsig: Option<String>,
+    // 上記以外のヘッダ (出現順を保持する)
+    pub extra_headers: Vec<(String, String)>,
+    // i18n.commitEncoding などで UTF-8 以外のこともあるので、書き戻したときに同じ内容になるようバイト列のまま持つ
+    pub message: Vec<u8>,
 }
 
-impl <'a>ObjectBase for CommitObject<'a> {
+impl CommitObject {
+    pub fn parse(body: &[u8]) -> io::Result<Self> {
+        let (headers, message) = split_headers(body)?;
+
+        let mut tree_hash = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut encoding = None;
+        let mut mergetags = Vec::new();
+        let mut gpgsig = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" if tree_hash.is_none() => tree_hash = Some(value.parse()?),
+                "parent" => parents.push(value.parse()?),
+                "author" if author.is_none() => author = Some(User::parse_with_timestamp(&value)?),
+                "committer" if committer.is_none() => committer = Some(User::parse_with_timestamp(&value)?),
+                "encoding" if encoding.is_none() => encoding = Some(value),
+                "mergetag" => mergetags.push(value),
+                "gpgsig" if gpgsig.is_none() => gpgsig = Some(value),
+                "tree" | "author" | "committer" | "encoding" | "gpgsig" => {
+                    return Err(invalid_data(format!("duplicate {} header in commit", key)));
+                },
+                _ => extra_headers.push((key, value)),
+            }
+        }
+
+        let tree_hash = tree_hash.ok_or_else(|| invalid_data("commit has no tree header"))?;
+        let (author, author_timestamp) = author.ok_or_else(|| invalid_data("commit has no author header"))?;
+        let (committer, commit_timestamp) = committer.ok_or_else(|| invalid_data("commit has no committer header"))?;
+
+        Ok(Self {
+            tree_hash,
+            parents,
+            author,
+            author_timestamp,
+            committer,
+            commit_timestamp,
+            encoding,
+            mergetags,
+            gpgsig,
+            extra_headers,
+            message: message.to_vec(),
+        })
+    }
+}
+
+// git commit のデフォルトと同様に、行末の空白と前後の空行を取り除き、連続する空行を一つにまとめる
+// strip_comments が true の場合は '#' で始まる行も取り除く
+pub fn cleanup_message(message: &str, strip_comments: bool) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines.join("\n") + "\n"
+}
+
+impl CommitObject {
+    // UTF-8 でないメッセージは置換文字に置き換えて読む
+    pub fn message_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.message)
+    }
+
+    pub fn subject(&self) -> String {
+        self.message_lossy().lines().next().unwrap_or("").to_string()
+    }
+}
+
+// 複数行の値は 2 行目以降を空白 1 つでインデントして書く
+pub(super) fn write_header<W>(writer: &mut W, key: &str, value: &str) -> io::Result<()> where W: Write {
+    let mut lines = value.split('\n');
+    writeln!(writer, "{} {}", key, lines.next().unwrap_or(""))?;
+    for line in lines {
+        writeln!(writer, " {}", line)?;
+    }
+    Ok(())
+}
+
+pub(super) type Headers = Vec<(String, String)>;
+
+// ヘッダ部分を (key, value) の列とメッセージに分ける
+// 空白で始まる行は直前のヘッダの値の続きとして扱う
+// i18n.commitEncoding などで UTF-8 以外の文字が含まれることがあるので、バイト列のまま分けてから値を文字列にする
+pub(super) fn split_headers(body: &[u8]) -> io::Result<(Headers, &[u8])> {
+    let mut headers: Headers = Vec::new();
+    let mut rest = body;
+
+    loop {
+        if rest.is_empty() {
+            return Ok((headers, rest));
+        }
+        let (line, next) = match rest.iter().position(|&b| b == b'\n') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+            None => (rest, &[][..]),
+        };
+        rest = next;
+
+        if line.is_empty() {
+            return Ok((headers, rest));
+        }
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers.last_mut()
+                .ok_or_else(|| invalid_data("continuation line without header"))?;
+            value.push('\n');
+            value.push_str(&String::from_utf8_lossy(continuation));
+            continue;
+        }
+        let space = line.iter().position(|&b| b == b' ')
+            .ok_or_else(|| invalid_data(format!("malformed header line: {:?}", String::from_utf8_lossy(line))))?;
+        let key = String::from_utf8_lossy(&line[..space]).into_owned();
+        let value = String::from_utf8_lossy(&line[space + 1..]).into_owned();
+        headers.push((key, value));
+    }
+}
+
+impl ObjectBase for CommitObject {
     fn obj_type(&self) -> &str {
         "commit"
     }
 
     fn body_size(&self) -> usize {
-        4 + 1 + STR_HASH_LEN + 1 +
-        6 + 1 + STR_HASH_LEN + 1 +
-        6 + 1 + self.author.to_string().len() + 1 + self.author_timestamp.to_string().len() + 1 +
-        9 + 1 + self.committer.to_string().len() + 1 + self.commit_timestamp.to_string().len() + 1 +
-        1 +
-        self.message.len() + 1
+        serialized_body_size(self)
     }
 
     fn write_body_to<W>(&self, writer: &mut W) -> std::io::Result<()> where W: std::io::Write {
         writeln!(writer, "tree {}", &self.tree_hash)?;
-        writeln!(writer, "parent {}", &self.parent_hash)?;
+        for parent in self.parents.iter() {
+            writeln!(writer, "parent {}", parent)?;
+        }
         writeln!(writer, "author {} {}", &self.author, &self.author_timestamp)?;
         writeln!(writer, "committer {} {}", &self.committer, &self.commit_timestamp)?;
+        if let Some(encoding) = &self.encoding {
+            write_header(writer, "encoding", encoding)?;
+        }
+        for mergetag in self.mergetags.iter() {
+            write_header(writer, "mergetag", mergetag)?;
+        }
+        if let Some(gpgsig) = &self.gpgsig {
+            write_header(writer, "gpgsig", gpgsig)?;
+        }
+        for (key, value) in self.extra_headers.iter() {
+            write_header(writer, key, value)?;
+        }
         writeln!(writer)?;
-        writeln!(writer, "{}", self.message)?;
+        writer.write_all(&self.message)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::objects::tag::TagObject;
+
+    #[test]
+    fn parse_non_utf8_commit() {
+        let body = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author Jos\xe9 <jose@example.com> 1700000000 +0000\n\
+            committer Jos\xe9 <jose@example.com> 1700000000 +0000\n\
+            encoding ISO-8859-1\n\
+            \n\
+            caf\xe9\n";
+        let commit = CommitObject::parse(body).unwrap();
+        assert_eq!(commit.author.name, "Jos\u{fffd}");
+        assert_eq!(commit.encoding.as_deref(), Some("ISO-8859-1"));
+        assert_eq!(commit.subject(), "caf\u{fffd}");
+        assert_eq!(commit.message, b"caf\xe9\n");
+    }
+
+    #[test]
+    fn non_utf8_message_round_trip() {
+        let body = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1700000000 +0000\n\
+            committer A U Thor <author@example.com> 1700000000 +0000\n\
+            encoding ISO-8859-1\n\
+            \n\
+            caf\xe9\n\
+            \n\
+            na\xefve\n";
+        let commit = CommitObject::parse(body).unwrap();
+        let mut written = Vec::new();
+        commit.write_body_to(&mut written).unwrap();
+        assert_eq!(written, body);
+    }
+
+    #[test]
+    fn parse_non_utf8_tag() {
+        let body = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            type tree\n\
+            tag v1\n\
+            tagger Jos\xe9 <jose@example.com> 1700000000 +0000\n\
+            \n\
+            caf\xe9\n";
+        let tag = TagObject::parse(body).unwrap();
+        assert_eq!(tag.tag, "v1");
+        assert_eq!(tag.message, "caf\u{fffd}\n");
+        assert!(tag.signature.is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::api::common::error::invalid_data;
+
+// 一致を探すときの単位。ベースはこの長さのブロックごとに索引を作る
+const BLOCK_SIZE: usize = 16;
+// pack v2 との互換性のため git はコピー 1 回あたり 64KiB までにしている
+const MAX_COPY_SIZE: usize = 0x10000;
+const MAX_INSERT_SIZE: usize = 0x7f;
+// 同じ内容のブロックが大量にあるときに探索が遅くならないようにする
+const MAX_CANDIDATES: usize = 64;
+
+// デルタ先頭のサイズは下位 7bit ずつのリトルエンディアン
+fn read_delta_size(delta: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut size = 0u64;
+    let mut shift = 0;
+    loop {
+        let c = *delta.get(*pos).ok_or_else(|| invalid_data("truncated delta header"))?;
+        *pos += 1;
+        if shift > 63 {
+            return Err(invalid_data("delta size overflow"));
+        }
+        size |= ((c & 0x7f) as u64) << shift;
+        shift += 7;
+        if c & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = read_delta_size(delta, &mut pos)?;
+    if base_size != base.len() as u64 {
+        return Err(invalid_data("delta base size mismatch"));
+    }
+    let result_size = read_delta_size(delta, &mut pos)? as usize;
+    let truncated = || invalid_data("truncated delta");
+
+    let mut result = Vec::with_capacity(result_size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // コピー命令: 立っているビットに対応するオフセットとサイズのバイトだけが続く
+            let mut copy_offset = 0usize;
+            let mut copy_size = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    copy_offset |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    copy_size |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            let chunk = copy_offset.checked_add(copy_size)
+                .and_then(|end| base.get(copy_offset..end))
+                .ok_or_else(|| invalid_data("delta copies outside of the base object"))?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            // 挿入命令: 続く op バイトをそのまま追加する
+            let chunk = delta.get(pos..pos + op as usize).ok_or_else(truncated)?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            return Err(invalid_data("unexpected delta opcode 0"));
+        }
+        if result.len() > result_size {
+            return Err(invalid_data("delta produces more data than its header says"));
+        }
+    }
+    if result.len() != result_size {
+        return Err(invalid_data("delta result size mismatch"));
+    }
+    Ok(result)
+}
+
+fn write_delta_size(out: &mut Vec<u8>, mut size: u64) {
+    while size >= 0x80 {
+        out.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+fn push_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+// 0 のバイトは省略し、どのバイトが続くかを命令のビットで示す
+fn push_copy(out: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let chunk = size.min(MAX_COPY_SIZE);
+        let mut op = 0x80u8;
+        let mut args = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                args.push(byte);
+            }
+        }
+        // 0x10000 はサイズのバイトがすべて 0 になり、読み込み側で 0x10000 として扱われる
+        for i in 0..3 {
+            let byte = (chunk >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 0x10 << i;
+                args.push(byte);
+            }
+        }
+        out.push(op);
+        out.extend_from_slice(&args);
+        offset += chunk;
+        size -= chunk;
+    }
+}
+
+fn block_key(data: &[u8], pos: usize) -> u128 {
+    let mut block = [0u8; BLOCK_SIZE];
+    block.copy_from_slice(&data[pos..pos + BLOCK_SIZE]);
+    u128::from_le_bytes(block)
+}
+
+// ベースの各ブロックの位置の索引。同じベースに対して複数のデルタを作るときに使い回す
+pub struct DeltaIndex {
+    base_len: usize,
+    blocks: HashMap<u128, Vec<usize>>,
+}
+
+impl DeltaIndex {
+    pub fn new(base: &[u8]) -> Self {
+        let mut blocks: HashMap<u128, Vec<usize>> = HashMap::new();
+        // コピー命令のオフセットは 32bit までしか表せない
+        if base.len() <= u32::MAX as usize {
+            for pos in (0..base.len() / BLOCK_SIZE).map(|i| i * BLOCK_SIZE) {
+                let positions = blocks.entry(block_key(base, pos)).or_default();
+                if positions.len() < MAX_CANDIDATES {
+                    positions.push(pos);
+                }
+            }
+        }
+        Self { base_len: base.len(), blocks }
+    }
+
+    // target をベースからのコピーと挿入で表したデルタを作る。max_size を超える場合は None を返す
+    pub fn create_delta(&self, base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        assert_eq!(base.len(), self.base_len);
+        if self.blocks.is_empty() {
+            return None;
+        }
+
+        let mut out = Vec::new();
+        write_delta_size(&mut out, base.len() as u64);
+        write_delta_size(&mut out, target.len() as u64);
+
+        let mut insert_start = 0;
+        let mut pos = 0;
+        while pos + BLOCK_SIZE <= target.len() {
+            let best = self.blocks.get(&block_key(target, pos)).and_then(|candidates| {
+                candidates.iter().map(|&base_pos| {
+                    let len = base[base_pos..].iter().zip(&target[pos..]).take_while(|(a, b)| a == b).count();
+                    (base_pos, len)
+                }).max_by_key(|&(_, len)| len)
+            });
+            let (mut base_pos, mut len) = match best {
+                Some(best) if best.1 >= BLOCK_SIZE => best,
+                _ => {
+                    pos += 1;
+                    continue;
+                },
+            };
+
+            // 直前の挿入予定のバイトもベースと一致するならコピーに含める
+            let mut start = pos;
+            while start > insert_start && base_pos > 0 && base[base_pos - 1] == target[start - 1] {
+                start -= 1;
+                base_pos -= 1;
+                len += 1;
+            }
+
+            push_insert(&mut out, &target[insert_start..start]);
+            push_copy(&mut out, base_pos, len);
+            pos = start + len;
+            insert_start = pos;
+            if out.len() > max_size {
+                return None;
+            }
+        }
+        push_insert(&mut out, &target[insert_start..]);
+
+        if out.len() > max_size {
+            return None;
+        }
+        Some(out)
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, Write};
+
+use crate::api::common::error::invalid_data;
+
+use super::base::{ObjectBase, ObjectType, parse_header};
+use super::blob::BlobObject;
+use super::commit::CommitObject;
+use super::tag::TagObject;
+use super::tree::TreeObject;
+
+pub enum Object {
+    Blob(BlobObject),
+    Tree(TreeObject),
+    Commit(CommitObject),
+    Tag(TagObject),
+}
+
+impl Object {
+    // ObjectReader::read が返すヘッダ付きの内容をパースする
+    pub fn parse(mut raw: Vec<u8>) -> io::Result<Self> {
+        let (obj_type, size, header_len) = parse_header(&raw)?;
+        if raw.len() - header_len != size {
+            return Err(invalid_data(format!(
+                "object size mismatch: header says {} but body is {} bytes", size, raw.len() - header_len
+            )));
+        }
+        raw.drain(..header_len);
+        Self::parse_body(obj_type, raw)
+    }
+
+    pub fn parse_body(obj_type: ObjectType, body: Vec<u8>) -> io::Result<Self> {
+        Ok(match obj_type {
+            ObjectType::Blob => Object::Blob(BlobObject::parse(body)?),
+            ObjectType::Tree => Object::Tree(TreeObject::parse(&body)?),
+            ObjectType::Commit => Object::Commit(CommitObject::parse(&body)?),
+            ObjectType::Tag => Object::Tag(TagObject::parse(&body)?),
+        })
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Object::Blob(_) => ObjectType::Blob,
+            Object::Tree(_) => ObjectType::Tree,
+            Object::Commit(_) => ObjectType::Commit,
+            Object::Tag(_) => ObjectType::Tag,
+        }
+    }
+}
+
+impl ObjectBase for Object {
+    fn obj_type(&self) -> &str {
+        self.object_type().as_str()
+    }
+
+    fn body_size(&self) -> usize {
+        match self {
+            Object::Blob(blob) => blob.body_size(),
+            Object::Tree(tree) => tree.body_size(),
+            Object::Commit(commit) => commit.body_size(),
+            Object::Tag(tag) => tag.body_size(),
+        }
+    }
+
+    fn write_body_to<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+        match self {
+            Object::Blob(blob) => blob.write_body_to(writer),
+            Object::Tree(tree) => tree.write_body_to(writer),
+            Object::Commit(commit) => commit.write_body_to(writer),
+            Object::Tag(tag) => tag.write_body_to(writer),
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::api::common::error::invalid_data;
+use crate::api::common::varint::decode_offset_varint;
+use crate::api::repository::Repository;
+
+use super::base::ObjectType;
+use super::delta::apply_delta;
+use super::io::{HASH_SIZE, Hash, ObjectReader};
+
+pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+pub const IDX_V2_SIGNATURE: &[u8; 4] = b"\xfftOc";
+
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+pub fn type_id(obj_type: ObjectType) -> u8 {
+    match obj_type {
+        ObjectType::Commit => OBJ_COMMIT,
+        ObjectType::Tree => OBJ_TREE,
+        ObjectType::Blob => OBJ_BLOB,
+        ObjectType::Tag => OBJ_TAG,
+    }
+}
+
+const FANOUT_SIZE: usize = 256 * 4;
+// 壊れた pack でデルタが循環しても止まるようにする
+const MAX_DELTA_CHAIN: usize = 10000;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+// .idx ファイル (v1 / v2)。大きなリポジトリでも全エントリを展開しないよう、生のバイト列を直接引く
+pub struct PackIndex {
+    version: u32,
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(fs::read(path)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> io::Result<Self> {
+        let version = if data.starts_with(IDX_V2_SIGNATURE) {
+            if data.len() < 8 {
+                return Err(invalid_data("pack index is truncated"));
+            }
+            let version = read_u32(&data, 4);
+            if version != 2 {
+                return Err(invalid_data(format!("unsupported pack index version: {}", version)));
+            }
+            version
+        } else {
+            1
+        };
+
+        let mut index = Self { version, data, count: 0 };
+        let fanout_end = index.fanout_offset() + FANOUT_SIZE;
+        if index.data.len() < fanout_end {
+            return Err(invalid_data("pack index is truncated"));
+        }
+        let mut prev = 0;
+        for i in 0..256 {
+            let n = index.fanout(i);
+            if n < prev {
+                return Err(invalid_data("pack index has a non-monotonic fanout table"));
+            }
+            prev = n;
+        }
+        index.count = prev as usize;
+
+        let entry_size = if version == 1 { 4 + HASH_SIZE } else { HASH_SIZE + 4 + 4 };
+        if index.data.len() < fanout_end + index.count * entry_size + 2 * HASH_SIZE {
+            return Err(invalid_data("pack index is truncated"));
+        }
+        Ok(index)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn fanout_offset(&self) -> usize {
+        if self.version == 1 { 0 } else { 8 }
+    }
+
+    fn fanout(&self, i: usize) -> u32 {
+        read_u32(&self.data, self.fanout_offset() + i * 4)
+    }
+
+    fn table_offset(&self) -> usize {
+        self.fanout_offset() + FANOUT_SIZE
+    }
+
+    fn hash_bytes(&self, i: usize) -> &[u8] {
+        let pos = if self.version == 1 {
+            self.table_offset() + i * (4 + HASH_SIZE) + 4
+        } else {
+            self.table_offset() + i * HASH_SIZE
+        };
+        &self.data[pos..pos + HASH_SIZE]
+    }
+
+    pub fn hash_at(&self, i: usize) -> Hash {
+        Hash::from_bytes(self.hash_bytes(i)).unwrap()
+    }
+
+    pub fn offset_at(&self, i: usize) -> io::Result<u64> {
+        if self.version == 1 {
+            return Ok(read_u32(&self.data, self.table_offset() + i * (4 + HASH_SIZE)) as u64);
+        }
+        let offsets = self.table_offset() + self.count * (HASH_SIZE + 4);
+        let offset = read_u32(&self.data, offsets + i * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+
+        // 最上位ビットが立っている場合は 64bit オフセットの表を指す
+        let pos = offsets + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        if pos + 8 > self.data.len() - 2 * HASH_SIZE {
+            return Err(invalid_data("pack index has an out of range 64-bit offset"));
+        }
+        Ok(u64::from_be_bytes(self.data[pos..pos + 8].try_into().unwrap()))
+    }
+
+    // fanout で範囲を絞ってから二分探索する
+    pub fn position(&self, hash: &Hash) -> Option<usize> {
+        let first = hash.as_bytes()[0] as usize;
+        let mut lo = if first == 0 { 0 } else { self.fanout(first - 1) as usize };
+        let mut hi = self.fanout(first) as usize;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.hash_bytes(mid).cmp(&hash.as_bytes()[..]) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    // 16 進の接頭辞 (2 文字以上) に一致するハッシュ
+    pub fn find_prefix(&self, prefix: &str) -> Vec<Hash> {
+        let first = match prefix.get(..2).and_then(|first| u8::from_str_radix(first, 16).ok()) {
+            Some(first) => first as usize,
+            None => return Vec::new(),
+        };
+        let lo = if first == 0 { 0 } else { self.fanout(first - 1) as usize };
+        let hi = self.fanout(first) as usize;
+        (lo..hi).map(|i| self.hash_at(i)).filter(|hash| hash.to_string().starts_with(prefix)).collect()
+    }
+
+    pub fn find(&self, hash: &Hash) -> io::Result<Option<u64>> {
+        self.position(hash).map(|i| self.offset_at(i)).transpose()
+    }
+
+    // 対応する .pack ファイルのチェックサム
+    pub fn pack_checksum(&self) -> Hash {
+        let pos = self.data.len() - 2 * HASH_SIZE;
+        Hash::from_bytes(&self.data[pos..pos + HASH_SIZE]).unwrap()
+    }
+
+    pub fn verify_checksum(&self) -> io::Result<()> {
+        let (content, checksum) = self.data.split_at(self.data.len() - HASH_SIZE);
+        if Sha1::digest(content).as_slice() != checksum {
+            return Err(invalid_data("pack index checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    // (ハッシュ, pack 内のオフセット) をハッシュ順に返す
+    pub fn entries(&self) -> impl Iterator<Item = io::Result<(Hash, u64)>> + '_ {
+        (0..self.count).map(move |i| Ok((self.hash_at(i), self.offset_at(i)?)))
+    }
+}
+
+enum EntryKind {
+    Base(ObjectType),
+    OfsDelta(u64),
+    RefDelta(Hash),
+}
+
+struct EntryHeader {
+    kind: EntryKind,
+    size: u64,
+}
+
+// (型, 本体のサイズ, 本体の reader)
+pub type OpenedObject = (ObjectType, u64, Box<dyn Read>);
+
+pub struct Pack {
+    pack_path: PathBuf,
+    index: PackIndex,
+}
+
+impl Pack {
+    pub fn open(idx_path: &Path) -> io::Result<Self> {
+        let pack_path = idx_path.with_extension("pack");
+        if !pack_path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("packfile {} not found", pack_path.display()),
+            ));
+        }
+        Ok(Self {
+            pack_path,
+            index: PackIndex::read(idx_path)?,
+        })
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    // .pack 末尾のチェックサムが内容と一致し、.idx に記録されたものとも一致するか確かめる
+    pub fn verify_checksum(&self) -> io::Result<()> {
+        self.index.verify_checksum()?;
+
+        let mut file = File::open(&self.pack_path)?;
+        let len = file.metadata()?.len();
+        if len < 12 + HASH_SIZE as u64 {
+            return Err(invalid_data("packfile is truncated"));
+        }
+        let mut hasher = Sha1::new();
+        io::copy(&mut (&mut file).take(len - HASH_SIZE as u64), &mut hasher)?;
+        let mut checksum = [0u8; HASH_SIZE];
+        file.read_exact(&mut checksum)?;
+        if hasher.finalize().as_slice() != checksum {
+            return Err(invalid_data("packfile checksum mismatch"));
+        }
+        if Hash(checksum) != self.index.pack_checksum() {
+            return Err(invalid_data("packfile does not match its index"));
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.index.position(hash).is_some()
+    }
+
+    fn open_file(&self) -> io::Result<BufReader<File>> {
+        let mut file = BufReader::new(File::open(&self.pack_path)?);
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)?;
+        let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        if &header[..4] != PACK_SIGNATURE || (version != 2 && version != 3) {
+            return Err(invalid_data(format!("{} is not a supported packfile", self.pack_path.display())));
+        }
+        Ok(file)
+    }
+
+    // pack 内のオブジェクトを開く。デルタでなければ本体を展開しながら読めるようにする
+    pub fn open_object(&self, repo: &Repository, hash: &Hash) -> io::Result<Option<OpenedObject>> {
+        let offset = match self.index.find(hash)? {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        let mut file = self.open_file()?;
+        let header = read_entry_header(&mut file, offset)?;
+        if let EntryKind::Base(obj_type) = header.kind {
+            let body = ZlibDecoder::new(file).take(header.size);
+            return Ok(Some((obj_type, header.size, Box::new(body))));
+        }
+
+        let (obj_type, body) = self.read_at(repo, &mut file, offset)?;
+        Ok(Some((obj_type, body.len() as u64, Box::new(Cursor::new(body)))))
+    }
+
+    pub fn read_object(&self, repo: &Repository, hash: &Hash) -> io::Result<Option<(ObjectType, Vec<u8>)>> {
+        match self.index.find(hash)? {
+            Some(offset) => self.read_at(repo, &mut self.open_file()?, offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // デルタをベースまでたどってから、ベースに近い方から順に適用する
+    fn read_at(&self, repo: &Repository, file: &mut BufReader<File>, mut offset: u64) -> io::Result<(ObjectType, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let (obj_type, mut body) = loop {
+            if deltas.len() > MAX_DELTA_CHAIN {
+                return Err(invalid_data(format!("delta chain too long at offset {}", offset)));
+            }
+            let header = read_entry_header(file, offset)?;
+            let data = inflate(&mut *file, header.size)?;
+            match header.kind {
+                EntryKind::Base(obj_type) => break (obj_type, data),
+                EntryKind::OfsDelta(base_offset) => {
+                    deltas.push(data);
+                    offset = base_offset;
+                },
+                EntryKind::RefDelta(base) => {
+                    deltas.push(data);
+                    match self.index.find(&base)? {
+                        Some(base_offset) => offset = base_offset,
+                        None => {
+                            // 同じ pack にないベースは別の pack や loose オブジェクトから読む
+                            let mut reader = ObjectReader::new(repo, &base.to_string())?;
+                            let mut body = Vec::new();
+                            reader.read_to_end(&mut body)?;
+                            break (reader.object_type(), body);
+                        },
+                    }
+                },
+            }
+        };
+        for delta in deltas.iter().rev() {
+            body = apply_delta(&body, delta)?;
+        }
+        Ok((obj_type, body))
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+// 種類 (3bit) とサイズ (可変長) からなるエントリのヘッダと、デルタならベースの位置を読む
+fn read_entry_header(file: &mut BufReader<File>, offset: u64) -> io::Result<EntryHeader> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut c = read_byte(file)?;
+    let type_id = (c >> 4) & 0x7;
+    let mut size = (c & 0x0f) as u64;
+    let mut shift = 4;
+    while c & 0x80 != 0 {
+        if shift > 57 {
+            return Err(invalid_data(format!("bad object size at offset {}", offset)));
+        }
+        c = read_byte(file)?;
+        size |= ((c & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+
+    let kind = match type_id {
+        OBJ_COMMIT => EntryKind::Base(ObjectType::Commit),
+        OBJ_TREE => EntryKind::Base(ObjectType::Tree),
+        OBJ_BLOB => EntryKind::Base(ObjectType::Blob),
+        OBJ_TAG => EntryKind::Base(ObjectType::Tag),
+        OBJ_OFS_DELTA => {
+            let mut bytes = vec![read_byte(file)?];
+            while bytes[bytes.len() - 1] & 0x80 != 0 {
+                bytes.push(read_byte(file)?);
+            }
+            let (distance, _) = decode_offset_varint(&bytes)?;
+            if distance == 0 || distance > offset {
+                return Err(invalid_data(format!("bad delta base offset at offset {}", offset)));
+            }
+            EntryKind::OfsDelta(offset - distance)
+        },
+        OBJ_REF_DELTA => {
+            let mut base = [0u8; HASH_SIZE];
+            file.read_exact(&mut base)?;
+            EntryKind::RefDelta(Hash(base))
+        },
+        _ => return Err(invalid_data(format!("unknown object type {} at offset {}", type_id, offset))),
+    };
+    Ok(EntryHeader { kind, size })
+}
+
+fn inflate<R: Read>(reader: R, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader).take(size).read_to_end(&mut data)?;
+    if data.len() as u64 != size {
+        return Err(invalid_data("packed object is shorter than its header says"));
+    }
+    Ok(data)
+}
+
+// objects/pack にある .idx と対応する .pack を読み込む
+pub fn find_packs(objects_dir: &Path) -> io::Result<Vec<Pack>> {
+    let pack_dir = objects_dir.join("pack");
+    let mut idx_paths = Vec::new();
+    match fs::read_dir(&pack_dir) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").is_file() {
+                    idx_paths.push(path);
+                }
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e),
+    }
+    idx_paths.sort();
+    // git と同様に、壊れた pack は警告して使わないだけにし、ほかのオブジェクトは読めるようにする
+    let packs = idx_paths.iter().filter_map(|path| match Pack::open(path) {
+        Ok(pack) => Some(pack),
+        Err(e) => {
+            eprintln!("warning: ignoring unreadable pack index {}: {}", path.display(), e);
+            None
+        },
+    });
+    Ok(packs.collect())
+}
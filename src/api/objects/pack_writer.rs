@@ -0,0 +1,257 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{File, Permissions};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use flate2::{Compression, Crc, CrcWriter};
+use flate2::write::ZlibEncoder;
+use sha1::{Digest, Sha1};
+
+use crate::api::common::varint::encode_offset_varint;
+use crate::api::repository::Repository;
+
+use super::base::ObjectType;
+use super::delta::DeltaIndex;
+use super::io::{Hash, ObjectReader, TempFile};
+use super::pack::{IDX_V2_SIGNATURE, OBJ_OFS_DELTA, PACK_SIGNATURE, type_id};
+
+// これより小さいオブジェクトはデルタにしても得にならない
+const MIN_DELTA_SIZE: usize = 50;
+
+// core.bigFileThreshold の既定値
+pub const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 * 1024 * 1024;
+
+pub struct PackOptions {
+    // デルタのベースの候補として直前のいくつのオブジェクトを見るか
+    pub window: usize,
+    // デルタの連鎖の最大の長さ
+    pub depth: usize,
+    // これより大きいオブジェクトはデルタを探さず、メモリに載せずにそのまま書き込む
+    pub big_file_threshold: u64,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self { window: 10, depth: 50, big_file_threshold: DEFAULT_BIG_FILE_THRESHOLD }
+    }
+}
+
+// pack に含めるオブジェクト。path はデルタのベースを選ぶときの並び替えに使う
+pub struct PackObject {
+    pub hash: Hash,
+    pub path: Option<String>,
+}
+
+// 末尾のファイル名が似ているものが近くに並ぶよう、後ろの文字ほど重みを大きくする (git の pack_name_hash)
+fn name_hash(path: &str) -> u32 {
+    let mut hash = 0u32;
+    for c in path.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        hash = (hash >> 2).wrapping_add((c as u32) << 24);
+    }
+    hash
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+    written: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.hasher.update(&buf[..size]);
+        self.written += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct WrittenEntry {
+    hash: Hash,
+    offset: u64,
+    crc32: u32,
+}
+
+struct WindowEntry {
+    obj_type: ObjectType,
+    data: Vec<u8>,
+    index: DeltaIndex,
+    depth: usize,
+    offset: u64,
+}
+
+// 種類とサイズのヘッダ (OFS_DELTA ならベースまでの距離も)
+fn encode_entry_header(type_id: u8, mut size: u64, base_distance: Option<u64>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut c = (type_id << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size != 0 {
+        out.push(c | 0x80);
+        c = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(c);
+    if let Some(distance) = base_distance {
+        out.extend(encode_offset_varint(distance));
+    }
+    out
+}
+
+// ヘッダに続けて、zlib で圧縮した内容を並べる
+fn encode_entry(type_id: u8, data: &[u8], base_distance: Option<u64>) -> io::Result<Vec<u8>> {
+    let out = encode_entry_header(type_id, data.len() as u64, base_distance);
+    let mut encoder = ZlibEncoder::new(out, Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn encode_index(entries: &mut [WrittenEntry], pack_checksum: &Hash) -> Vec<u8> {
+    entries.sort_by_key(|entry| entry.hash);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(IDX_V2_SIGNATURE);
+    out.extend_from_slice(&2u32.to_be_bytes());
+    for first in 0..=0xffu8 {
+        let count = entries.iter().take_while(|entry| entry.hash.as_bytes()[0] <= first).count();
+        out.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for entry in entries.iter() {
+        out.extend_from_slice(entry.hash.as_bytes());
+    }
+    for entry in entries.iter() {
+        out.extend_from_slice(&entry.crc32.to_be_bytes());
+    }
+    // 31bit に収まらないオフセットは 64bit の表に置き、その位置を最上位ビット付きで書く
+    let mut large_offsets = Vec::new();
+    for entry in entries.iter() {
+        if entry.offset < 0x8000_0000 {
+            out.extend_from_slice(&(entry.offset as u32).to_be_bytes());
+        } else {
+            out.extend_from_slice(&(0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(entry.offset);
+        }
+    }
+    for offset in large_offsets {
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+    out.extend_from_slice(pack_checksum.as_bytes());
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+    out
+}
+
+fn persist_read_only(temp_file: TempFile, file: File, path: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    file.set_permissions(Permissions::from_mode(0o444))?;
+    drop(file);
+    // 同じ内容の pack が既にあればそれを使う
+    if path.is_file() {
+        return Ok(());
+    }
+    temp_file.persist(path)
+}
+
+// 大きなオブジェクトを、本体を読み込まずに圧縮しながら書き込み、書いた内容の CRC32 を返す
+fn write_entry_stream<W, R>(writer: &mut W, type_id: u8, size: u64, reader: R) -> io::Result<u32> where W: Write, R: Read {
+    let mut crc_writer = CrcWriter::new(writer);
+    crc_writer.write_all(&encode_entry_header(type_id, size, None))?;
+    let mut encoder = ZlibEncoder::new(&mut crc_writer, Compression::default());
+    let copied = io::copy(&mut reader.take(size), &mut encoder)?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("expected {} bytes but read only {}", size, copied),
+        ));
+    }
+    encoder.finish()?;
+    Ok(crc_writer.crc().sum())
+}
+
+// objects を <base_name>-<checksum>.pack と .idx に書き出し、pack のチェックサムを返す
+// 型・名前・サイズの順に並べ、直前の window 個のオブジェクトをデルタのベースの候補にする
+pub fn write_pack(repo: &Repository, objects: &[PackObject], options: &PackOptions, base_name: &Path) -> io::Result<Hash> {
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    for object in objects.iter().filter(|object| seen.insert(object.hash)) {
+        let reader = ObjectReader::new(repo, &object.hash.to_string())?;
+        let hash = object.path.as_deref().map_or(0, name_hash);
+        items.push((type_id(reader.object_type()), hash, reader.size(), object.hash));
+    }
+    // 大きいものを先に置き、小さいものを大きいものからのデルタにする
+    items.sort_by_key(|&(type_id, hash, size, _)| (type_id, hash, std::cmp::Reverse(size)));
+
+    let pack_dir = base_name.parent().unwrap_or_else(|| Path::new("."));
+    let (pack_temp, file) = TempFile::create(pack_dir, "tmp_pack_")?;
+    let mut writer = HashingWriter { inner: BufWriter::new(file), hasher: Sha1::new(), written: 0 };
+    writer.write_all(PACK_SIGNATURE)?;
+    writer.write_all(&2u32.to_be_bytes())?;
+    writer.write_all(&(items.len() as u32).to_be_bytes())?;
+
+    let mut entries = Vec::with_capacity(items.len());
+    let mut window: VecDeque<WindowEntry> = VecDeque::new();
+    for (type_id, _, size, hash) in items {
+        let mut reader = ObjectReader::new(repo, &hash.to_string())?;
+        let obj_type = reader.object_type();
+
+        if size > options.big_file_threshold {
+            let offset = writer.written;
+            let crc32 = write_entry_stream(&mut writer, type_id, size, reader)?;
+            entries.push(WrittenEntry { hash, offset, crc32 });
+            continue;
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        reader.read_to_end(&mut data)?;
+
+        // 元の半分以下にならないデルタは使わない
+        let mut best: Option<(&WindowEntry, Vec<u8>)> = None;
+        if data.len() >= MIN_DELTA_SIZE {
+            let mut max_size = data.len() / 2 - 20;
+            for base in window.iter().rev() {
+                if base.obj_type != obj_type || base.depth >= options.depth || base.data.len() < data.len() / 32 {
+                    continue;
+                }
+                if let Some(delta) = base.index.create_delta(&base.data, &data, max_size) {
+                    max_size = delta.len() - 1;
+                    best = Some((base, delta));
+                }
+            }
+        }
+
+        let offset = writer.written;
+        let (encoded, depth) = match best {
+            Some((base, delta)) => (encode_entry(OBJ_OFS_DELTA, &delta, Some(offset - base.offset))?, base.depth + 1),
+            None => (encode_entry(type_id, &data, None)?, 0),
+        };
+        let mut crc = Crc::new();
+        crc.update(&encoded);
+        writer.write_all(&encoded)?;
+        entries.push(WrittenEntry { hash, offset, crc32: crc.sum() });
+
+        if options.window > 0 {
+            if window.len() == options.window {
+                window.pop_front();
+            }
+            let index = DeltaIndex::new(&data);
+            window.push_back(WindowEntry { obj_type, data, index, depth, offset });
+        }
+    }
+
+    let checksum = Hash::from_bytes(writer.hasher.finalize().as_slice())?;
+    let mut inner = writer.inner;
+    inner.write_all(checksum.as_bytes())?;
+    let file = inner.into_inner().map_err(|e| e.into_error())?;
+
+    let (idx_temp, mut idx_file) = TempFile::create(pack_dir, "tmp_idx_")?;
+    idx_file.write_all(&encode_index(&mut entries, &checksum))?;
+
+    // .idx が見えた時点で .pack が完全であるよう、.pack を先に置く
+    let base_name = base_name.to_string_lossy();
+    persist_read_only(pack_temp, file, Path::new(&format!("{}-{}.pack", base_name, checksum)))?;
+    persist_read_only(idx_temp, idx_file, Path::new(&format!("{}-{}.idx", base_name, checksum)))?;
+
+    Ok(checksum)
+}
@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+
+use crate::api::common::datetime::Timestamp;
+use crate::api::common::error::invalid_data;
+use crate::api::common::user::User;
+
+use super::base::{ObjectBase, ObjectType, serialized_body_size};
+use super::commit::split_headers;
+use super::io::Hash;
+
+const SIGNATURE_HEADERS: [&str; 3] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN PGP MESSAGE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+];
+
+pub struct TagObject {
+    pub object: Hash,
+    pub object_type: ObjectType,
+    pub tag: String,
+    pub tagger: Option<(User, Timestamp)>,
+    pub message: String,
+    // メッセージの後ろに付けられた署名 ("-----BEGIN ... SIGNATURE-----" 以降)
+    pub signature: Option<String>,
+}
+
+// メッセージ中の行頭から始まる最初の署名ブロックの位置を探す
+fn find_signature(message: &str) -> Option<usize> {
+    let mut pos = 0;
+    for line in message.split_inclusive('\n') {
+        if SIGNATURE_HEADERS.iter().any(|header| line.trim_end() == *header) {
+            return Some(pos);
+        }
+        pos += line.len();
+    }
+    None
+}
+
+impl TagObject {
+    pub fn parse(body: &[u8]) -> io::Result<Self> {
+        let (headers, message) = split_headers(body)?;
+        let message = String::from_utf8_lossy(message);
+
+        let mut object = None;
+        let mut object_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" if object.is_none() => object = Some(value.parse()?),
+                "type" if object_type.is_none() => object_type = Some(value.parse()?),
+                "tag" if tag.is_none() => tag = Some(value),
+                "tagger" if tagger.is_none() => tagger = Some(User::parse_with_timestamp(&value)?),
+                "object" | "type" | "tag" | "tagger" => {
+                    return Err(invalid_data(format!("duplicate {} header in tag", key)));
+                },
+                _ => {},
+            }
+        }
+
+        let signature_start = find_signature(&message).unwrap_or(message.len());
+
+        Ok(Self {
+            object: object.ok_or_else(|| invalid_data("tag has no object header"))?,
+            object_type: object_type.ok_or_else(|| invalid_data("tag has no type header"))?,
+            tag: tag.ok_or_else(|| invalid_data("tag has no tag header"))?,
+            tagger,
+            message: message[..signature_start].to_string(),
+            signature: if signature_start < message.len() { Some(message[signature_start..].to_string()) } else { None },
+        })
+    }
+}
+
+impl ObjectBase for TagObject {
+    fn obj_type(&self) -> &str {
+        "tag"
+    }
+
+    fn body_size(&self) -> usize {
+        serialized_body_size(self)
+    }
+
+    fn write_body_to<W>(&self, writer: &mut W) -> std::io::Result<()> where W: Write {
+        writeln!(writer, "object {}", &self.object)?;
+        writeln!(writer, "type {}", &self.object_type)?;
+        writeln!(writer, "tag {}", &self.tag)?;
+        if let Some((user, timestamp)) = &self.tagger {
+            writeln!(writer, "tagger {} {}", user, timestamp)?;
+        }
+        writeln!(writer)?;
+        write!(writer, "{}", self.message)?;
+        if let Some(signature) = &self.signature {
+            write!(writer, "{}", signature)?;
+        }
+        Ok(())
+    }
+}
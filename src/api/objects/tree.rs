@@ -1,20 +1,60 @@
+use std::cmp::Ordering;
 use std::fmt::Display;
-use std::io::Write;
+use std::io::{self, Write};
 
-use super::base::ObjectBase;
+use crate::api::common::error::invalid_data;
+
+use super::base::{ObjectBase, ObjectType};
 use super::io::{HASH_SIZE, Hash};
 
-const MODE_LEN: usize = 6;
+const MAX_MODE_LEN: usize = 6;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Mode(pub u32);
 
+pub const MODE_FILE: Mode = Mode(0o100644);
+pub const MODE_EXECUTABLE: Mode = Mode(0o100755);
+pub const MODE_SYMLINK: Mode = Mode(0o120000);
+pub const MODE_GITLINK: Mode = Mode(0o160000);
+pub const MODE_TREE: Mode = Mode(0o40000);
+
+impl Mode {
+    pub fn parse(s: &[u8]) -> io::Result<Self> {
+        if s.is_empty() || s.len() > MAX_MODE_LEN || !s.iter().all(|b| (b'0'..=b'7').contains(b)) {
+            return Err(invalid_data(format!("invalid mode: {:?}", String::from_utf8_lossy(s))));
+        }
+        let mode = s.iter().fold(0, |acc, b| acc * 8 + (b - b'0') as u32);
+        Ok(Mode(mode))
+    }
+
+    pub fn is_tree(&self) -> bool {
+        *self == MODE_TREE
+    }
+
+    // エントリが指すオブジェクトの種類 (サブモジュールはコミットを指す)
+    pub fn object_type(&self) -> ObjectType {
+        match *self {
+            MODE_TREE => ObjectType::Tree,
+            MODE_GITLINK => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+}
+
+// git はツリーのモードを先頭の 0 なしで書く ("40000")
 impl Display for Mode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s= format!("{:06o}", self.0); // TODO: もし可能であれば6をMODE_LEN定数で埋め込む
-        write!(f, "{}", &s[(s.len() - MODE_LEN)..])
+        write!(f, "{:o}", self.0)
     }
 }
 
+// git のツリーエントリの並び順: 名前をバイト列として比較するが、ディレクトリは末尾に '/' があるものとして扱う
+pub fn compare_entry_names(name1: &[u8], is_tree1: bool, name2: &[u8], is_tree2: bool) -> Ordering {
+    let suffix = |is_tree| if is_tree { &b"/"[..] } else { &b""[..] };
+    name1.iter().chain(suffix(is_tree1))
+        .cmp(name2.iter().chain(suffix(is_tree2)))
+}
+
 pub struct TreeEntry {
     pub mode: Mode,
     pub name: String,
@@ -24,16 +64,21 @@ pub struct TreeEntry {
 impl TreeEntry {
     pub fn write_to<T>(&self, writer: &mut T) -> std::io::Result<()> where T: Write {
         write!(writer, "{} {}\0", self.mode, self.name)?;
-        writer.write(self.hash.as_bytes())?;
+        writer.write_all(self.hash.as_bytes())?;
 
         Ok(())
     }
 
     pub fn size(&self) -> usize {
-        MODE_LEN + 1 + self.name.len() + 1 + HASH_SIZE
+        self.mode.to_string().len() + 1 + self.name.len() + 1 + HASH_SIZE
+    }
+
+    fn cmp_order(&self, other: &TreeEntry) -> Ordering {
+        compare_entry_names(self.name.as_bytes(), self.mode.is_tree(), other.name.as_bytes(), other.mode.is_tree())
     }
 }
 
+#[derive(Default)]
 pub struct TreeObject {
     entries: Vec<TreeEntry>,
 }
@@ -45,8 +90,45 @@ impl TreeObject {
         }
     }
 
+    // git と同じ順序になるように挿入する (同名のエントリは置き換える)
     pub fn add(&mut self, entry: TreeEntry) {
-        self.entries.push(entry);
+        match self.entries.binary_search_by(|e| e.cmp_order(&entry)) {
+            Ok(pos) => self.entries[pos] = entry,
+            Err(pos) => self.entries.insert(pos, entry),
+        }
+    }
+
+    pub fn entries(&self) -> &[TreeEntry] {
+        &self.entries
+    }
+
+    pub fn parse(body: &[u8]) -> io::Result<Self> {
+        let mut tree = Self::new();
+        let mut rest = body;
+
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&b| b == b' ')
+                .ok_or_else(|| invalid_data("malformed tree entry: missing mode"))?;
+            let mode = Mode::parse(&rest[..space])?;
+            rest = &rest[space + 1..];
+
+            let nul = rest.iter().position(|&b| b == 0)
+                .ok_or_else(|| invalid_data("malformed tree entry: missing name"))?;
+            let name = String::from_utf8(rest[..nul].to_vec())
+                .map_err(|_| invalid_data("tree entry name is not valid UTF-8"))?;
+            rest = &rest[nul + 1..];
+
+            if rest.len() < HASH_SIZE {
+                return Err(invalid_data("malformed tree entry: truncated hash"));
+            }
+            let hash = Hash::from_bytes(&rest[..HASH_SIZE])?;
+            rest = &rest[HASH_SIZE..];
+
+            // 既存のツリーの順序を検証できるよう、パース時は並べ替えない
+            tree.entries.push(TreeEntry { mode, name, hash });
+        }
+
+        Ok(tree)
     }
 }
 
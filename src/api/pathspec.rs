@@ -0,0 +1,46 @@
+use std::io;
+use std::path::Path;
+
+use super::common::wildmatch::wildmatch;
+use super::repository::Repository;
+
+// コマンドラインで渡されたパス (カレントディレクトリ基準) をワークツリー基準に直したもの
+pub struct Pathspec {
+    items: Vec<String>,
+}
+
+fn has_glob(item: &str) -> bool {
+    item.contains(['*', '?', '['])
+}
+
+impl Pathspec {
+    pub fn parse(repo: &Repository, args: &[String]) -> io::Result<Self> {
+        let items = args.iter()
+            .map(|arg| repo.to_repo_path(Path::new(arg)))
+            .collect::<io::Result<Vec<String>>>()?;
+        Ok(Self { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn matches_item(item: &str, path: &str) -> bool {
+        if item.is_empty() || path == item {
+            return true;
+        }
+        if path.len() > item.len() && path.starts_with(item) && path.as_bytes()[item.len()] == b'/' {
+            return true;
+        }
+        has_glob(item) && wildmatch(item, path, false)
+    }
+
+    // 空の pathspec は全てのパスにマッチする
+    pub fn matches(&self, path: &str) -> bool {
+        self.items.is_empty() || self.items.iter().any(|item| Self::matches_item(item, path))
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::io;
+
+use super::index::Index;
+use super::objects::base::ObjectType;
+use super::objects::io::{Hash, ObjectReader};
+use super::objects::object::Object;
+use super::objects::tree::MODE_GITLINK;
+use super::reflog::{list_reflogs, read_reflog};
+use super::refs::{list_refs, resolve_ref};
+use super::repository::Repository;
+
+// 到達可能なオブジェクトと、それが見つかったパス (ツリーをたどった場合のみ)
+pub struct ReachableObject {
+    pub hash: Hash,
+    pub obj_type: ObjectType,
+    pub path: Option<String>,
+}
+
+// HEAD・すべての ref・reflog の各エントリが指すオブジェクトを、どこから指されているかの名前とともに返す
+// 他のワークツリーについては、そのワークツリーだけが持つ HEAD・ref・reflog も含める
+pub fn named_root_objects(repo: &Repository) -> io::Result<Vec<(String, Hash)>> {
+    let mut roots = Vec::new();
+    for (i, worktree) in repo.worktrees()?.iter().enumerate() {
+        let own = |name: &str| i == 0 || worktree.ref_dir(name) == worktree.git_dir();
+        let prefix = worktree_prefix(i, worktree);
+        if let Some(hash) = resolve_ref(worktree, "HEAD")? {
+            roots.push((format!("{}HEAD", prefix), hash));
+        }
+        for r in list_refs(worktree, "refs/")? {
+            if own(&r.name) {
+                roots.push((format!("{}{}", prefix, r.name), r.hash));
+            }
+        }
+        for refname in list_reflogs(worktree)? {
+            if !own(&refname) {
+                continue;
+            }
+            for (n, log) in read_reflog(worktree, &refname)?.iter().rev().enumerate() {
+                for hash in [log.prev_hash, log.hash].iter().filter(|hash| !hash.is_zero()) {
+                    roots.push((format!("{}{}@{{{}}}", prefix, refname, n), *hash));
+                }
+            }
+        }
+    }
+    Ok(roots)
+}
+
+pub fn root_objects(repo: &Repository) -> io::Result<Vec<Hash>> {
+    Ok(named_root_objects(repo)?.into_iter().map(|(_, hash)| hash).collect())
+}
+
+// 他のワークツリーの ref は git と同様に "main-worktree/HEAD" や "worktrees/<名前>/HEAD" と表す
+fn worktree_prefix(i: usize, worktree: &Repository) -> String {
+    if i == 0 {
+        String::new()
+    } else if worktree.git_dir() == worktree.common_dir() {
+        "main-worktree/".to_string()
+    } else {
+        let name = worktree.git_dir().file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        format!("worktrees/{}/", name)
+    }
+}
+
+// 各ワークツリーの index に登録されている blob (サブモジュールと intent-to-add は除く) をパスとともに返す
+pub fn index_blobs(repo: &Repository) -> io::Result<Vec<(String, Hash)>> {
+    let mut blobs = Vec::new();
+    for worktree in repo.worktrees()? {
+        for entry in Index::read(&worktree)?.entries() {
+            if entry.mode != MODE_GITLINK && !entry.intent_to_add {
+                blobs.push((entry.path.clone(), entry.hash));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+// roots からコミットの親・ツリー・タグの参照先をたどる。サブモジュールのコミットはたどらない
+pub fn walk_objects(repo: &Repository, roots: &[Hash]) -> io::Result<Vec<ReachableObject>> {
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    // (ハッシュ, パス, blob であることが分かっているか)
+    let mut stack: Vec<(Hash, Option<String>, bool)> = roots.iter().rev().map(|&hash| (hash, None, false)).collect();
+
+    while let Some((hash, path, is_blob)) = stack.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        // blob は中身を読む必要がない
+        if is_blob {
+            objects.push(ReachableObject { hash, obj_type: ObjectType::Blob, path });
+            continue;
+        }
+
+        let object = ObjectReader::read_object(repo, &hash.to_string())
+            .map_err(|e| io::Error::new(e.kind(), format!("unable to read {}: {}", hash, e)))?;
+        match &object {
+            Object::Blob(_) => {},
+            Object::Tree(tree) => {
+                for entry in tree.entries().iter().rev() {
+                    if entry.mode == MODE_GITLINK {
+                        continue;
+                    }
+                    let entry_path = match &path {
+                        Some(path) if !path.is_empty() => format!("{}/{}", path, entry.name),
+                        _ => entry.name.clone(),
+                    };
+                    stack.push((entry.hash, Some(entry_path), !entry.mode.is_tree()));
+                }
+            },
+            Object::Commit(commit) => {
+                for parent in commit.parents.iter().rev() {
+                    stack.push((*parent, None, false));
+                }
+                stack.push((commit.tree_hash, Some(String::new()), false));
+            },
+            Object::Tag(tag) => stack.push((tag.object, None, false)),
+        }
+        objects.push(ReachableObject { hash, obj_type: object.object_type(), path });
+    }
+    Ok(objects)
+}
+
+// ref や reflog から到達できるものと、各ワークツリーの index に登録されている blob
+pub fn reachable_objects(repo: &Repository) -> io::Result<Vec<ReachableObject>> {
+    let mut objects = walk_objects(repo, &root_objects(repo)?)?;
+    let mut seen: HashSet<Hash> = objects.iter().map(|object| object.hash).collect();
+    for (path, hash) in index_blobs(repo)? {
+        if seen.insert(hash) {
+            objects.push(ReachableObject { hash, obj_type: ObjectType::Blob, path: Some(path) });
+        }
+    }
+    Ok(objects)
+}
@@ -0,0 +1,720 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io;
+
+use super::common::datetime::{DateFormat, Timestamp};
+use super::common::error::invalid_data;
+use super::common::user::User;
+use super::common::wildmatch::wildmatch;
+use super::config::Config;
+use super::objects::base::ObjectType;
+use super::objects::io::{Hash, ObjectReader};
+use super::objects::object::Object;
+use super::refs::{Ref, RefTarget, list_refs, read_ref, resolve_ref, resolve_symbolic, shorten_refname};
+use super::repository::Repository;
+use super::revision::{DEFAULT_ABBREV, ahead_behind, branch_upstream, commit_ancestors, find_unique_abbrev, is_ancestor, peel_tags};
+
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+// ref 名の表示のしかた (":short"、":lstrip=<n>"、":rstrip=<n>")
+#[derive(Clone, Copy)]
+enum NameFormat {
+    Full,
+    Short,
+    Lstrip(i32),
+    Rstrip(i32),
+}
+
+#[derive(Clone, Copy)]
+enum UpstreamFormat {
+    Name(NameFormat),
+    Track { brackets: bool },
+    TrackShort,
+}
+
+#[derive(Clone, Copy)]
+enum Role {
+    Author,
+    Committer,
+    Tagger,
+    // コミットならコミッタ、タグならタガー
+    Creator,
+}
+
+#[derive(Clone)]
+enum PersonPart {
+    Full,
+    Name,
+    Email { trim: bool },
+    Date(DateFormat),
+}
+
+#[derive(Clone, Copy)]
+enum ContentsPart {
+    All,
+    Subject,
+    Body,
+    Signature,
+}
+
+#[derive(Clone)]
+enum Atom {
+    RefName(NameFormat),
+    Upstream(UpstreamFormat),
+    ObjectName(Option<usize>),
+    ObjectType,
+    ObjectSize,
+    Tree,
+    Parent,
+    NumParent,
+    // タグの "object"・"type"・"tag" ヘッダ
+    TagObject,
+    TagType,
+    TagName,
+    Person(Role, PersonPart),
+    Contents(ContentsPart),
+    Head,
+    Symref(NameFormat),
+}
+
+// 先頭の '*' はタグが指すオブジェクトの値を表す
+#[derive(Clone)]
+struct AtomSpec {
+    deref: bool,
+    atom: Atom,
+}
+
+enum Condition {
+    NonEmpty,
+    Equals(String),
+    NotEquals(String),
+}
+
+enum Node {
+    Literal(String),
+    Atom(AtomSpec),
+    If {
+        condition: Condition,
+        test: Vec<Node>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+fn parse_name_format(name: &str, modifier: Option<&str>) -> io::Result<NameFormat> {
+    let strip = |value: &str| value.parse::<i32>().map_err(|_| invalid_data(format!("expected an integer in %({}:{})", name, value)));
+    match modifier {
+        None => Ok(NameFormat::Full),
+        Some("short") => Ok(NameFormat::Short),
+        Some(modifier) => {
+            if let Some(n) = modifier.strip_prefix("lstrip=").or_else(|| modifier.strip_prefix("strip=")) {
+                Ok(NameFormat::Lstrip(strip(n)?))
+            } else if let Some(n) = modifier.strip_prefix("rstrip=") {
+                Ok(NameFormat::Rstrip(strip(n)?))
+            } else {
+                Err(invalid_data(format!("unrecognized %({}) argument: {}", name, modifier)))
+            }
+        },
+    }
+}
+
+fn parse_person(role: Role, part: &str, modifier: Option<&str>) -> io::Result<Atom> {
+    let part = match (part, modifier) {
+        ("", None) => PersonPart::Full,
+        ("name", None) => PersonPart::Name,
+        ("email", None) => PersonPart::Email { trim: false },
+        ("email", Some("trim")) => PersonPart::Email { trim: true },
+        ("date", None) => PersonPart::Date(DateFormat::Default),
+        ("date", Some(format)) => PersonPart::Date(format.parse()?),
+        _ => return Err(invalid_data(format!("unknown field name: {}{}", part, modifier.map_or(String::new(), |m| format!(":{}", m))))),
+    };
+    Ok(Atom::Person(role, part))
+}
+
+fn parse_atom(spec: &str) -> io::Result<AtomSpec> {
+    let (deref, spec) = match spec.strip_prefix('*') {
+        Some(spec) => (true, spec),
+        None => (false, spec),
+    };
+    let (name, modifier) = match spec.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (spec, None),
+    };
+    let unknown = || invalid_data(format!("unknown field name: {}", spec));
+    let no_modifier = |atom: Atom| if modifier.is_none() { Ok(atom) } else { Err(unknown()) };
+
+    let atom = match name {
+        "refname" => Atom::RefName(parse_name_format(name, modifier)?),
+        "symref" => Atom::Symref(parse_name_format(name, modifier)?),
+        "upstream" => Atom::Upstream(match modifier {
+            Some("track") => UpstreamFormat::Track { brackets: true },
+            Some("track,nobracket") => UpstreamFormat::Track { brackets: false },
+            Some("trackshort") => UpstreamFormat::TrackShort,
+            _ => UpstreamFormat::Name(parse_name_format(name, modifier)?),
+        }),
+        "objectname" => match modifier {
+            None => Atom::ObjectName(None),
+            Some("short") => Atom::ObjectName(Some(DEFAULT_ABBREV)),
+            Some(modifier) => match modifier.strip_prefix("short=").and_then(|n| n.parse().ok()) {
+                Some(len) => Atom::ObjectName(Some(len)),
+                None => return Err(unknown()),
+            },
+        },
+        "objecttype" => no_modifier(Atom::ObjectType)?,
+        "objectsize" => no_modifier(Atom::ObjectSize)?,
+        "tree" => no_modifier(Atom::Tree)?,
+        "parent" => no_modifier(Atom::Parent)?,
+        "numparent" => no_modifier(Atom::NumParent)?,
+        "object" => no_modifier(Atom::TagObject)?,
+        "type" => no_modifier(Atom::TagType)?,
+        "tag" => no_modifier(Atom::TagName)?,
+        "HEAD" => no_modifier(Atom::Head)?,
+        "subject" => no_modifier(Atom::Contents(ContentsPart::Subject))?,
+        "body" => no_modifier(Atom::Contents(ContentsPart::Body))?,
+        "contents" => Atom::Contents(match modifier {
+            None => ContentsPart::All,
+            Some("subject") => ContentsPart::Subject,
+            Some("body") => ContentsPart::Body,
+            Some("signature") => ContentsPart::Signature,
+            Some(_) => return Err(unknown()),
+        }),
+        _ => {
+            let roles = [("author", Role::Author), ("committer", Role::Committer), ("tagger", Role::Tagger), ("creator", Role::Creator)];
+            match roles.iter().find(|(prefix, _)| name.starts_with(prefix)) {
+                Some((prefix, role)) => parse_person(*role, &name[prefix.len()..], modifier)?,
+                None => return Err(unknown()),
+            }
+        },
+    };
+    Ok(AtomSpec { deref, atom })
+}
+
+// "%(if)...%(then)...%(else)...%(end)" を入れ子の構造にしながら書式を読む
+// end_markers のいずれかの %(...) に行き当たったらその名前を返す
+fn parse_nodes<'a>(rest: &mut &'a str, end_markers: &[&str]) -> io::Result<(Vec<Node>, Option<&'a str>)> {
+    let mut nodes = Vec::new();
+    let mut literal = String::new();
+    while let Some(c) = rest.chars().next() {
+        if c != '%' {
+            literal.push(c);
+            *rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("%%") {
+            literal.push('%');
+            *rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("%(") {
+            let end = after.find(')').ok_or_else(|| invalid_data(format!("malformed format string {}", rest)))?;
+            let spec = &after[..end];
+            *rest = &after[end + 1..];
+            let name = spec.split(':').next().unwrap_or("");
+            if end_markers.contains(&name) {
+                if !literal.is_empty() {
+                    nodes.push(Node::Literal(literal));
+                }
+                return Ok((nodes, Some(spec)));
+            }
+            if !literal.is_empty() {
+                nodes.push(Node::Literal(std::mem::take(&mut literal)));
+            }
+            match name {
+                "if" => nodes.push(parse_if(rest, spec)?),
+                "then" | "else" | "end" => return Err(invalid_data(format!("format: %({}) atom used without an %(if) atom", name))),
+                // 色の指定は無視する
+                "color" => {},
+                _ => nodes.push(Node::Atom(parse_atom(spec)?)),
+            }
+            continue;
+        }
+        // "%xx" は 16 進数で表した 1 バイト
+        match rest.get(1..3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                literal.push(byte as char);
+                *rest = &rest[3..];
+            },
+            None => {
+                literal.push('%');
+                *rest = &rest[1..];
+            },
+        }
+    }
+    if !literal.is_empty() {
+        nodes.push(Node::Literal(literal));
+    }
+    Ok((nodes, None))
+}
+
+fn parse_if(rest: &mut &str, spec: &str) -> io::Result<Node> {
+    let condition = match spec.strip_prefix("if").unwrap_or("") {
+        "" => Condition::NonEmpty,
+        modifier => match modifier.strip_prefix(":equals=") {
+            Some(value) => Condition::Equals(value.to_string()),
+            None => match modifier.strip_prefix(":notequals=") {
+                Some(value) => Condition::NotEquals(value.to_string()),
+                None => return Err(invalid_data(format!("unrecognized %(if) argument: {}", modifier))),
+            },
+        },
+    };
+    let (test, marker) = parse_nodes(rest, &["then"])?;
+    if marker.is_none() {
+        return Err(invalid_data("format: %(if) atom used without a %(then) atom"));
+    }
+    let (then, marker) = parse_nodes(rest, &["else", "end"])?;
+    let otherwise = match marker {
+        Some("else") => match parse_nodes(rest, &["end"])? {
+            (otherwise, Some(_)) => otherwise,
+            (_, None) => return Err(invalid_data("format: %(end) atom missing")),
+        },
+        Some(_) => Vec::new(),
+        None => return Err(invalid_data("format: %(end) atom missing")),
+    };
+    Ok(Node::If { condition, test, then, otherwise })
+}
+
+// --format に渡された書式
+pub struct RefFormat {
+    nodes: Vec<Node>,
+}
+
+impl RefFormat {
+    pub fn parse(format: &str) -> io::Result<Self> {
+        let mut rest = format;
+        let (nodes, _) = parse_nodes(&mut rest, &[])?;
+        Ok(Self { nodes })
+    }
+}
+
+// --sort のキー。"-" で始まれば降順、"version:" (または "v:") ならバージョン番号として比較する
+pub struct SortKey {
+    atom: AtomSpec,
+    reverse: bool,
+    version: bool,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> io::Result<Self> {
+        let (reverse, key) = match key.strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, key),
+        };
+        let (version, key) = match key.strip_prefix("version:").or_else(|| key.strip_prefix("v:")) {
+            Some(key) => (true, key),
+            None => (false, key),
+        };
+        Ok(Self { atom: parse_atom(key)?, reverse, version })
+    }
+}
+
+// ref の絞り込みの条件 (各リストはいずれかに当てはまればよい)
+#[derive(Default)]
+pub struct RefFilter {
+    pub patterns: Vec<String>,
+    pub points_at: Vec<Hash>,
+    pub merged: Vec<Hash>,
+    pub no_merged: Vec<Hash>,
+    pub contains: Vec<Hash>,
+    pub no_contains: Vec<Hash>,
+}
+
+// パターンはそのものか、"/" 区切りの前方一致か、glob で ref 名に一致する
+fn matches_pattern(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| {
+        let prefix = pattern.trim_end_matches('/');
+        name == pattern
+            || (name.len() > prefix.len() && name.starts_with(prefix) && name.as_bytes()[prefix.len()] == b'/')
+            || wildmatch(pattern, name, true)
+    })
+}
+
+fn peel_to_commit(repo: &Repository, hash: &Hash) -> io::Result<Option<Hash>> {
+    let target = peel_tags(repo, *hash)?;
+    let is_commit = ObjectReader::new(repo, &target.to_string())?.object_type() == ObjectType::Commit;
+    Ok(if is_commit { Some(target) } else { None })
+}
+
+pub fn filter_refs(repo: &Repository, filter: &RefFilter) -> io::Result<Vec<Ref>> {
+    let merged = commit_ancestors(repo, &filter.merged)?;
+    let no_merged = commit_ancestors(repo, &filter.no_merged)?;
+    let needs_commit = !(filter.merged.is_empty() && filter.no_merged.is_empty() && filter.contains.is_empty() && filter.no_contains.is_empty());
+
+    let mut refs = Vec::new();
+    for r in list_refs(repo, "refs/")? {
+        if !matches_pattern(&filter.patterns, &r.name) {
+            continue;
+        }
+        if !filter.points_at.is_empty() {
+            let peeled = match r.peeled {
+                Some(peeled) => peeled,
+                None => peel_tags(repo, r.hash)?,
+            };
+            if !filter.points_at.iter().any(|hash| *hash == r.hash || *hash == peeled) {
+                continue;
+            }
+        }
+        if needs_commit {
+            // コミットを指さない ref は除く
+            let commit = match peel_to_commit(repo, &r.hash)? {
+                Some(commit) => commit,
+                None => continue,
+            };
+            if !filter.merged.is_empty() && !merged.contains(&commit) {
+                continue;
+            }
+            if no_merged.contains(&commit) {
+                continue;
+            }
+            let mut contains = filter.contains.is_empty();
+            for hash in filter.contains.iter() {
+                if is_ancestor(repo, hash, &commit)? {
+                    contains = true;
+                    break;
+                }
+            }
+            if !contains {
+                continue;
+            }
+            let mut excluded = false;
+            for hash in filter.no_contains.iter() {
+                if is_ancestor(repo, hash, &commit)? {
+                    excluded = true;
+                    break;
+                }
+            }
+            if excluded {
+                continue;
+            }
+        }
+        refs.push(r);
+    }
+    Ok(refs)
+}
+
+// 書式の値を求めるのに必要なオブジェクトの情報 (コミットとタグは中身も読む)
+struct ObjectInfo {
+    hash: Hash,
+    obj_type: ObjectType,
+    size: u64,
+    object: Option<Object>,
+}
+
+impl ObjectInfo {
+    fn read(repo: &Repository, hash: &Hash) -> io::Result<Self> {
+        let reader = ObjectReader::new(repo, &hash.to_string())?;
+        let (obj_type, size) = (reader.object_type(), reader.size());
+        let object = match obj_type {
+            ObjectType::Commit | ObjectType::Tag => Some(ObjectReader::read_object(repo, &hash.to_string())?),
+            _ => None,
+        };
+        Ok(Self { hash: *hash, obj_type, size, object })
+    }
+
+    fn person(&self, role: Role) -> Option<(&User, &Timestamp)> {
+        match (&self.object, role) {
+            (Some(Object::Commit(commit)), Role::Author) => Some((&commit.author, &commit.author_timestamp)),
+            (Some(Object::Commit(commit)), Role::Committer) | (Some(Object::Commit(commit)), Role::Creator) => {
+                Some((&commit.committer, &commit.commit_timestamp))
+            },
+            (Some(Object::Tag(tag)), Role::Tagger) | (Some(Object::Tag(tag)), Role::Creator) => {
+                tag.tagger.as_ref().map(|(user, timestamp)| (user, timestamp))
+            },
+            _ => None,
+        }
+    }
+
+    // (メッセージ, 署名)
+    fn message(&self) -> (Cow<'_, str>, &str) {
+        match &self.object {
+            Some(Object::Commit(commit)) => (commit.message_lossy(), ""),
+            Some(Object::Tag(tag)) => (Cow::from(tag.message.as_str()), tag.signature.as_deref().unwrap_or("")),
+            _ => (Cow::from(""), ""),
+        }
+    }
+}
+
+// メッセージを最初の段落 (件名) とそれ以降 (本文) に分ける。件名の改行は空白にする
+fn split_message(message: &str) -> (String, &str) {
+    let message = message.trim_start_matches('\n');
+    let (subject, body) = match message.find("\n\n") {
+        Some(pos) => (&message[..pos], message[pos..].trim_start_matches('\n')),
+        None => (message.trim_end_matches('\n'), ""),
+    };
+    (subject.split('\n').collect::<Vec<_>>().join(" "), body)
+}
+
+fn strip_components(name: &str, format: NameFormat) -> String {
+    let components: Vec<&str> = name.split('/').collect();
+    let len = components.len() as i32;
+    // 負の値は残す要素の数を表す
+    let (start, end) = match format {
+        NameFormat::Lstrip(n) if n >= 0 => (n.min(len), len),
+        NameFormat::Lstrip(n) => ((len + n).max(0), len),
+        NameFormat::Rstrip(n) if n >= 0 => (0, (len - n).max(0)),
+        NameFormat::Rstrip(n) => (0, (-n).min(len)),
+        _ => (0, len),
+    };
+    components[start as usize..end as usize].join("/")
+}
+
+fn format_name(name: &str, format: NameFormat) -> String {
+    match format {
+        NameFormat::Full => name.to_string(),
+        NameFormat::Short => shorten_refname(name).to_string(),
+        _ => strip_components(name, format),
+    }
+}
+
+// 並べ替えに使う値 (日付やサイズは数値として比較する)
+enum Value {
+    Text(String),
+    Number(i64),
+}
+
+impl Value {
+    fn into_string(self) -> String {
+        match self {
+            Value::Text(text) => text,
+            Value::Number(n) => n.to_string(),
+        }
+    }
+}
+
+// 1 つの ref について書式の値を求める
+pub struct RefContext<'a> {
+    repo: &'a Repository,
+    config: &'a Config,
+    head: Option<String>,
+    item: &'a Ref,
+    object: ObjectInfo,
+    peeled: Option<ObjectInfo>,
+}
+
+impl<'a> RefContext<'a> {
+    pub fn new(repo: &'a Repository, config: &'a Config, item: &'a Ref) -> io::Result<Self> {
+        let object = ObjectInfo::read(repo, &item.hash)?;
+        let peeled = match object.obj_type {
+            ObjectType::Tag => match item.peeled {
+                Some(peeled) => Some(peeled),
+                None => Some(peel_tags(repo, item.hash)?),
+            },
+            _ => None,
+        };
+        let peeled = peeled.map(|hash| ObjectInfo::read(repo, &hash)).transpose()?;
+        let head = match resolve_symbolic(repo, "HEAD")? {
+            (name, _) if name != "HEAD" => Some(name),
+            _ => None,
+        };
+        Ok(Self { repo, config, head, item, object, peeled })
+    }
+
+    fn upstream(&self, format: UpstreamFormat) -> io::Result<String> {
+        let upstream = match self.item.name.strip_prefix("refs/heads/").and_then(|branch| branch_upstream(self.config, branch)) {
+            Some(upstream) => upstream,
+            None => return Ok(String::new()),
+        };
+        let (ahead, behind) = match format {
+            UpstreamFormat::Name(format) => return Ok(format_name(&upstream, format)),
+            _ => match resolve_ref(self.repo, &upstream)? {
+                Some(hash) => ahead_behind(self.repo, &self.item.hash, &hash)?,
+                None => {
+                    return Ok(match format {
+                        UpstreamFormat::Track { brackets: true } => "[gone]".to_string(),
+                        UpstreamFormat::Track { brackets: false } => "gone".to_string(),
+                        _ => String::new(),
+                    });
+                },
+            },
+        };
+        Ok(match format {
+            UpstreamFormat::TrackShort => match (ahead, behind) {
+                (0, 0) => "=",
+                (_, 0) => ">",
+                (0, _) => "<",
+                _ => "<>",
+            }.to_string(),
+            UpstreamFormat::Track { brackets } => {
+                let track = match (ahead, behind) {
+                    (0, 0) => return Ok(String::new()),
+                    (ahead, 0) => format!("ahead {}", ahead),
+                    (0, behind) => format!("behind {}", behind),
+                    (ahead, behind) => format!("ahead {}, behind {}", ahead, behind),
+                };
+                if brackets { format!("[{}]", track) } else { track }
+            },
+            UpstreamFormat::Name(_) => unreachable!(),
+        })
+    }
+
+    fn value(&self, spec: &AtomSpec) -> io::Result<Value> {
+        let text = |s: String| Ok(Value::Text(s));
+        match &spec.atom {
+            Atom::RefName(format) => return text(format_name(&self.item.name, *format)),
+            Atom::Upstream(format) => return text(self.upstream(*format)?),
+            Atom::Head => return text(if self.head.as_deref() == Some(self.item.name.as_str()) { "*" } else { " " }.to_string()),
+            Atom::Symref(format) => {
+                return text(match read_ref(self.repo, &self.item.name)? {
+                    Some(RefTarget::Symbolic(target)) => format_name(&target, *format),
+                    _ => String::new(),
+                });
+            },
+            _ => {},
+        }
+
+        let object = match (spec.deref, &self.peeled) {
+            (false, _) => &self.object,
+            (true, Some(peeled)) => peeled,
+            (true, None) => return text(String::new()),
+        };
+        match &spec.atom {
+            Atom::ObjectName(None) => text(object.hash.to_string()),
+            Atom::ObjectName(Some(len)) => text(find_unique_abbrev(self.repo, &object.hash, *len)?),
+            Atom::ObjectType => text(object.obj_type.to_string()),
+            Atom::ObjectSize => Ok(Value::Number(object.size as i64)),
+            Atom::Tree => text(match &object.object {
+                Some(Object::Commit(commit)) => commit.tree_hash.to_string(),
+                _ => String::new(),
+            }),
+            Atom::Parent | Atom::NumParent => {
+                let parents = match &object.object {
+                    Some(Object::Commit(commit)) => &commit.parents,
+                    _ => return text(String::new()),
+                };
+                if let Atom::NumParent = spec.atom {
+                    return Ok(Value::Number(parents.len() as i64));
+                }
+                text(parents.iter().map(|parent| parent.to_string()).collect::<Vec<_>>().join(" "))
+            },
+            Atom::TagObject | Atom::TagType | Atom::TagName => text(match &object.object {
+                Some(Object::Tag(tag)) => match spec.atom {
+                    Atom::TagObject => tag.object.to_string(),
+                    Atom::TagType => tag.object_type.to_string(),
+                    _ => tag.tag.clone(),
+                },
+                _ => String::new(),
+            }),
+            Atom::Person(role, part) => {
+                let (user, timestamp) = match object.person(*role) {
+                    Some(person) => person,
+                    None => return text(String::new()),
+                };
+                match part {
+                    PersonPart::Full => text(format!("{} {}", user, timestamp)),
+                    PersonPart::Name => text(user.name.clone()),
+                    PersonPart::Email { trim: false } => text(format!("<{}>", user.email)),
+                    PersonPart::Email { trim: true } => text(user.email.clone()),
+                    PersonPart::Date(format) => text(timestamp.format(format)?),
+                }
+            },
+            Atom::Contents(part) => {
+                let (message, signature) = object.message();
+                let (subject, body) = split_message(&message);
+                text(match part {
+                    ContentsPart::All => format!("{}{}", message, signature),
+                    ContentsPart::Subject => subject,
+                    ContentsPart::Body => body.to_string(),
+                    ContentsPart::Signature => signature.to_string(),
+                })
+            },
+            _ => text(String::new()),
+        }
+    }
+
+    // 日付は書式によらず時刻で比較する
+    fn sort_value(&self, spec: &AtomSpec) -> io::Result<Value> {
+        if let Atom::Person(role, PersonPart::Date(_)) = &spec.atom {
+            let object = if spec.deref { self.peeled.as_ref() } else { Some(&self.object) };
+            let epoch = object.and_then(|object| object.person(*role)).map_or(0, |(_, timestamp)| timestamp.epoch());
+            return Ok(Value::Number(epoch));
+        }
+        self.value(spec)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], out: &mut String) -> io::Result<()> {
+        for node in nodes {
+            match node {
+                Node::Literal(literal) => out.push_str(literal),
+                Node::Atom(spec) => out.push_str(&self.value(spec)?.into_string()),
+                Node::If { condition, test, then, otherwise } => {
+                    let mut value = String::new();
+                    self.render_nodes(test, &mut value)?;
+                    // 空白だけの文字列は空とみなす
+                    let satisfied = match condition {
+                        Condition::NonEmpty => !value.trim().is_empty(),
+                        Condition::Equals(expected) => value == *expected,
+                        Condition::NotEquals(expected) => value != *expected,
+                    };
+                    self.render_nodes(if satisfied { then } else { otherwise }, out)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn format(&self, format: &RefFormat) -> io::Result<String> {
+        let mut out = String::new();
+        self.render_nodes(&format.nodes, &mut out)?;
+        Ok(out)
+    }
+}
+
+// 数字の並びを数値として比較する ("v1.10" > "v1.9")
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_num, b_num) = (&a[..a_len], &b[..b_len]);
+                let a_trimmed = &a_num[a_num.iter().take_while(|&&c| c == b'0').count()..];
+                let b_trimmed = &b_num[b_num.iter().take_while(|&&c| c == b'0').count()..];
+                let ordering = a_trimmed.len().cmp(&b_trimmed.len()).then_with(|| a_trimmed.cmp(b_trimmed));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            },
+            (Some(x), Some(y)) if x != y => return x.cmp(y),
+            _ => {
+                a = &a[1..];
+                b = &b[1..];
+            },
+        }
+    }
+}
+
+fn compare_values(a: &Value, b: &Value, version: bool) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.cmp(b),
+        (Value::Text(a), Value::Text(b)) if version => compare_versions(a, b),
+        (Value::Text(a), Value::Text(b)) => a.cmp(b),
+        (Value::Number(_), Value::Text(_)) => Ordering::Less,
+        (Value::Text(_), Value::Number(_)) => Ordering::Greater,
+    }
+}
+
+// keys は優先度の高い順。どのキーでも差がなければ ref 名で比べる
+pub fn sort_refs(contexts: &mut Vec<RefContext>, keys: &[SortKey]) -> io::Result<()> {
+    let mut keyed = Vec::with_capacity(contexts.len());
+    for context in contexts.drain(..) {
+        let values = keys.iter().map(|key| context.sort_value(&key.atom)).collect::<io::Result<Vec<Value>>>()?;
+        keyed.push((values, context));
+    }
+    keyed.sort_by(|(a_values, a), (b_values, b)| {
+        for ((key, a_value), b_value) in keys.iter().zip(a_values).zip(b_values) {
+            let ordering = compare_values(a_value, b_value, key.version);
+            let ordering = if key.reverse { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        a.item.name.cmp(&b.item.name)
+    });
+    contexts.extend(keyed.into_iter().map(|(_, context)| context));
+    Ok(())
+}
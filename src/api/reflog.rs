@@ -1,51 +1,411 @@
 
-use std::fs::{OpenOptions};
-use std::io::{BufWriter, Write};
+use std::fs::{self, OpenOptions, create_dir_all};
+use std::io::{self, BufWriter, Write};
 use std::fmt;
+use std::path::{Path, PathBuf};
 
+use super::common::datetime::{DateFormat, Timestamp, parse_approxidate};
+use super::common::error::invalid_data;
+use super::common::user::User;
+use super::lockfile::LockFile;
+use super::objects::io::Hash;
+use super::refs::{expand_ref_name, shorten_refname};
+use super::repository::Repository;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RebaseAction {
+    Start,
+    Finish,
+    Pick,
+    Other(String),
+}
+
+impl fmt::Display for RebaseAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RebaseAction::Start => write!(f, "start"),
+            RebaseAction::Finish => write!(f, "finish"),
+            RebaseAction::Pick => write!(f, "pick"),
+            RebaseAction::Other(action) => write!(f, "{}", action),
+        }
+    }
+}
+
+impl RebaseAction {
+    fn parse(action: &str) -> Self {
+        match action {
+            "start" => RebaseAction::Start,
+            "finish" => RebaseAction::Finish,
+            "pick" => RebaseAction::Pick,
+            _ => RebaseAction::Other(action.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum RefLogKind {
-    COMMIT,
-    // CHECKOUT,
+    Commit,
+    CommitInitial,
+    CommitAmend,
+    CommitMerge,
+    Checkout { from: String, to: String },
+    Reset { to: String },
+    Merge { branch: String },
+    Rebase(RebaseAction),
+    CherryPick,
+    Branch { from: String },
+    Pull,
+    Clone { from: String },
+    // 解釈できないメッセージはそのまま保持する
+    Other(String),
 }
 
 impl fmt::Display for RefLogKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            RefLogKind::COMMIT => write!(f, "commit"),
-            // RefLogKind::CHECKOUT => write!(f, "checkout"),
+        match self {
+            RefLogKind::Commit => write!(f, "commit"),
+            RefLogKind::CommitInitial => write!(f, "commit (initial)"),
+            RefLogKind::CommitAmend => write!(f, "commit (amend)"),
+            RefLogKind::CommitMerge => write!(f, "commit (merge)"),
+            RefLogKind::Checkout { .. } => write!(f, "checkout"),
+            RefLogKind::Reset { .. } => write!(f, "reset"),
+            RefLogKind::Merge { branch } => write!(f, "merge {}", branch),
+            RefLogKind::Rebase(action) => write!(f, "rebase ({})", action),
+            RefLogKind::CherryPick => write!(f, "cherry-pick"),
+            RefLogKind::Branch { .. } => write!(f, "branch"),
+            RefLogKind::Pull => write!(f, "pull"),
+            RefLogKind::Clone { .. } => write!(f, "clone"),
+            RefLogKind::Other(message) => write!(f, "{}", message),
         }
     }
 }
 
+impl RefLogKind {
+    // reflog のメッセージ部分を組み立てる
+    pub fn format_message(&self, description: &str) -> String {
+        match self {
+            RefLogKind::Checkout { from, to } => format!("checkout: moving from {} to {}", from, to),
+            RefLogKind::Reset { to } => format!("reset: moving to {}", to),
+            RefLogKind::Branch { from } => format!("branch: Created from {}", from),
+            RefLogKind::Clone { from } => format!("clone: from {}", from),
+            RefLogKind::Other(message) => message.clone(),
+            _ => format!("{}: {}", self, description),
+        }
+    }
+
+    // format_message の逆変換。種類と説明文に分解する
+    pub fn parse(message: &str) -> (Self, String) {
+        let other = || (RefLogKind::Other(message.to_string()), String::new());
+        let (label, description) = match message.split_once(": ") {
+            Some(pair) => pair,
+            None => return other(),
+        };
+        let with_description = |kind| (kind, description.to_string());
+
+        match label {
+            "commit" => with_description(RefLogKind::Commit),
+            "commit (initial)" => with_description(RefLogKind::CommitInitial),
+            "commit (amend)" => with_description(RefLogKind::CommitAmend),
+            "commit (merge)" => with_description(RefLogKind::CommitMerge),
+            "cherry-pick" => with_description(RefLogKind::CherryPick),
+            "pull" => with_description(RefLogKind::Pull),
+            "checkout" => match description.strip_prefix("moving from ").and_then(|rest| rest.split_once(" to ")) {
+                Some((from, to)) if !from.contains(' ') && !to.contains(' ') => {
+                    (RefLogKind::Checkout { from: from.to_string(), to: to.to_string() }, String::new())
+                },
+                _ => other(),
+            },
+            "reset" => match description.strip_prefix("moving to ") {
+                Some(to) => (RefLogKind::Reset { to: to.to_string() }, String::new()),
+                None => other(),
+            },
+            "branch" => match description.strip_prefix("Created from ") {
+                Some(from) => (RefLogKind::Branch { from: from.to_string() }, String::new()),
+                None => other(),
+            },
+            "clone" => match description.strip_prefix("from ") {
+                Some(from) => (RefLogKind::Clone { from: from.to_string() }, String::new()),
+                None => other(),
+            },
+            _ => {
+                if let Some(branch) = label.strip_prefix("merge ") {
+                    with_description(RefLogKind::Merge { branch: branch.to_string() })
+                } else if let Some(action) = label.strip_prefix("rebase (").and_then(|rest| rest.strip_suffix(')')) {
+                    with_description(RefLogKind::Rebase(RebaseAction::parse(action)))
+                } else {
+                    other()
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct RefLog {
-    pub prev_hash: String,
-    pub hash: String,
-    pub author: String,
-    pub email: String,
-    pub timestamp: u64,
-    pub timezone: i16,
+    pub prev_hash: Hash,
+    pub hash: Hash,
+    pub committer: User,
+    pub timestamp: Timestamp,
+    pub kind: RefLogKind,
+    pub description: String,
+}
+
+impl RefLog {
+    pub fn message(&self) -> String {
+        self.kind.format_message(&self.description)
+    }
+
+    // "<old> <new> <name> <<email>> <epoch> <tz>\t<message>" の形式の行をパースする
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let (prev_hash, rest) = header.split_once(' ')
+            .ok_or_else(|| invalid_data(format!("malformed reflog line: {:?}", line)))?;
+        let (hash, identity) = rest.split_once(' ')
+            .ok_or_else(|| invalid_data(format!("malformed reflog line: {:?}", line)))?;
+        let (committer, timestamp) = User::parse_with_timestamp(identity)?;
+
+        let (kind, description) = RefLogKind::parse(message);
+
+        Ok(Self {
+            prev_hash: prev_hash.parse()?,
+            hash: hash.parse()?,
+            committer,
+            timestamp,
+            kind,
+            description,
+        })
+    }
+}
+
+impl fmt::Display for RefLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}\t{}",
+            self.prev_hash,
+            self.hash,
+            self.committer,
+            self.timestamp,
+            self.message(),
+        )
+    }
+}
+
+// ref の更新時に記録する内容 (更新前後のハッシュは refs::update_ref が埋める)
+pub struct RefLogMessage {
+    pub committer: User,
+    pub timestamp: Timestamp,
     pub kind: RefLogKind,
     pub description: String,
 }
 
-pub fn append_reflog(target: &str, log: RefLog) {
+impl RefLogMessage {
+    pub fn to_reflog(&self, prev_hash: &Hash, hash: &Hash) -> RefLog {
+        RefLog {
+            prev_hash: *prev_hash,
+            hash: *hash,
+            committer: self.committer.clone(),
+            timestamp: self.timestamp,
+            kind: self.kind.clone(),
+            description: self.description.clone(),
+        }
+    }
+}
+
+pub fn reflog_path(repo: &Repository, refname: &str) -> PathBuf {
+    repo.ref_dir(refname).join("logs").join(refname)
+}
+
+pub fn reflog_exists(repo: &Repository, refname: &str) -> bool {
+    reflog_path(repo, refname).is_file()
+}
+
+// 短い ref 名を、reflog が存在する完全な ref 名に展開する
+pub fn dwim_log(repo: &Repository, name: &str) -> Option<String> {
+    expand_ref_name(name).into_iter().find(|candidate| reflog_exists(repo, candidate))
+}
+
+fn collect_reflogs(logs_dir: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_reflogs(logs_dir, &path, names)?;
+        } else if let Some(name) = path.strip_prefix(logs_dir).ok().and_then(|p| p.to_str()) {
+            if !name.ends_with(".lock") {
+                names.push(name.replace(std::path::MAIN_SEPARATOR, "/"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// reflog を持つ全ての ref 名を返す
+pub fn list_reflogs(repo: &Repository) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut dirs = vec![repo.common_dir()];
+    if repo.git_dir() != repo.common_dir() {
+        dirs.push(repo.git_dir());
+    }
+    for dir in dirs {
+        let logs_dir = dir.join("logs");
+        let mut found = Vec::new();
+        if logs_dir.is_dir() {
+            collect_reflogs(&logs_dir, &logs_dir, &mut found)?;
+        }
+        // ワークツリーごとの reflog はそのワークツリーの git ディレクトリにあるものだけを見る
+        names.extend(found.into_iter().filter(|name| repo.ref_dir(name) == dir));
+    }
+    names.sort();
+    Ok(names)
+}
+
+pub fn append_reflog(repo: &Repository, refname: &str, log: RefLog) -> io::Result<()> {
+    let target = reflog_path(repo, refname);
+    if let Some(dir) = target.parent() {
+        create_dir_all(dir)?;
+    }
     let mut f = BufWriter::new(
         OpenOptions::new()
-            .read(true)
-            .write(true)
+            .append(true)
             .create(true)
-            .open(target).unwrap()
+            .open(target)?
         );
-    writeln!(
-        &mut f,
-        "{} {} {} <{}> {} {}    {}: {}",
-        log.prev_hash,
-        log.hash,
-        log.author,
-        log.email,
-        log.timestamp,
-        log.timezone,
-        log.kind,
-        log.description
-    ).unwrap();
+    writeln!(&mut f, "{}", log)?;
+    f.flush()
+}
+
+// 古いものから順に返す
+// git と同じく、解釈できない行は読み飛ばし、UTF-8 でないメッセージは置換文字に置き換えて読む
+pub fn read_reflog(repo: &Repository, refname: &str) -> io::Result<Vec<RefLog>> {
+    let content = match fs::read(reflog_path(repo, refname)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| RefLog::parse(&String::from_utf8_lossy(line)).ok())
+        .collect())
+}
+
+// ref と reflog をロックした上で reflog 全体を書き換える
+pub fn rewrite_reflog(repo: &Repository, refname: &str, logs: &[RefLog]) -> io::Result<()> {
+    let _ref_lock = LockFile::acquire(&repo.ref_dir(refname).join(refname))?;
+    let mut log_lock = LockFile::acquire(&reflog_path(repo, refname))?;
+    for log in logs {
+        writeln!(log_lock, "{}", log)?;
+    }
+    log_lock.commit()
+}
+
+// expire より古いエントリを削除し、削除した件数を返す
+pub fn expire_reflog(repo: &Repository, refname: &str, expire: i64) -> io::Result<usize> {
+    let logs = read_reflog(repo, refname)?;
+    let total = logs.len();
+    let kept: Vec<RefLog> = logs.into_iter().filter(|log| log.timestamp.epoch() >= expire).collect();
+    if kept.len() == total {
+        return Ok(0);
+    }
+    rewrite_reflog(repo, refname, &kept)?;
+    Ok(total - kept.len())
+}
+
+// ref@{n} に相当するエントリを削除する
+pub fn delete_reflog_entry(repo: &Repository, refname: &str, n: usize) -> io::Result<()> {
+    let mut logs = read_reflog(repo, refname)?;
+    if n >= logs.len() {
+        return Err(invalid_data(format!("reflog for '{}' has only {} entries", refname, logs.len())));
+    }
+    let index = logs.len() - 1 - n;
+    logs.remove(index);
+    rewrite_reflog(repo, refname, &logs)
+}
+
+// "@{n}" や "@{date}" の中身 (selector) から ref の過去の値を求める
+pub fn lookup_reflog(repo: &Repository, refname: &str, selector: &str) -> io::Result<Hash> {
+    let logs = read_reflog(repo, refname)?;
+    if logs.is_empty() {
+        return Err(invalid_data(format!("log for '{}' is empty", refname)));
+    }
+
+    if !selector.is_empty() && selector.bytes().all(|b| b.is_ascii_digit()) {
+        let n: usize = selector.parse().map_err(|_| invalid_data(format!("invalid reflog index: {}", selector)))?;
+        return match logs.len().checked_sub(n + 1) {
+            Some(index) => Ok(logs[index].hash),
+            None => Err(invalid_data(format!("log for '{}' only has {} entries", refname, logs.len()))),
+        };
+    }
+
+    let date = parse_approxidate(selector, Timestamp::now().epoch())?;
+    if let Some(log) = logs.iter().rev().find(|log| log.timestamp.epoch() <= date) {
+        return Ok(log.hash);
+    }
+    // 最も古いエントリより前の日付。ref がそのエントリで作られた場合は作られた時点の値を使う
+    let oldest = &logs[0];
+    eprintln!("warning: log for '{}' only goes back to {}", shorten_refname(refname), oldest.timestamp.format(&DateFormat::Rfc)?);
+    Ok(if oldest.prev_hash.is_zero() { oldest.hash } else { oldest.prev_hash })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(message: &str, kind: RefLogKind, description: &str) {
+        let (parsed_kind, parsed_description) = RefLogKind::parse(message);
+        assert_eq!(parsed_kind, kind, "{:?}", message);
+        assert_eq!(parsed_description, description, "{:?}", message);
+        assert_eq!(parsed_kind.format_message(&parsed_description), message);
+    }
+
+    #[test]
+    fn commit_messages() {
+        assert_round_trip("commit: add file", RefLogKind::Commit, "add file");
+        assert_round_trip("commit (initial): first", RefLogKind::CommitInitial, "first");
+        assert_round_trip("commit (amend): fix typo", RefLogKind::CommitAmend, "fix typo");
+        assert_round_trip("commit (merge): Merge branch 'topic'", RefLogKind::CommitMerge, "Merge branch 'topic'");
+    }
+
+    #[test]
+    fn checkout_and_reset() {
+        let checkout = RefLogKind::Checkout { from: "a".to_string(), to: "b".to_string() };
+        assert_round_trip("checkout: moving from a to b", checkout, "");
+        assert_round_trip("reset: moving to HEAD~1", RefLogKind::Reset { to: "HEAD~1".to_string() }, "");
+    }
+
+    #[test]
+    fn merge_and_rebase() {
+        assert_round_trip("merge x: Fast-forward", RefLogKind::Merge { branch: "x".to_string() }, "Fast-forward");
+        assert_round_trip("rebase (start): checkout main", RefLogKind::Rebase(RebaseAction::Start), "checkout main");
+        assert_round_trip("rebase (finish): returning to refs/heads/topic", RefLogKind::Rebase(RebaseAction::Finish), "returning to refs/heads/topic");
+        assert_round_trip("rebase (pick): add file", RefLogKind::Rebase(RebaseAction::Pick), "add file");
+        assert_round_trip("rebase (reword): new message", RefLogKind::Rebase(RebaseAction::Other("reword".to_string())), "new message");
+        assert_round_trip("cherry-pick: add file", RefLogKind::CherryPick, "add file");
+    }
+
+    #[test]
+    fn branch_pull_and_clone() {
+        assert_round_trip("branch: Created from HEAD", RefLogKind::Branch { from: "HEAD".to_string() }, "");
+        assert_round_trip("pull: Fast-forward", RefLogKind::Pull, "Fast-forward");
+        assert_round_trip("clone: from https://example.com/repo.git", RefLogKind::Clone { from: "https://example.com/repo.git".to_string() }, "");
+    }
+
+    #[test]
+    fn unknown_messages_are_kept() {
+        for message in ["", "no separator", "update by push", "checkout: moving from a b to c", "reset: somewhere", "branch: renamed a to b"] {
+            assert_round_trip(message, RefLogKind::Other(message.to_string()), "");
+        }
+    }
+
+    #[test]
+    fn reflog_line_round_trip() {
+        let line = "0000000000000000000000000000000000000000 ce013625030ba8dba906f756967f9e9ca394464a A U Thor <author@example.com> 1700000000 +0900\tcommit (initial): hello";
+        let log = RefLog::parse(line).unwrap();
+        assert!(log.prev_hash.is_zero());
+        assert_eq!(log.hash.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert_eq!(log.kind, RefLogKind::CommitInitial);
+        assert_eq!(log.description, "hello");
+        assert_eq!(log.to_string(), line);
+    }
 }
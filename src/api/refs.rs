@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::common::error::invalid_data;
+use super::lockfile::LockFile;
+use super::objects::io::Hash;
+use super::reflog::{RefLogMessage, append_reflog, reflog_exists, reflog_path};
+use super::repository::Repository;
+use super::revision::peel_tags;
+
+const SYMREF_PREFIX: &str = "ref: ";
+const MAX_SYMREF_DEPTH: usize = 5;
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefTarget {
+    Direct(Hash),
+    Symbolic(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Ref {
+    pub name: String,
+    pub hash: Hash,
+    pub peeled: Option<Hash>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PackedRef {
+    pub hash: Hash,
+    pub peeled: Option<Hash>,
+}
+
+#[derive(Default)]
+pub struct PackedRefs {
+    pub refs: BTreeMap<String, PackedRef>,
+}
+
+impl PackedRefs {
+    pub fn parse(content: &str) -> io::Result<Self> {
+        let mut packed = Self::default();
+        let mut last: Option<String> = None;
+
+        for line in content.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            if let Some(peeled) = line.strip_prefix('^') {
+                let name = last.as_ref().ok_or_else(|| invalid_data("peeled line without ref in packed-refs"))?;
+                packed.refs.get_mut(name).unwrap().peeled = Some(peeled.parse()?);
+                continue;
+            }
+            let (hash, name) = line.split_once(' ')
+                .ok_or_else(|| invalid_data(format!("malformed packed-refs line: {:?}", line)))?;
+            packed.refs.insert(name.to_string(), PackedRef { hash: hash.parse()?, peeled: None });
+            last = Some(name.to_string());
+        }
+
+        Ok(packed)
+    }
+
+    pub fn read(repo: &Repository) -> io::Result<Self> {
+        match fs::read_to_string(repo.common_dir().join("packed-refs")) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+        writer.write_all(PACKED_REFS_HEADER.as_bytes())?;
+        for (name, packed_ref) in self.refs.iter() {
+            writeln!(writer, "{} {}", packed_ref.hash, name)?;
+            if let Some(peeled) = &packed_ref.peeled {
+                writeln!(writer, "^{}", peeled)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, repo: &Repository) -> io::Result<()> {
+        let mut lock = LockFile::acquire(&repo.common_dir().join("packed-refs"))?;
+        self.write_to(&mut lock)?;
+        lock.commit()
+    }
+}
+
+// git check-ref-format の規則のうち主要なものを検査する
+pub fn is_valid_refname(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.ends_with('/') || name.ends_with('.') || name.contains("@{") {
+        return false;
+    }
+    if name.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c)) {
+        return false;
+    }
+    name.split('/').all(|component| {
+        !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock") && !component.contains("..")
+    })
+}
+
+// 省略された ref 名を展開する際の探索順 (git rev-parse と同じ)
+const REF_SEARCH_RULES: [(&str, &str); 6] = [
+    ("", ""),
+    ("refs/", ""),
+    ("refs/tags/", ""),
+    ("refs/heads/", ""),
+    ("refs/remotes/", ""),
+    ("refs/remotes/", "/HEAD"),
+];
+
+pub fn expand_ref_name(name: &str) -> Vec<String> {
+    REF_SEARCH_RULES.iter().map(|(prefix, suffix)| format!("{}{}{}", prefix, name, suffix)).collect()
+}
+
+// "master" のような短い名前を探索順に従って完全な ref 名に展開する
+pub fn dwim_ref(repo: &Repository, name: &str) -> io::Result<Option<String>> {
+    for candidate in expand_ref_name(name) {
+        if (candidate == "HEAD" || is_valid_refname(&candidate)) && read_ref(repo, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+// refs/heads/ などを除いた短い名前
+pub fn shorten_refname(refname: &str) -> &str {
+    for prefix in &["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"] {
+        if let Some(name) = refname.strip_prefix(prefix) {
+            return name;
+        }
+    }
+    refname
+}
+
+fn ref_path(repo: &Repository, name: &str) -> PathBuf {
+    repo.ref_dir(name).join(name)
+}
+
+fn parse_loose_ref(content: &str) -> io::Result<RefTarget> {
+    let content = content.trim_end();
+    match content.strip_prefix(SYMREF_PREFIX) {
+        Some(target) => Ok(RefTarget::Symbolic(target.to_string())),
+        None => Ok(RefTarget::Direct(content.parse()?)),
+    }
+}
+
+fn read_loose_ref(repo: &Repository, name: &str) -> io::Result<Option<RefTarget>> {
+    let path = ref_path(repo, name);
+    if path.is_dir() {
+        return Ok(None);
+    }
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(parse_loose_ref(&content)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// シンボリック参照を辿らずに ref の中身を読む (loose を優先し、無ければ packed-refs)
+pub fn read_ref(repo: &Repository, name: &str) -> io::Result<Option<RefTarget>> {
+    if let Some(target) = read_loose_ref(repo, name)? {
+        return Ok(Some(target));
+    }
+    let packed = PackedRefs::read(repo)?;
+    Ok(packed.refs.get(name).map(|packed_ref| RefTarget::Direct(packed_ref.hash)))
+}
+
+// シンボリック参照を辿り、最終的な ref 名とその値を返す
+// 値が None の場合は ref が存在しない (HEAD が未作成のブランチを指している場合など)
+pub fn resolve_symbolic(repo: &Repository, name: &str) -> io::Result<(String, Option<Hash>)> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(repo, &name)? {
+            Some(RefTarget::Symbolic(target)) => name = target,
+            Some(RefTarget::Direct(hash)) => return Ok((name, Some(hash))),
+            None => return Ok((name, None)),
+        }
+    }
+    Err(invalid_data(format!("symbolic ref nesting too deep: {}", name)))
+}
+
+pub fn resolve_ref(repo: &Repository, name: &str) -> io::Result<Option<Hash>> {
+    Ok(resolve_symbolic(repo, name)?.1)
+}
+
+// base の下の dir 以下にある loose ref を集める (ref 名は base からの相対パス)
+fn collect_loose_refs(repo: &Repository, base: &Path, dir: &Path, refs: &mut BTreeMap<String, Ref>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_loose_refs(repo, base, &path, refs)?;
+            continue;
+        }
+        let name = match path.strip_prefix(base).ok().and_then(|p| p.to_str()) {
+            Some(name) => name.replace(std::path::MAIN_SEPARATOR, "/"),
+            None => continue,
+        };
+        // ワークツリーごとの ref はそのワークツリーの git ディレクトリにあるものだけを見る
+        if !is_valid_refname(&name) || repo.ref_dir(&name) != base {
+            continue;
+        }
+        if let Some(hash) = resolve_ref(repo, &name)? {
+            refs.insert(name.clone(), Ref { name, hash, peeled: None });
+        }
+    }
+    Ok(())
+}
+
+fn collect_all_loose_refs(repo: &Repository, refs: &mut BTreeMap<String, Ref>) -> io::Result<()> {
+    collect_loose_refs(repo, repo.common_dir(), &repo.common_dir().join("refs"), refs)?;
+    if repo.git_dir() != repo.common_dir() {
+        collect_loose_refs(repo, repo.git_dir(), &repo.git_dir().join("refs"), refs)?;
+    }
+    Ok(())
+}
+
+// prefix (例: "refs/heads/") 以下の ref を名前順に列挙する
+pub fn list_refs(repo: &Repository, prefix: &str) -> io::Result<Vec<Ref>> {
+    let mut refs = BTreeMap::new();
+
+    for (name, packed_ref) in PackedRefs::read(repo)?.refs {
+        if name.starts_with(prefix) {
+            refs.insert(name.clone(), Ref { name, hash: packed_ref.hash, peeled: packed_ref.peeled });
+        }
+    }
+
+    let mut loose = BTreeMap::new();
+    collect_all_loose_refs(repo, &mut loose)?;
+    for (name, loose_ref) in loose {
+        if name.starts_with(prefix) {
+            refs.insert(name, loose_ref);
+        }
+    }
+
+    Ok(refs.into_values().collect())
+}
+
+fn should_log(repo: &Repository, name: &str) -> bool {
+    if reflog_exists(repo, name) {
+        return true;
+    }
+    !repo.is_bare() && (
+        name == "HEAD" ||
+        name.starts_with("refs/heads/") ||
+        name.starts_with("refs/remotes/") ||
+        name.starts_with("refs/notes/")
+    )
+}
+
+fn write_reflogs(repo: &Repository, name: &str, old: &Hash, new: &Hash, log: &RefLogMessage) -> io::Result<()> {
+    if should_log(repo, name) {
+        append_reflog(repo, name, log.to_reflog(old, new))?;
+    }
+    if name != "HEAD" {
+        if let Some(RefTarget::Symbolic(head_target)) = read_ref(repo, "HEAD")? {
+            if head_target == name {
+                append_reflog(repo, "HEAD", log.to_reflog(old, new))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_old_value(name: &str, current: Option<Hash>, expected: Option<Hash>) -> io::Result<()> {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let matches = match current {
+        Some(current) => current == expected,
+        None => expected.is_zero(),
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "cannot lock ref '{}': is at {} but expected {}",
+            name,
+            current.unwrap_or_else(Hash::zero),
+            expected,
+        )))
+    }
+}
+
+// トランザクション内の 1 つの更新。new が None なら現在値の検証のみ、ゼロハッシュなら削除を表す
+struct RefUpdate {
+    name: String,
+    new: Option<Hash>,
+    old: Option<Hash>,
+}
+
+impl RefUpdate {
+    fn is_delete(&self) -> bool {
+        self.new.is_some_and(|new| new.is_zero())
+    }
+}
+
+// 複数の ref をまとめて更新する
+// prepare ですべての ref をロックして現在値を検証し、commit で書き込む (途中で失敗した場合は何も書き込まない)
+#[derive(Default)]
+pub struct RefTransaction {
+    updates: Vec<RefUpdate>,
+    // prepare 後は updates と同じ順に並ぶ
+    locks: Vec<LockFile>,
+    current: Vec<Option<Hash>>,
+    packed_lock: Option<LockFile>,
+    prepared: bool,
+}
+
+impl RefTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, name: &str, new: Option<Hash>, old: Option<Hash>) -> io::Result<()> {
+        if self.prepared {
+            return Err(io::Error::other("transaction is already prepared"));
+        }
+        if name != "HEAD" && !is_valid_refname(name) {
+            return Err(invalid_data(format!("invalid ref name: {}", name)));
+        }
+        self.updates.push(RefUpdate { name: name.to_string(), new, old });
+        Ok(())
+    }
+
+    // old が Some の場合は現在値と比較し、一致しなければ失敗する (ゼロハッシュは「まだ存在しないこと」を表す)
+    pub fn update(&mut self, name: &str, new: Hash, old: Option<Hash>) -> io::Result<()> {
+        self.add(name, Some(new), old)
+    }
+
+    pub fn create(&mut self, name: &str, new: Hash) -> io::Result<()> {
+        if new.is_zero() {
+            return Err(invalid_data(format!("create {}: zero <newvalue>", name)));
+        }
+        self.add(name, Some(new), Some(Hash::zero()))
+    }
+
+    pub fn delete(&mut self, name: &str, old: Option<Hash>) -> io::Result<()> {
+        self.add(name, Some(Hash::zero()), old)
+    }
+
+    pub fn verify(&mut self, name: &str, old: Hash) -> io::Result<()> {
+        self.add(name, None, Some(old))
+    }
+
+    pub fn prepare(&mut self, repo: &Repository) -> io::Result<()> {
+        if self.prepared {
+            return Ok(());
+        }
+        // シンボリック参照は参照先を更新する (削除の場合はシンボリック参照そのものを消す)
+        for update in self.updates.iter_mut() {
+            update.name = match read_loose_ref(repo, &update.name)? {
+                Some(RefTarget::Symbolic(_)) if update.is_delete() => update.name.clone(),
+                _ => resolve_symbolic(repo, &update.name)?.0,
+            };
+            if update.name != "HEAD" && !is_valid_refname(&update.name) {
+                return Err(invalid_data(format!("invalid ref name: {}", update.name)));
+            }
+        }
+
+        // 複数のプロセスが同じ順でロックするよう、名前順にロックを取る
+        let mut order: Vec<usize> = (0..self.updates.len()).collect();
+        order.sort_by(|&a, &b| self.updates[a].name.cmp(&self.updates[b].name));
+        for pair in order.windows(2) {
+            if self.updates[pair[0]].name == self.updates[pair[1]].name {
+                return Err(io::Error::other(format!("multiple updates for ref '{}' not allowed", self.updates[pair[0]].name)));
+            }
+        }
+        let mut locks: Vec<Option<LockFile>> = self.updates.iter().map(|_| None).collect();
+        for &i in order.iter() {
+            let path = ref_path(repo, &self.updates[i].name);
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir)?;
+            }
+            locks[i] = Some(LockFile::acquire(&path).map_err(|e| {
+                io::Error::new(e.kind(), format!("cannot lock ref '{}': {}", self.updates[i].name, e))
+            })?);
+        }
+
+        let mut current = Vec::with_capacity(self.updates.len());
+        let mut packed_deleted = false;
+        let packed = PackedRefs::read(repo)?;
+        for update in self.updates.iter() {
+            let value = match read_ref(repo, &update.name)? {
+                Some(RefTarget::Direct(hash)) => Some(hash),
+                _ => None,
+            };
+            check_old_value(&update.name, value, update.old)?;
+            packed_deleted |= update.is_delete() && packed.refs.contains_key(&update.name);
+            current.push(value);
+        }
+        if packed_deleted {
+            self.packed_lock = Some(LockFile::acquire(&repo.common_dir().join("packed-refs"))?);
+        }
+
+        self.locks = locks.into_iter().flatten().collect();
+        self.current = current;
+        self.prepared = true;
+        Ok(())
+    }
+
+    // log が None の場合は reflog を書かない
+    pub fn commit(mut self, repo: &Repository, log: Option<&RefLogMessage>) -> io::Result<()> {
+        self.prepare(repo)?;
+
+        if let Some(mut lock) = self.packed_lock.take() {
+            let mut packed = PackedRefs::read(repo)?;
+            for update in self.updates.iter().filter(|update| update.is_delete()) {
+                packed.refs.remove(&update.name);
+            }
+            packed.write_to(&mut lock)?;
+            lock.commit()?;
+        }
+
+        let updates = self.updates.iter().zip(self.locks.drain(..)).zip(self.current.iter());
+        for ((update, mut lock), current) in updates {
+            match update.new {
+                None => {
+                    lock.rollback();
+                    remove_empty_parents(repo, &update.name, &ref_path(repo, &update.name));
+                },
+                Some(new) if new.is_zero() => {
+                    let path = ref_path(repo, &update.name);
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => {},
+                    }
+                    lock.rollback();
+                    remove_empty_parents(repo, &update.name, &path);
+
+                    let log_path = reflog_path(repo, &update.name);
+                    if log_path.is_file() {
+                        fs::remove_file(&log_path)?;
+                    }
+                },
+                Some(new) => {
+                    writeln!(lock, "{}", new)?;
+                    lock.commit()?;
+                    if let Some(log) = log {
+                        write_reflogs(repo, &update.name, &current.unwrap_or_else(Hash::zero), &new, log)?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // ロックを解放して何も書き込まずに終える
+    pub fn abort(self) {}
+}
+
+// ref を new に更新する。old が Some の場合は現在値と比較し、一致しなければ失敗する
+// (old にゼロハッシュを渡すと「まだ存在しないこと」を要求する)
+// log が None の場合は reflog を書かない
+// name がシンボリック参照の場合は参照先を更新する
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    new: &Hash,
+    old: Option<Hash>,
+    log: Option<&RefLogMessage>,
+) -> io::Result<()> {
+    let mut transaction = RefTransaction::new();
+    transaction.update(name, *new, old)?;
+    transaction.commit(repo, log)
+}
+
+// log が Some の場合は、シンボリック参照が指す値の変化を reflog に記録する
+pub fn update_symbolic_ref(repo: &Repository, name: &str, target: &str, log: Option<&RefLogMessage>) -> io::Result<()> {
+    if name != "HEAD" && !is_valid_refname(name) {
+        return Err(invalid_data(format!("invalid ref name: {}", name)));
+    }
+    if !is_valid_refname(target) {
+        return Err(invalid_data(format!("invalid ref name: {}", target)));
+    }
+    let path = ref_path(repo, name);
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut lock = LockFile::acquire(&path)?;
+    let old = resolve_ref(repo, name)?;
+    writeln!(lock, "{}{}", SYMREF_PREFIX, target)?;
+    lock.commit()?;
+
+    if let (Some(log), Some(new)) = (log, resolve_ref(repo, target)?) {
+        append_reflog(repo, name, log.to_reflog(&old.unwrap_or_else(Hash::zero), &new))?;
+    }
+    Ok(())
+}
+
+fn remove_empty_parents(repo: &Repository, name: &str, path: &Path) {
+    let refs_dir = repo.ref_dir(name).join("refs");
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == refs_dir || !d.starts_with(&refs_dir) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+// シンボリック参照の場合は参照先ではなくシンボリック参照そのものを削除する
+pub fn delete_ref(repo: &Repository, name: &str, old: Option<Hash>) -> io::Result<()> {
+    let mut transaction = RefTransaction::new();
+    transaction.delete(name, old)?;
+    transaction.commit(repo, None)
+}
+
+// loose ref をすべて packed-refs にまとめて削除する (git pack-refs --all --prune)
+// シンボリック参照は loose のまま残す
+pub fn pack_refs(repo: &Repository) -> io::Result<()> {
+    let mut lock = LockFile::acquire(&repo.common_dir().join("packed-refs"))?;
+    let mut packed = PackedRefs::read(repo)?;
+
+    let mut loose = BTreeMap::new();
+    collect_all_loose_refs(repo, &mut loose)?;
+    let mut packed_names = Vec::new();
+    // refs/bisect/ などワークツリーごとの ref は共有の packed-refs に入れない
+    for name in loose.into_keys().filter(|name| !Repository::is_per_worktree_ref(name)) {
+        if let Some(RefTarget::Direct(hash)) = read_loose_ref(repo, &name)? {
+            packed.refs.insert(name.clone(), PackedRef { hash, peeled: None });
+            packed_names.push((name, hash));
+        }
+    }
+    // ヘッダで fully-peeled を宣言しているので、タグはすべて peel しておく
+    for packed_ref in packed.refs.values_mut() {
+        if packed_ref.peeled.is_none() {
+            packed_ref.peeled = peel_tags(repo, packed_ref.hash).ok().filter(|peeled| *peeled != packed_ref.hash);
+        }
+    }
+    packed.write_to(&mut lock)?;
+    lock.commit()?;
+
+    // まとめている間に更新された ref は消さない
+    for (name, hash) in packed_names {
+        let path = ref_path(repo, &name);
+        let lock = LockFile::acquire(&path)?;
+        if read_loose_ref(repo, &name)? == Some(RefTarget::Direct(hash)) {
+            fs::remove_file(&path)?;
+        }
+        lock.rollback();
+        remove_empty_parents(repo, &name, &path);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::fs::{create_dir_all, remove_file};
+use std::io;
+
+use super::objects::io::{Hash, list_loose_objects, remove_loose_object};
+use super::objects::pack_writer::{PackObject, PackOptions, write_pack};
+use super::reachable::reachable_objects;
+use super::repository::Repository;
+
+// 到達可能なオブジェクトを 1 つの pack にまとめ、作った pack のチェックサムを返す
+// all でなければ pack に入っていないものだけをまとめる。delete なら不要になった pack と loose オブジェクトを消す
+pub fn repack(repo: &Repository, all: bool, delete: bool, options: &PackOptions) -> io::Result<Option<Hash>> {
+    let old_packs = repo.packs()?;
+    let objects: Vec<PackObject> = reachable_objects(repo)?.into_iter()
+        .filter(|object| all || !old_packs.iter().any(|pack| pack.contains(&object.hash)))
+        .map(|object| PackObject { hash: object.hash, path: object.path })
+        .collect();
+    if objects.is_empty() {
+        return Ok(None);
+    }
+
+    let pack_dir = repo.objects_dir().join("pack");
+    create_dir_all(&pack_dir)?;
+    let checksum = write_pack(repo, &objects, options, &pack_dir.join("pack"))?;
+    repo.reload_packs();
+
+    if delete {
+        if all {
+            // .keep が置かれた pack は残す
+            for pack in old_packs.iter() {
+                let path = pack.pack_path();
+                if pack.index().pack_checksum() == checksum || path.with_extension("keep").exists() {
+                    continue;
+                }
+                for ext in ["idx", "rev", "bitmap"].iter() {
+                    let _ = remove_file(path.with_extension(ext));
+                }
+                remove_file(path)?;
+            }
+            repo.reload_packs();
+        }
+        prune_packed(repo)?;
+    }
+    Ok(Some(checksum))
+}
+
+// pack に含まれている loose オブジェクトを削除し、削除した数を返す
+pub fn prune_packed(repo: &Repository) -> io::Result<usize> {
+    let packs = repo.packs()?;
+    let mut count = 0;
+    for hash in list_loose_objects(repo)? {
+        if packs.iter().any(|pack| pack.contains(&hash)) {
+            remove_loose_object(repo, &hash)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
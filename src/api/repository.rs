@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, create_dir, create_dir_all, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use super::common::error::invalid_data;
+use super::objects::pack::{Pack, find_packs};
+
+// git_dir は HEAD や index などワークツリーごとのファイルの置き場所
+// common_dir はオブジェクトや ref など全ワークツリーで共有するものの置き場所 (git worktree add で作ったワークツリー以外では git_dir と同じ)
+pub struct Repository {
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    packs: RefCell<Option<Rc<Vec<Pack>>>>,
+}
+
+impl Repository {
+    pub fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Self {
+        let common_dir = common_dir(&git_dir);
+        Self { git_dir, common_dir, work_tree, packs: RefCell::new(None) }
+    }
+
+    // カレントディレクトリから GIT_DIR / GIT_WORK_TREE を考慮してリポジトリを探す
+    pub fn discover() -> io::Result<Self> {
+        let cwd = env::current_dir()?;
+        let work_tree_env = env::var_os("GIT_WORK_TREE").map(|p| cwd.join(p));
+
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let git_dir = cwd.join(git_dir);
+            if !is_git_dir(&git_dir) {
+                return Err(not_a_repository(&git_dir));
+            }
+            return Ok(Self::new(git_dir, Some(work_tree_env.unwrap_or(cwd))));
+        }
+
+        let mut repo = Self::discover_from(&cwd)?;
+        if work_tree_env.is_some() {
+            repo.work_tree = work_tree_env;
+        }
+        Ok(repo)
+    }
+
+    pub fn discover_from(start: &Path) -> io::Result<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_file() {
+                let git_dir = read_gitfile(&dot_git)?;
+                if is_git_dir(&git_dir) {
+                    return Ok(Self::new(git_dir, Some(dir.to_path_buf())));
+                }
+                return Err(not_a_repository(&git_dir));
+            }
+            if is_git_dir(&dot_git) {
+                return Ok(Self::new(dot_git, Some(dir.to_path_buf())));
+            }
+            if is_git_dir(dir) {
+                return Ok(Self::new(dir.to_path_buf(), None));
+            }
+        }
+        Err(not_a_repository(start))
+    }
+
+    pub fn init(path: &Path, bare: bool) -> io::Result<Self> {
+        let git_dir = if bare { path.to_path_buf() } else { path.join(".git") };
+        if is_git_dir(&git_dir) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already initialized"));
+        }
+        create_dir_all(&git_dir)?;
+
+        // create HEAD file
+        let mut f = File::create(git_dir.join("HEAD"))?;
+        f.write_all(b"ref: refs/heads/master\n")?;
+
+        // create objects/ and refs/{heads,tags}/ directories
+        create_dir(git_dir.join("objects"))?;
+        create_dir(git_dir.join("refs"))?;
+        create_dir(git_dir.join("refs").join("heads"))?;
+        create_dir(git_dir.join("refs").join("tags"))?;
+
+        let work_tree = if bare { None } else { Some(path.to_path_buf()) };
+        Ok(Self::new(git_dir, work_tree))
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    // HEAD などの "refs/" で始まらないものと refs/worktree/・refs/bisect/・refs/rewritten/ はワークツリーごとに持つ
+    pub fn is_per_worktree_ref(name: &str) -> bool {
+        !name.starts_with("refs/")
+            || ["refs/worktree/", "refs/bisect/", "refs/rewritten/"].iter().any(|prefix| name.starts_with(prefix))
+    }
+
+    // ref (と reflog) を置くディレクトリ
+    pub fn ref_dir(&self, name: &str) -> &Path {
+        if Self::is_per_worktree_ref(name) { &self.git_dir } else { &self.common_dir }
+    }
+
+    // 同じ common_dir を共有するすべてのワークツリー (このリポジトリ自身を含む) の git ディレクトリ
+    pub fn worktrees(&self) -> io::Result<Vec<Repository>> {
+        let mut worktrees = vec![Self::new(self.git_dir.clone(), self.work_tree.clone())];
+        if self.common_dir != self.git_dir && self.common_dir.join("HEAD").is_file() {
+            worktrees.push(Self::new(self.common_dir.clone(), None));
+        }
+        let entries = match fs::read_dir(self.common_dir.join("worktrees")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(worktrees),
+            Err(e) => return Err(e),
+        };
+        let current = fs::canonicalize(&self.git_dir).ok();
+        for entry in entries {
+            let path = entry?.path();
+            if path.join("HEAD").is_file() && fs::canonicalize(&path).ok() != current {
+                worktrees.push(Self::new(path, None));
+            }
+        }
+        Ok(worktrees)
+    }
+
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn require_work_tree(&self) -> io::Result<&Path> {
+        self.work_tree().ok_or_else(|| io::Error::other("this operation must be run in a work tree"))
+    }
+
+    // カレントディレクトリからの相対パスを、ワークツリーのルートからの "/" 区切りのパスに変換する
+    pub fn to_repo_path(&self, path: &Path) -> io::Result<String> {
+        let work_tree = self.require_work_tree()?;
+        let absolute = normalize_path(&env::current_dir()?.join(path));
+        let relative = absolute.strip_prefix(normalize_path(work_tree)).map_err(|_| {
+            io::Error::other(format!("'{}' is outside repository", path.display()))
+        })?;
+        let components: Vec<&str> = relative.iter().map(|c| c.to_str().unwrap_or("")).collect();
+        Ok(components.join("/"))
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.work_tree.is_none()
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        match env::var_os("GIT_INDEX_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => self.git_dir.join("index"),
+        }
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        match env::var_os("GIT_OBJECT_DIRECTORY") {
+            Some(dir) => PathBuf::from(dir),
+            None => self.common_dir.join("objects"),
+        }
+    }
+
+    // オブジェクトを読むたびに .idx を読み直さないよう、最初に必要になったときに読み込んでおく
+    pub fn packs(&self) -> io::Result<Rc<Vec<Pack>>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(Rc::clone(packs));
+        }
+        let packs = Rc::new(find_packs(&self.objects_dir())?);
+        *self.packs.borrow_mut() = Some(Rc::clone(&packs));
+        Ok(packs)
+    }
+
+    // pack を追加・削除した後に呼ぶ
+    pub fn reload_packs(&self) {
+        *self.packs.borrow_mut() = None;
+    }
+}
+
+// ".." や "." をファイルシステムに問い合わせずに取り除く
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+// "commondir" ファイルがあれば、そこに書かれたディレクトリ (相対パスなら git_dir から) を共有の置き場所とする
+fn common_dir(git_dir: &Path) -> PathBuf {
+    if let Some(dir) = env::var_os("GIT_COMMON_DIR") {
+        return PathBuf::from(dir);
+    }
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(content) => normalize_path(&git_dir.join(content.trim_end())),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+fn is_git_dir(path: &Path) -> bool {
+    let common_dir = common_dir(path);
+    path.join("HEAD").is_file() && common_dir.join("objects").is_dir() && common_dir.join("refs").is_dir()
+}
+
+// "gitdir: <path>" 形式の .git ファイルを読む
+fn read_gitfile(path: &Path) -> io::Result<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let target = content.trim_end().strip_prefix("gitdir: ")
+        .ok_or_else(|| invalid_data(format!("invalid gitfile format: {}", path.display())))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    Ok(base.join(target))
+}
+
+fn not_a_repository(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("not a git repository (or any of the parent directories): {}", path.display()),
+    )
+}
@@ -0,0 +1,424 @@
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use super::common::error::invalid_data;
+use super::common::regex::Regex;
+use super::config::Config;
+use super::index::Index;
+use super::objects::base::ObjectType;
+use super::objects::io::{Hash, ObjectReader, STR_HASH_LEN, find_objects_by_prefix};
+use super::objects::object::Object;
+use super::reflog::{RefLogKind, dwim_log, lookup_reflog, read_reflog};
+use super::refs::{dwim_ref, list_refs, resolve_ref, resolve_symbolic};
+use super::repository::Repository;
+
+// 省略されたハッシュとして受け付ける最短の長さ
+pub const MINIMUM_ABBREV: usize = 4;
+pub const DEFAULT_ABBREV: usize = 7;
+
+fn unknown_revision(spec: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("ambiguous argument '{}': unknown revision or path not in the working tree.", spec),
+    )
+}
+
+// タグオブジェクトであればタグでないオブジェクトに行き着くまでたどる (タグでなければ hash をそのまま返す)
+pub fn peel_tags(repo: &Repository, hash: Hash) -> io::Result<Hash> {
+    let mut current = hash;
+    // 大きな blob を読まないよう、先にヘッダだけで型を確認する
+    while ObjectReader::new(repo, &current.to_string())?.object_type() == ObjectType::Tag {
+        if let Object::Tag(tag) = ObjectReader::read_object(repo, &current.to_string())? {
+            current = tag.object;
+        }
+    }
+    Ok(current)
+}
+
+// タグをたどり、コミットからはツリーを取り出して target の型のオブジェクトにする
+pub fn peel_to_type(repo: &Repository, hash: Hash, target: ObjectType) -> io::Result<Hash> {
+    if ObjectReader::new(repo, &hash.to_string())?.object_type() == target {
+        return Ok(hash);
+    }
+    let peeled = peel_tags(repo, hash)?;
+    let obj_type = ObjectReader::new(repo, &peeled.to_string())?.object_type();
+    if obj_type == target {
+        return Ok(peeled);
+    }
+    match ObjectReader::read_object(repo, &peeled.to_string())? {
+        Object::Commit(commit) if target == ObjectType::Tree => Ok(commit.tree_hash),
+        _ => Err(invalid_data(format!("{} is a {}, not a {}", peeled, obj_type, target))),
+    }
+}
+
+fn read_commit_parents(repo: &Repository, hash: &Hash) -> io::Result<Vec<Hash>> {
+    match ObjectReader::read_object(repo, &hash.to_string())? {
+        Object::Commit(commit) => Ok(commit.parents),
+        _ => Err(invalid_data(format!("{} is not a commit", hash))),
+    }
+}
+
+// starts から親をたどって到達できるコミットをすべて集める (starts 自身も含む)
+pub fn commit_ancestors(repo: &Repository, starts: &[Hash]) -> io::Result<HashSet<Hash>> {
+    let mut seen: HashSet<Hash> = starts.iter().copied().collect();
+    let mut queue: Vec<Hash> = starts.to_vec();
+    while let Some(hash) = queue.pop() {
+        for parent in read_commit_parents(repo, &hash)? {
+            if seen.insert(parent) {
+                queue.push(parent);
+            }
+        }
+    }
+    Ok(seen)
+}
+
+// ancestor が descendant から到達できるか (同じコミットも含む)
+pub fn is_ancestor(repo: &Repository, ancestor: &Hash, descendant: &Hash) -> io::Result<bool> {
+    let mut seen = HashSet::new();
+    let mut queue = vec![*descendant];
+    while let Some(hash) = queue.pop() {
+        if hash == *ancestor {
+            return Ok(true);
+        }
+        if seen.insert(hash) {
+            queue.extend(read_commit_parents(repo, &hash)?);
+        }
+    }
+    Ok(false)
+}
+
+// (a にあって b にないコミットの数, b にあって a にないコミットの数)
+pub fn ahead_behind(repo: &Repository, a: &Hash, b: &Hash) -> io::Result<(usize, usize)> {
+    let from_a = commit_ancestors(repo, &[*a])?;
+    let from_b = commit_ancestors(repo, &[*b])?;
+    Ok((from_a.difference(&from_b).count(), from_b.difference(&from_a).count()))
+}
+
+// "<rev>:<path>" の区切りの ':' を探す ("@{...}" の中の ':' は日時の一部として扱う)
+fn find_path_separator(spec: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ':' if depth == 0 => return Some(i),
+            _ => {},
+        }
+    }
+    None
+}
+
+// 一意に決まるよう必要なだけ伸ばした省略形
+pub fn find_unique_abbrev(repo: &Repository, hash: &Hash, min_len: usize) -> io::Result<String> {
+    let full = hash.to_string();
+    for len in min_len.max(MINIMUM_ABBREV)..STR_HASH_LEN {
+        if find_objects_by_prefix(repo, &full[..len])?.len() <= 1 {
+            return Ok(full[..len].to_string());
+        }
+    }
+    Ok(full)
+}
+
+// 省略形のハッシュに一致するオブジェクトが複数ある場合のエラー (io::Error の中身として返す)
+#[derive(Debug)]
+pub struct AmbiguousObjectName {
+    pub prefix: String,
+    // (候補のハッシュ, オブジェクトの種類)
+    pub candidates: Vec<(Hash, String)>,
+}
+
+impl fmt::Display for AmbiguousObjectName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "short object ID {} is ambiguous\nThe candidates are:", self.prefix)?;
+        for (candidate, obj_type) in self.candidates.iter() {
+            write!(f, "\n  {} {}", candidate, obj_type)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousObjectName {}
+
+impl AmbiguousObjectName {
+    // 名前の解決に失敗した理由が、省略形のハッシュが曖昧だったことか
+    pub fn is_cause_of(error: &io::Error) -> bool {
+        error.get_ref().is_some_and(|inner| inner.is::<Self>())
+    }
+}
+
+fn resolve_abbrev(repo: &Repository, prefix: &str) -> io::Result<Option<Hash>> {
+    if prefix.len() < MINIMUM_ABBREV || prefix.len() > STR_HASH_LEN || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let candidates = find_objects_by_prefix(repo, prefix)?;
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(Some(candidates[0])),
+        _ => {
+            let candidates = candidates.into_iter().map(|candidate| {
+                let obj_type = ObjectReader::new(repo, &candidate.to_string()).map(|reader| reader.object_type().to_string());
+                (candidate, obj_type.unwrap_or_else(|_| "unknown".to_string()))
+            });
+            Err(invalid_data(AmbiguousObjectName { prefix: prefix.to_string(), candidates: candidates.collect() }))
+        },
+    }
+}
+
+// HEAD が指すブランチの名前 ("refs/heads/" を除いたもの)
+fn current_branch(repo: &Repository) -> io::Result<String> {
+    let (name, _) = resolve_symbolic(repo, "HEAD")?;
+    match name.strip_prefix("refs/heads/") {
+        Some(branch) => Ok(branch.to_string()),
+        None => Err(invalid_data("HEAD does not point to a branch")),
+    }
+}
+
+// branch.<name>.remote と branch.<name>.merge から、ブランチが追跡している ref の名前を求める
+pub fn branch_upstream(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    // remote が "." ならローカルのブランチを追跡している
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+    Some(format!("refs/remotes/{}/{}", remote, merge))
+}
+
+fn upstream_ref(repo: &Repository, base: &str) -> io::Result<String> {
+    let branch = if base.is_empty() || base == "HEAD" || base == "@" {
+        current_branch(repo)?
+    } else {
+        match dwim_ref(repo, base)?.as_deref().and_then(|name| name.strip_prefix("refs/heads/")) {
+            Some(branch) => branch.to_string(),
+            None => return Err(invalid_data(format!("no such branch: '{}'", base))),
+        }
+    };
+
+    let config = Config::load(Some(repo))?;
+    branch_upstream(&config, &branch)
+        .ok_or_else(|| invalid_data(format!("no upstream configured for branch '{}'", branch)))
+}
+
+// HEAD の reflog の checkout の記録から n 個前にいたブランチを探す
+fn previous_branch(repo: &Repository, n: usize) -> io::Result<String> {
+    let mut count = 0;
+    for log in read_reflog(repo, "HEAD")?.iter().rev() {
+        if let RefLogKind::Checkout { from, .. } = &log.kind {
+            count += 1;
+            if count == n {
+                return Ok(from.to_string());
+            }
+        }
+    }
+    Err(invalid_data(format!("only {} entries in the checkout history", count)))
+}
+
+// "<base>@{<selector>}" が ref を表す場合はその名前を返す (@{upstream} と @{-n})
+fn resolve_at_ref(repo: &Repository, base: &str, selector: &str) -> io::Result<Option<String>> {
+    let lower = selector.to_ascii_lowercase();
+    if lower == "u" || lower == "upstream" {
+        return upstream_ref(repo, base).map(Some);
+    }
+    if let Some(n) = selector.strip_prefix('-').and_then(|n| n.parse::<usize>().ok()) {
+        if base.is_empty() && n > 0 {
+            let branch = previous_branch(repo, n)?;
+            return Ok(Some(dwim_ref(repo, &branch)?.unwrap_or(branch)));
+        }
+    }
+    Ok(None)
+}
+
+fn split_at_selector(name: &str) -> Option<(&str, &str)> {
+    if !name.ends_with('}') {
+        return None;
+    }
+    let at = name.rfind("@{")?;
+    Some((&name[..at], &name[at + 2..name.len() - 1]))
+}
+
+// 名前が指す ref の完全な名前 (ref でなければ None)
+pub fn resolve_ref_name(repo: &Repository, name: &str) -> io::Result<Option<String>> {
+    if let Some((base, selector)) = split_at_selector(name) {
+        return resolve_at_ref(repo, base, selector);
+    }
+    let name = if name == "@" { "HEAD" } else { name };
+    match dwim_ref(repo, name)? {
+        Some(refname) if refname == "HEAD" => Ok(Some(resolve_symbolic(repo, "HEAD")?.0)),
+        refname => Ok(refname),
+    }
+}
+
+fn resolve_basic(repo: &Repository, name: &str) -> io::Result<Hash> {
+    if name.len() == STR_HASH_LEN {
+        if let Ok(hash) = name.parse() {
+            return Ok(hash);
+        }
+    }
+
+    if let Some((base, selector)) = split_at_selector(name) {
+        if let Some(refname) = resolve_at_ref(repo, base, selector)? {
+            return resolve_ref(repo, &refname)?
+                .ok_or_else(|| invalid_data(format!("'{}' does not point to a valid object", refname)));
+        }
+        // @{n} や @{<date>} は reflog をたどる。ブランチ名がなければ現在のブランチ
+        let refname = if base.is_empty() {
+            match current_branch(repo) {
+                Ok(branch) => format!("refs/heads/{}", branch),
+                Err(_) => "HEAD".to_string(),
+            }
+        } else {
+            let base = if base == "@" { "HEAD" } else { base };
+            dwim_log(repo, base).ok_or_else(|| invalid_data(format!("no reflog for '{}'", base)))?
+        };
+        return lookup_reflog(repo, &refname, selector);
+    }
+
+    // ref の名前が省略形のハッシュより優先される
+    let refname = if name == "@" { "HEAD" } else { name };
+    if let Some(full) = dwim_ref(repo, refname)? {
+        if let Some(hash) = resolve_ref(repo, &full)? {
+            return Ok(hash);
+        }
+    }
+    resolve_abbrev(repo, name)?.ok_or_else(|| unknown_revision(name))
+}
+
+// 末尾の ^{type}・~n・^n を外側から順に解釈する
+fn resolve_rev(repo: &Repository, name: &str) -> io::Result<Hash> {
+    if name.ends_with('}') {
+        if let Some(start) = name.rfind("^{") {
+            let hash = resolve_rev(repo, &name[..start])?;
+            let inner = &name[start + 2..name.len() - 1];
+            return match inner {
+                "" => peel_tags(repo, hash),
+                "object" => ObjectReader::new(repo, &hash.to_string()).map(|_| hash),
+                _ => peel_to_type(repo, hash, inner.parse()?),
+            };
+        }
+    }
+
+    let digits_start = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits_start > 0 && (name[..digits_start].ends_with('~') || name[..digits_start].ends_with('^')) {
+        let op = name.as_bytes()[digits_start - 1];
+        let digits = &name[digits_start..];
+        let n: usize = if digits.is_empty() {
+            1
+        } else {
+            digits.parse().map_err(|_| unknown_revision(name))?
+        };
+        let mut commit = peel_to_type(repo, resolve_rev(repo, &name[..digits_start - 1])?, ObjectType::Commit)?;
+        if op == b'^' {
+            if n == 0 {
+                return Ok(commit);
+            }
+            return read_commit_parents(repo, &commit)?.get(n - 1).copied().ok_or_else(|| unknown_revision(name));
+        }
+        for _ in 0..n {
+            commit = *read_commit_parents(repo, &commit)?.first().ok_or_else(|| unknown_revision(name))?;
+        }
+        return Ok(commit);
+    }
+
+    resolve_basic(repo, name)
+}
+
+fn lookup_path(repo: &Repository, tree: Hash, path: &str, spec: &str) -> io::Result<Hash> {
+    let mut current = tree;
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let tree = match ObjectReader::read_object(repo, &current.to_string())? {
+            Object::Tree(tree) => tree,
+            _ => return Err(invalid_data(format!("path '{}' does not exist in '{}'", path, spec))),
+        };
+        current = tree.entries().iter().find(|entry| entry.name == component)
+            .map(|entry| entry.hash)
+            .ok_or_else(|| invalid_data(format!("path '{}' does not exist in '{}'", path, spec)))?;
+    }
+    Ok(current)
+}
+
+// "./" や "../" で始まるパスはカレントディレクトリからの相対パスとして扱う
+fn to_tree_path(repo: &Repository, path: &str) -> io::Result<String> {
+    if path.starts_with("./") || path.starts_with("../") || path == "." || path == ".." {
+        repo.to_repo_path(Path::new(path))
+    } else {
+        Ok(path.to_string())
+    }
+}
+
+fn resolve_index_path(repo: &Repository, stage: u8, path: &str) -> io::Result<Hash> {
+    let path = to_tree_path(repo, path)?;
+    Index::read(repo)?.entry(&path, stage).map(|entry| entry.hash)
+        .ok_or_else(|| invalid_data(format!("path '{}' is not in the index at stage {}", path, stage)))
+}
+
+// すべての ref から新しいコミット順にたどり、メッセージが正規表現 text に一致する最初のコミットを返す
+// git と同様に "!-" で始まれば一致しないもの、"!!" で始まれば "!" そのものを探し、ほかの "!" で始まるものは受け付けない
+fn search_commit_message(repo: &Repository, text: &str) -> io::Result<Hash> {
+    let (pattern, negate) = match text.strip_prefix('!') {
+        Some(rest) if rest.starts_with('!') => (rest, false),
+        Some(rest) => match rest.strip_prefix('-') {
+            Some(rest) => (rest, true),
+            None => return Err(invalid_data(format!("invalid search pattern: ':/{}'", text))),
+        },
+        None => (text, false),
+    };
+    let regex = Regex::new(pattern)?;
+
+    let mut starts = Vec::new();
+    if let Some(hash) = resolve_ref(repo, "HEAD")? {
+        starts.push(hash);
+    }
+    starts.extend(list_refs(repo, "refs/")?.into_iter().map(|r| r.hash));
+
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+    for hash in starts {
+        if let Ok(commit) = peel_to_type(repo, hash, ObjectType::Commit) {
+            if seen.insert(commit) {
+                queue.push((i64::MAX, commit));
+            }
+        }
+    }
+    while let Some((_, hash)) = queue.pop() {
+        let commit = match ObjectReader::read_object(repo, &hash.to_string())? {
+            Object::Commit(commit) => commit,
+            _ => continue,
+        };
+        if regex.is_match(&commit.message_lossy()) != negate {
+            return Ok(hash);
+        }
+        for parent in commit.parents {
+            if seen.insert(parent) {
+                let time = match ObjectReader::read_object(repo, &parent.to_string())? {
+                    Object::Commit(parent) => parent.commit_timestamp.epoch(),
+                    _ => continue,
+                };
+                queue.push((time, parent));
+            }
+        }
+    }
+    Err(invalid_data(format!("no commit message matches '{}'", text)))
+}
+
+// git rev-parse と同じ記法のリビジョンをオブジェクトのハッシュに解決する
+pub fn resolve_revision(repo: &Repository, spec: &str) -> io::Result<Hash> {
+    if let Some(text) = spec.strip_prefix(":/") {
+        return search_commit_message(repo, text);
+    }
+    if let Some(rest) = spec.strip_prefix(':') {
+        // ":<stage>:<path>" または ":<path>"
+        return match rest.as_bytes() {
+            [stage @ b'0'..=b'3', b':', ..] => resolve_index_path(repo, stage - b'0', &rest[2..]),
+            _ => resolve_index_path(repo, 0, rest),
+        };
+    }
+    if let Some(colon) = find_path_separator(spec) {
+        let tree = peel_to_type(repo, resolve_rev(repo, &spec[..colon])?, ObjectType::Tree)?;
+        let path = to_tree_path(repo, &spec[colon + 1..])?;
+        return lookup_path(repo, tree, &path, &spec[..colon]);
+    }
+    resolve_rev(repo, spec)
+}
@@ -1,119 +1,67 @@
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::io;
 
-use super::objects::blob::BlobObject;
-use super::objects::io::{Hash, ObjectWriter};
-use super::objects::tree::{Mode, TreeObject};
+use super::common::error::invalid_data;
+use super::index::IndexEntry;
+use super::objects::io::{Hash, ObjectWriter, object_exists};
+use super::objects::tree::{MODE_GITLINK, MODE_TREE, TreeEntry, TreeObject};
+use super::repository::Repository;
 
-struct Blob {
-    path: String
-}
-
-pub struct Tree {
-    entries: HashMap<TreeEntryName, TreeEntry>,
-}
-
-impl Tree {
-    pub fn new() -> Self {
-        Self {
-            entries: HashMap::new(),
-        }
-    }
-
-    pub fn add_path(&mut self, path: &String, is_file: bool) {
-        let mut split_path: Vec<String> = path.split("/").map(|s| s.to_string()).collect();
-        self._add_path(&mut split_path, is_file, path)
-    }
+fn write_index_subtree(
+    repo: &Repository,
+    entries: &[&IndexEntry],
+    prefix_len: usize,
+    missing_ok: bool,
+) -> io::Result<Hash> {
+    let mut tree_object = TreeObject::new();
+    let mut i = 0;
 
-    fn _add_path(&mut self, path: &mut Vec<TreeEntryName>, is_file: bool, fullpath: &String) {
-        if path.len() <= 0 {
-            return;
+    while i < entries.len() {
+        let entry = entries[i];
+        let rest = &entry.path[prefix_len..];
+        match rest.find('/') {
+            None => {
+                if !missing_ok && entry.mode != MODE_GITLINK && !object_exists(repo, &entry.hash) {
+                    return Err(invalid_data(format!("invalid object {} for '{}'", entry.hash, entry.path)));
+                }
+                tree_object.add(TreeEntry {
+                    mode: entry.mode,
+                    name: rest.to_string(),
+                    hash: entry.hash,
+                });
+                i += 1;
+            },
+            Some(pos) => {
+                // index はパス順に並んでいるので、同じディレクトリのエントリは連続している
+                let dir_prefix = &entry.path[..prefix_len + pos + 1];
+                let count = entries[i..].iter().take_while(|e| e.path.starts_with(dir_prefix)).count();
+                let hash = write_index_subtree(repo, &entries[i..i + count], dir_prefix.len(), missing_ok)?;
+                tree_object.add(TreeEntry {
+                    mode: MODE_TREE,
+                    name: rest[..pos].to_string(),
+                    hash,
+                });
+                i += count;
+            },
         }
-        let entry_name = path.remove(0);
-        let entry_object =
-            if is_file && path.len() == 0 {
-                TreeEntryObject::Blob(Blob { path: fullpath.to_owned() })
-            } else {
-                TreeEntryObject::Tree(Tree::new())
-            };
-        let mut entry =
-            match self.entries.remove(&entry_name) {
-                Some(e) => e,
-                None => 
-                    TreeEntry {
-                        // mode: String::from(""),
-                        object: entry_object,
-                    },
-            };
-        entry.object = match entry.object {
-            TreeEntryObject::Blob(blob) => TreeEntryObject::Blob(blob),
-            TreeEntryObject::Tree(mut tree) => {
-                tree._add_path(path, is_file, fullpath);
-                TreeEntryObject::Tree(tree)
-            }
-        };
-        self.entries.insert(entry_name, entry);
     }
 
-    pub fn write_recursively(self) -> std::io::Result<Hash> {
-        let mut tree_object = TreeObject::new();
-        for (name, entry) in self.entries {
-            let mut mode = Mode(0o40000);
-            let hash = match entry.object {
-                TreeEntryObject::Blob(blob) => {
-                    mode = Mode(0o100644);
-                    let blob_object = BlobObject::from_path(&blob.path)?;
-                    ObjectWriter::write(blob_object)?
-                },
-                TreeEntryObject::Tree(tree) => {
-                    tree.write_recursively()?
-                },
-            };
-            tree_object.add(super::objects::tree::TreeEntry {
-                mode,
-                name,
-                hash
-            })
-        }
-        ObjectWriter::write(tree_object)
-    }
+    ObjectWriter::write(repo, tree_object)
 }
 
-impl Debug for Tree {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Tree(")?;
-        for (idx, (name, entry)) in self.entries.iter().enumerate() {
-            write!(f, "{:?}: {:?}", name, entry)?;
-            if idx < self.entries.len() - 1 {
-                write!(f, ", ")?;
-            }
-        };
-        write!(f, ")")?;
-        Ok(())
+// index の内容からツリーを書き込む。prefix を指定するとそのディレクトリのツリーを返す
+pub fn write_index_tree(repo: &Repository, entries: &[IndexEntry], prefix: &str, missing_ok: bool) -> io::Result<Hash> {
+    if entries.iter().any(|entry| entry.stage != 0) {
+        return Err(invalid_data("cannot write a tree with unmerged entries"));
     }
-}
 
-type TreeEntryName = String;
-
-struct TreeEntry {
-    // mode: String,
-    object: TreeEntryObject,
-}
-
-impl Debug for TreeEntry {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.object {
-            TreeEntryObject::Blob(blob) => {
-                write!(f, "<blob: {:?}>", blob.path)
-            },
-            TreeEntryObject::Tree(tree) => {
-                write!(f, "{:?}", tree)
-            }
-        }
+    let prefix = prefix.trim_end_matches('/');
+    let prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+    let entries: Vec<&IndexEntry> = entries.iter()
+        .filter(|entry| !entry.intent_to_add && entry.path.starts_with(&prefix))
+        .collect();
+    if !prefix.is_empty() && entries.is_empty() {
+        return Err(invalid_data(format!("prefix {} not found", prefix)));
     }
-}
 
-enum TreeEntryObject {
-    Blob(Blob),
-    Tree(Tree),
+    write_index_subtree(repo, &entries, prefix.len(), missing_ok)
 }
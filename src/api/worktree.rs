@@ -0,0 +1,156 @@
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use super::ignore::IgnoreRules;
+use super::index::{IndexEntry, StatData};
+use super::objects::blob::BlobObject;
+use super::objects::base::ObjectType;
+use super::objects::io::{Hash, ObjectWriter, compute_hash, compute_hash_stream};
+use super::objects::tree::{MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK, Mode};
+use super::repository::Repository;
+
+pub struct WorktreeFile {
+    pub path: String,
+    pub metadata: Metadata,
+    pub ignored: bool,
+}
+
+pub fn file_mode(metadata: &Metadata) -> Mode {
+    if metadata.file_type().is_symlink() {
+        MODE_SYMLINK
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+// シンボリックリンクはリンク先のパスを内容とする blob になる
+pub fn read_blob(path: &Path, metadata: &Metadata) -> io::Result<BlobObject> {
+    if metadata.file_type().is_symlink() {
+        Ok(BlobObject::new(fs::read_link(path)?.as_os_str().as_bytes().to_vec()))
+    } else {
+        BlobObject::from_path(&path.to_string_lossy())
+    }
+}
+
+// ワークツリー上のファイルを blob として書き込み、index のエントリを作る
+// 通常のファイルは大きくてもメモリに載せないよう、ストリームで書き込む
+pub fn stage_file(repo: &Repository, path: &str, metadata: &Metadata) -> io::Result<IndexEntry> {
+    let full_path = repo.require_work_tree()?.join(path);
+    let hash = if metadata.file_type().is_symlink() {
+        ObjectWriter::write(repo, read_blob(&full_path, metadata)?)?
+    } else {
+        ObjectWriter::write_stream(repo, ObjectType::Blob, metadata.len(), File::open(&full_path)?)?
+    };
+    Ok(IndexEntry::new(path.to_string(), file_mode(metadata), hash, StatData::from_metadata(metadata)))
+}
+
+pub fn hash_file(repo: &Repository, path: &str, metadata: &Metadata) -> io::Result<Hash> {
+    let full_path = repo.require_work_tree()?.join(path);
+    if metadata.file_type().is_symlink() {
+        compute_hash(&read_blob(&full_path, metadata)?)
+    } else {
+        compute_hash_stream(ObjectType::Blob, metadata.len(), File::open(&full_path)?)
+    }
+}
+
+// stat 情報が index に記録されたものと一致するか
+pub fn is_stat_clean(entry: &IndexEntry, metadata: &Metadata) -> bool {
+    let stat = StatData::from_metadata(metadata);
+    entry.mode == file_mode(metadata) &&
+    entry.stat.mtime_sec == stat.mtime_sec &&
+    entry.stat.mtime_nsec == stat.mtime_nsec &&
+    entry.stat.size == stat.size &&
+    entry.stat.ino == stat.ino
+}
+
+// ワークツリーの内容が index と異なるか (stat が一致しない場合は内容を比較する)
+pub fn is_modified(repo: &Repository, entry: &IndexEntry, metadata: &Metadata) -> io::Result<bool> {
+    if is_stat_clean(entry, metadata) {
+        return Ok(false);
+    }
+    if entry.mode != file_mode(metadata) {
+        return Ok(true);
+    }
+    Ok(hash_file(repo, &entry.path, metadata)? != entry.hash)
+}
+
+pub fn symlink_metadata(repo: &Repository, path: &str) -> io::Result<Option<Metadata>> {
+    match fs::symlink_metadata(repo.require_work_tree()?.join(path)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn walk_dir(
+    work_tree: &Path,
+    dir: &str,
+    ignore: &IgnoreRules,
+    include_ignored: bool,
+    parent_ignored: bool,
+    files: &mut Vec<WorktreeFile>,
+) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(work_tree.join(dir))?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if name == ".git" {
+            continue;
+        }
+        let path = if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) };
+        let metadata = fs::symlink_metadata(entry.path())?;
+
+        let is_dir = metadata.is_dir();
+        let ignored = parent_ignored || ignore.is_ignored(&path, is_dir);
+        if ignored && !include_ignored {
+            continue;
+        }
+
+        if is_dir {
+            // 入れ子になったリポジトリの中には入らない
+            if entry.path().join(".git").exists() {
+                continue;
+            }
+            walk_dir(work_tree, &path, ignore, include_ignored, ignored, files)?;
+        } else if metadata.is_file() || metadata.file_type().is_symlink() {
+            files.push(WorktreeFile { path, metadata, ignored });
+        }
+    }
+    Ok(())
+}
+
+// ワークツリーのファイルをパス順に列挙する (ディレクトリは含まない)
+pub fn walk(repo: &Repository, ignore: &IgnoreRules, include_ignored: bool) -> io::Result<Vec<WorktreeFile>> {
+    let work_tree = repo.require_work_tree()?;
+    let mut files = Vec::new();
+    walk_dir(work_tree, "", ignore, include_ignored, false, &mut files)?;
+    files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+    Ok(files)
+}
+
+// ファイルを削除し、空になった親ディレクトリも取り除く
+pub fn remove_file(repo: &Repository, path: &str) -> io::Result<()> {
+    let work_tree = repo.require_work_tree()?;
+    match fs::remove_file(work_tree.join(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    let mut dir = Path::new(path).parent();
+    while let Some(d) = dir {
+        if d.as_os_str().is_empty() || fs::remove_dir(work_tree.join(d)).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
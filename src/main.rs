@@ -1,82 +1,101 @@
+use std::collections::HashSet;
 use std::env::args;
+use std::fs::File;
 use std::process::exit;
-use std::fs::{create_dir, File};
-use std::path::Path;
-use std::io::{BufReader, BufWriter, Read, Write, stdin};
-use std::str::FromStr;
+use std::io::{self, BufReader, Read, Write, stdin};
+use std::path::{Path, PathBuf};
 
-use chrono::{DateTime, Local, Utc};
+use git_rust::api::config::{Config, ConfigEntry, ConfigFile, ConfigScope, ValuePattern, expand_path, normalize_key, parse_bool, parse_int, scope_path};
+use git_rust::api::fsck::{FsckProblem, fsck};
+use git_rust::api::gc::{GcOptions, gc, prune, too_many_loose_objects, too_many_packs};
+use git_rust::api::ident::{IdentRole, get_ident};
+use git_rust::api::refs::{RefTarget, RefTransaction, delete_ref, is_valid_refname, list_refs, read_ref, resolve_ref, resolve_symbolic, shorten_refname, update_ref, update_symbolic_ref};
+use git_rust::api::ref_filter::{DEFAULT_FORMAT, RefContext, RefFilter, RefFormat, SortKey, filter_refs, sort_refs};
+use git_rust::api::reflog::{RefLogKind, RefLogMessage, delete_reflog_entry, dwim_log, expire_reflog, list_reflogs, read_reflog};
+use git_rust::api::objects::io::{Hash, ObjectWriter, ObjectReader, compute_hash_stream};
+use git_rust::api::objects::base::ObjectType;
+use git_rust::api::objects::object::Object;
+use git_rust::api::objects::pack_writer::{DEFAULT_BIG_FILE_THRESHOLD, PackObject, PackOptions, write_pack};
+use git_rust::api::objects::tag::TagObject;
+use git_rust::api::objects::tree::{Mode, TreeEntry, TreeObject};
+use git_rust::api::objects::blob::BlobObject;
+use git_rust::api::objects::commit::{CommitObject, cleanup_message};
+use git_rust::api::repack::repack;
+use git_rust::api::repository::Repository;
+use git_rust::api::revision::{self, AmbiguousObjectName, DEFAULT_ABBREV, find_unique_abbrev, peel_tags, resolve_ref_name, resolve_revision};
+use git_rust::api::ignore::IgnoreRules;
+use git_rust::api::index::{Index, IndexEntry, StatData};
+use git_rust::api::pathspec::Pathspec;
+use git_rust::api::tree;
+use git_rust::api::worktree;
 
-mod api;
-use api::objects::tree::{Mode, TreeEntry, TreeObject};
-use api::reflog::{RefLog, RefLogKind, append_reflog};
-use api::objects::io::{ObjectWriter, ObjectReader, Hash};
-use api::objects::blob::BlobObject;
-use api::objects::commit::CommitObject;
-use api::tree;
+use git_rust::api::common::datetime::{Timestamp, parse_approxidate};
+use git_rust::api::common::error::invalid_data;
+use git_rust::api::common::regex::Regex;
+use git_rust::api::common::wildmatch::wildmatch;
 
-use crate::api::common::datetime::Timestamp;
-use crate::api::common::user::User;
-
-fn print_usage(args: &Vec<String>) {
+fn print_usage(args: &[String]) {
     eprintln!("Usage: {:} subcommand", args[0])
 }
 
-fn do_init() -> i32 {
-    let path_to_init = Path::new(".");
-    let git_dir = path_to_init.join(".git");
-
-    // create .git/ directory
-    create_dir(&git_dir).unwrap_or_else(|_| {
-        println!("already initialized");
-        return;
-    });
-
-    // create .git/HEAD file
-    let head_file = git_dir.join("HEAD");
-    let mut f = BufWriter::new(File::create(&head_file).unwrap());
-    f.write(b"ref: refs/heads/master\n").unwrap();
-
-    // create .git/objects/ directory
-    let objects_dir = git_dir.join("objects");
-    create_dir(&objects_dir).unwrap();
-
-    // create .git/refs/ directory
-    let refs_dir = git_dir.join("refs");
-    create_dir(&refs_dir).unwrap();
-
-    // create .git/refs/heads/ directory
-    let refs_heads_dir = refs_dir.join("heads");
-    create_dir(&refs_heads_dir).unwrap();
+fn open_repository() -> Option<Repository> {
+    match Repository::discover() {
+        Ok(repo) => Some(repo),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            None
+        },
+    }
+}
 
-    // create .git/refs/tags/ directory
-    let refs_tags_dir = refs_dir.join("tags");
-    create_dir(&refs_tags_dir).unwrap();
+fn do_init(subcommand_args: Vec<String>) -> i32 {
+    let bare = subcommand_args.iter().any(|arg| arg == "--bare");
+    let path_to_init = subcommand_args.iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map_or(".", |arg| arg.as_str());
 
-    0
+    match Repository::init(Path::new(path_to_init), bare) {
+        Ok(_) => 0,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            println!("already initialized");
+            1
+        },
+        Err(_) => {
+            eprintln!("error: failed to initialize repository");
+            1
+        },
+    }
 }
 
 // TODO: src/api/objects/raw.rsのBUF_SIZEと共通化する
 const BUF_SIZE: usize = 2048;
 
 fn do_write_object() -> i32 {
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
     let mut buf = [0u8; BUF_SIZE];
-    let mut writer = ObjectWriter::new();
+    let mut writer = match ObjectWriter::new(&repo) {
+        Ok(writer) => writer,
+        Err(_) => {
+            eprintln!("error: failed to create the object file");
+            return 1;
+        },
+    };
 
+    let mut stream = BufReader::new(stdin());
     loop {
-        match BufReader::new(stdin()).read(&mut buf) {
-            Ok(size) => {
-                if size <= 0 {
-                    break;
-                }
-            }
+        let size = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(size) => size,
             Err(_) => {
                 eprintln!("error: failed to read stdin");
                 return 1;
             },
         };
 
-        if let Err(_) = writer.write(&buf) {
+        if writer.write_all(&buf[..size]).is_err() {
             eprintln!("error: failed to write to the object file");
             return 1;
         };
@@ -94,45 +113,159 @@ fn do_write_object() -> i32 {
     }
 }
 
+fn hash_object<R: Read>(repo: Option<&Repository>, obj_type: ObjectType, size: u64, reader: R) -> io::Result<Hash> {
+    match repo {
+        Some(repo) => ObjectWriter::write_stream(repo, obj_type, size, reader),
+        None => compute_hash_stream(obj_type, size, reader),
+    }
+}
+
+// clean フィルタなどの変換は未対応なので --path は受け付けるだけ
+fn do_hash_object(subcommand_args: Vec<String>) -> i32 {
+    let mut write = false;
+    let mut obj_type = ObjectType::Blob;
+    let mut use_stdin = false;
+    let mut files: Vec<String> = Vec::new();
+
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-w" => write = true,
+            "--stdin" => use_stdin = true,
+            "--no-filters" => {},
+            "-t" | "--path" => {
+                let value = match iter.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("error: switch `{}' requires a value", arg.trim_start_matches('-'));
+                        return 1;
+                    },
+                };
+                if arg == "-t" {
+                    obj_type = match value.parse() {
+                        Ok(obj_type) => obj_type,
+                        Err(e) => {
+                            eprintln!("error: {}", e);
+                            return 1;
+                        },
+                    };
+                }
+            },
+            "--" => files.extend(iter.by_ref().cloned()),
+            _ if arg.starts_with("--path=") => {},
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                eprintln!("error: unknown option: {}", arg);
+                return 1;
+            },
+            _ => files.push(arg.to_string()),
+        }
+    }
+    if !use_stdin && files.is_empty() {
+        eprintln!("Usage: hash-object [-w] [-t <type>] [--path <file>] (--stdin | <file>...)");
+        return 1;
+    }
+
+    let repo = if write {
+        match open_repository() {
+            Some(repo) => Some(repo),
+            None => return 1,
+        }
+    } else {
+        None
+    };
+
+    let mut sources: Vec<Option<String>> = Vec::new();
+    if use_stdin {
+        sources.push(None);
+    }
+    sources.extend(files.into_iter().map(Some));
+
+    for source in sources {
+        let name = source.as_deref().unwrap_or("<stdin>");
+        let result = match (&source, obj_type) {
+            // blob は検証が不要なので、ファイルは読み込まずにストリームで処理する
+            (Some(file), ObjectType::Blob) => File::open(file)
+                .and_then(|f| Ok((f.metadata()?.len(), f)))
+                .and_then(|(size, f)| hash_object(repo.as_ref(), obj_type, size, f)),
+            _ => {
+                let content = match &source {
+                    Some(file) => std::fs::read(file),
+                    None => {
+                        let mut content = Vec::new();
+                        stdin().read_to_end(&mut content).map(|_| content)
+                    },
+                };
+                content.and_then(|content| {
+                    // 壊れたオブジェクトを作らないよう、blob 以外は内容を検証する
+                    if obj_type != ObjectType::Blob {
+                        Object::parse_body(obj_type, content.clone())?;
+                    }
+                    hash_object(repo.as_ref(), obj_type, content.len() as u64, &content[..])
+                })
+            },
+        };
+        match result {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("error: unable to hash {}: {}", name, e);
+                return 1;
+            },
+        }
+    }
+    0
+}
+
 fn do_read_object(subcommand_args: Vec<String>) -> i32 {
     if subcommand_args.len() != 1 {
-        eprintln!("Usage: read-object OBJECT_HASH");
+        eprintln!("Usage: read-object OBJECT");
         return 1;
     }
-    let hash = subcommand_args[0].to_string();
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let hash = match resolve_revision(&repo, &subcommand_args[0]) {
+        Ok(hash) => hash.to_string(),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
 
-    let content = match ObjectReader::read(&hash) {
+    let content = match ObjectReader::read(&repo, &hash) {
         Ok(content) => content,
-        Err(_) => {
-            eprintln!("error: failed to read object file");
+        Err(e) => {
+            eprintln!("error: failed to read object file: {}", e);
             return 1;
         },
     };
 
-    match String::from_utf8(content) {
-        Ok(res) => {
-            print!("{}", res);
-            0
-        },
-        Err(_) => {
-            eprintln!("error: can't display the content read from object file");
+    // バイナリのオブジェクトもそのまま出力する
+    match io::stdout().write_all(&content) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
             1
         },
     }
 }
 
 fn do_write_blob() -> i32 {
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
     let mut stream = BufReader::new(stdin());
     let mut content = Vec::new();
 
-    if let Err(_) = stream.read_to_end(&mut content) {
+    if stream.read_to_end(&mut content).is_err() {
         eprintln!("error: failed to read stdin");
         return 1;
     }
 
     let blob_object = BlobObject::new(content);
 
-    match ObjectWriter::write(blob_object) {
+    match ObjectWriter::write(&repo, blob_object) {
         Ok(hash) => {
             println!("{}", hash);
             0
@@ -144,89 +277,2875 @@ fn do_write_blob() -> i32 {
     }
 }
 
-fn do_write_tree() -> i32 {
-    let mut tree = TreeObject::new();
+fn do_write_tree(subcommand_args: Vec<String>) -> i32 {
+    let mut missing_ok = false;
+    let mut prefix = "";
+    for arg in subcommand_args.iter() {
+        if arg == "--missing-ok" {
+            missing_ok = true;
+        } else if let Some(value) = arg.strip_prefix("--prefix=") {
+            prefix = value;
+        } else {
+            eprintln!("Usage: write-tree [--missing-ok] [--prefix=<prefix>/]");
+            return 1;
+        }
+    }
 
-    tree.add(TreeEntry {
-        mode: Mode(0o100644),
-        name: String::from("hoge.txt"),
-        hash: Hash(*b"0123456789abcdef0123"),
-    });
-    tree.add(TreeEntry {
-        mode: Mode(0o100644),
-        name: String::from("foo.txt"),
-        hash: Hash(*b"0123456789abcdef0123"),
-    });
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let index = match Index::read(&repo) {
+        Ok(index) => index,
+        Err(_) => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
 
-    match ObjectWriter::write(tree) {
+    match tree::write_index_tree(&repo, index.entries(), prefix, missing_ok) {
         Ok(hash) => {
             println!("{}", hash);
             0
         },
-        Err(_) => {
-            eprintln!("error: failed to write tree object");
+        Err(e) => {
+            eprintln!("error: failed to write tree object: {}", e);
+            1
+        },
+    }
+}
+
+// "<mode> SP <type> SP <object> TAB <path>" (ls-tree の出力形式) の 1 行をツリーのエントリにする
+fn parse_mktree_line(repo: &Repository, line: &str, allow_missing: bool) -> io::Result<TreeEntry> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let (info, name) = line.split_once('\t').ok_or_else(|| invalid(format!("input format error: {}", line)))?;
+    let fields: Vec<&str> = info.split(' ').collect();
+    let (mode, obj_type, hash) = match fields.as_slice() {
+        [mode, obj_type, hash] => (Mode::parse(mode.as_bytes())?, obj_type.parse::<ObjectType>()?, hash.parse::<Hash>()?),
+        _ => return Err(invalid(format!("input format error: {}", line))),
+    };
+    if name.is_empty() || name.contains('/') {
+        return Err(invalid(format!("path {} contains slash", name)));
+    }
+    if mode.object_type() != obj_type {
+        return Err(invalid(format!("entry '{}' object type ({}) doesn't match mode type ({})", name, obj_type, mode.object_type())));
+    }
+
+    // サブモジュールのコミットはこのリポジトリにはない
+    if obj_type != ObjectType::Commit {
+        match ObjectReader::new(repo, &hash.to_string()) {
+            Ok(reader) if reader.object_type() != obj_type => {
+                return Err(invalid(format!("entry '{}' object {} is a {} but specified type was ({})", name, hash, reader.object_type(), obj_type)));
+            },
+            Ok(_) => {},
+            Err(_) if allow_missing => {},
+            Err(_) => return Err(invalid(format!("entry '{}' object {} is unavailable", name, hash))),
+        }
+    }
+    Ok(TreeEntry { mode, name: name.to_string(), hash })
+}
+
+fn do_mktree(subcommand_args: Vec<String>) -> i32 {
+    let mut allow_missing = false;
+    let mut terminator = b'\n';
+    for arg in subcommand_args.iter() {
+        match arg.as_str() {
+            "--missing" => allow_missing = true,
+            "-z" => terminator = b'\0',
+            _ => {
+                eprintln!("Usage: mktree [-z] [--missing]");
+                return 1;
+            },
+        }
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let mut input = Vec::new();
+    if let Err(e) = stdin().read_to_end(&mut input) {
+        eprintln!("error: {}", e);
+        return 1;
+    }
+    let mut tree = TreeObject::new();
+    for line in input.split(|&b| b == terminator).filter(|line| !line.is_empty()) {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(_) => {
+                eprintln!("error: input is not valid UTF-8");
+                return 1;
+            },
+        };
+        match parse_mktree_line(&repo, line, allow_missing) {
+            Ok(entry) => tree.add(entry),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        }
+    }
+
+    match ObjectWriter::write(&repo, tree) {
+        Ok(hash) => {
+            println!("{}", hash);
+            0
+        },
+        Err(e) => {
+            eprintln!("error: failed to write tree object: {}", e);
             1
         },
     }
 }
 
-fn do_tree_test() -> i32 {
-    let mut root_tree = tree::Tree::new();
+// git commit-tree と同様に、メッセージは整形せずそのまま使う (-m は段落ごとに空行で区切る)
+fn append_message_paragraph(message: &mut String, paragraph: &str, complete_line: bool) {
+    if !message.is_empty() {
+        message.push('\n');
+    }
+    message.push_str(paragraph);
+    if complete_line && !message.is_empty() && !message.ends_with('\n') {
+        message.push('\n');
+    }
+}
 
-    let mut path1 = String::from("src/api/objects/blob.rs");
+fn read_message_file(file: &str) -> io::Result<String> {
+    if file == "-" {
+        let mut content = String::new();
+        stdin().read_to_string(&mut content)?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(file)
+    }
+}
 
-    root_tree.add_path(&mut path1, true);
+fn do_commit_tree(subcommand_args: Vec<String>) -> i32 {
+    const USAGE: &str = "Usage: commit-tree <tree> [(-p <parent>)...] [(-m <message>)...] [(-F <file>)...]";
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
 
-    println!("{:?}", root_tree);
+    let mut tree = None;
+    let mut parents: Vec<Hash> = Vec::new();
+    let mut message = String::new();
+    let mut has_message = false;
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-p" | "-m" | "-F" => {
+                let value = match iter.next() {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("error: switch `{}' requires a value", &arg[1..]);
+                        return 1;
+                    },
+                };
+                if arg == "-p" {
+                    let hash = match resolve_revision(&repo, value).and_then(|hash| revision::peel_to_type(&repo, hash, ObjectType::Commit)) {
+                        Ok(hash) => hash,
+                        Err(_) => {
+                            eprintln!("error: Not a valid object name {}", value);
+                            return 1;
+                        },
+                    };
+                    if parents.contains(&hash) {
+                        eprintln!("error: duplicate parent {} ignored", hash);
+                    } else {
+                        parents.push(hash);
+                    }
+                } else if arg == "-m" {
+                    append_message_paragraph(&mut message, value, true);
+                    has_message = true;
+                } else {
+                    match read_message_file(value) {
+                        Ok(content) => append_message_paragraph(&mut message, &content, false),
+                        Err(e) => {
+                            eprintln!("error: could not read log file '{}': {}", value, e);
+                            return 1;
+                        },
+                    }
+                    has_message = true;
+                }
+            },
+            _ if !arg.starts_with('-') && tree.is_none() => tree = Some(arg.as_str()),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 1;
+            },
+        }
+    }
+    let tree = match tree {
+        Some(tree) => tree,
+        None => {
+            eprintln!("{}", USAGE);
+            return 1;
+        },
+    };
+    let tree_hash = match resolve_revision(&repo, tree) {
+        Ok(hash) => hash,
+        Err(_) => {
+            eprintln!("error: Not a valid object name {}", tree);
+            return 1;
+        },
+    };
+    match ObjectReader::new(&repo, &tree_hash.to_string()) {
+        Ok(reader) if reader.object_type() == ObjectType::Tree => {},
+        _ => {
+            eprintln!("error: {} is not a valid 'tree' object", tree_hash);
+            return 1;
+        },
+    }
 
-    let tree_hash = root_tree.write_recursively().unwrap();
+    // -m も -F もなければ標準入力から読む
+    if !has_message {
+        if let Err(e) = stdin().read_to_string(&mut message) {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    }
 
-    let user = User { name: String::from("Shuhei"), email: String::from("sh7916@gmail.com") };
-    let timestamp = Timestamp::from_datetime::<Local>(DateTime::from_str("2021-09-16 00:00:00+09:00").unwrap());
+    let config = match Config::load(Some(&repo)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: failed to read config: {}", e);
+            return 1;
+        },
+    };
+    let idents = get_ident(&config, IdentRole::Author)
+        .and_then(|author| Ok((author, get_ident(&config, IdentRole::Committer)?)));
+    let ((author, author_timestamp), (committer, commit_timestamp)) = match idents {
+        Ok(idents) => idents,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
 
     let commit = CommitObject {
         tree_hash,
-        parent_hash: Hash(*b"00000000000000000000"),
-        author: &user,
-        author_timestamp: &timestamp,
-        committer: &user,
-        commit_timestamp: &timestamp,
-        message: String::from("Initial commit"),
+        parents,
+        author,
+        author_timestamp,
+        committer,
+        commit_timestamp,
+        encoding: None,
+        mergetags: Vec::new(),
+        gpgsig: None,
+        extra_headers: Vec::new(),
+        message: message.into_bytes(),
+    };
+    match ObjectWriter::write(&repo, commit) {
+        Ok(hash) => {
+            println!("{}", hash);
+            0
+        },
+        Err(e) => {
+            eprintln!("error: failed to write commit object: {}", e);
+            1
+        },
+    }
+}
+
+fn parse_reflog_selector(arg: &str) -> Option<(&str, usize)> {
+    let (name, rest) = arg.split_once("@{")?;
+    let n = rest.strip_suffix('}')?.parse().ok()?;
+    Some((if name.is_empty() { "HEAD" } else { name }, n))
+}
+
+fn do_reflog_show(repo: &Repository, subcommand_args: &[String]) -> i32 {
+    let name = subcommand_args.first().map_or("HEAD", |arg| arg.as_str());
+    let refname = match dwim_log(repo, name) {
+        Some(refname) => refname,
+        None => {
+            eprintln!("error: no reflog for '{}'", name);
+            return 1;
+        },
+    };
+    let logs = match read_reflog(repo, &refname) {
+        Ok(logs) => logs,
+        Err(e) => {
+            eprintln!("error: failed to read reflog: {}", e);
+            return 1;
+        },
     };
 
-    let hash = ObjectWriter::write(commit).unwrap();
+    for (n, log) in logs.iter().rev().enumerate() {
+        println!("{} {}@{{{}}}: {}", &log.hash.to_string()[..7], name, n, log.message());
+    }
+    0
+}
+
+fn do_reflog_expire(repo: &Repository, subcommand_args: &[String]) -> i32 {
+    let now = Timestamp::now().epoch();
+    let mut expire = now - 90 * 24 * 60 * 60;
+    let mut all = false;
+    let mut names = Vec::new();
+
+    for arg in subcommand_args {
+        if let Some(date) = arg.strip_prefix("--expire=") {
+            expire = match parse_approxidate(date, now) {
+                Ok(expire) => expire,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    return 1;
+                },
+            };
+        } else if arg == "--all" {
+            all = true;
+        } else {
+            names.push(arg.to_string());
+        }
+    }
 
-    println!("{}", hash);
+    let refnames = if all {
+        match list_reflogs(repo) {
+            Ok(refnames) => refnames,
+            Err(_) => {
+                eprintln!("error: failed to list reflogs");
+                return 1;
+            },
+        }
+    } else {
+        let mut refnames = Vec::new();
+        for name in names.iter() {
+            match dwim_log(repo, name) {
+                Some(refname) => refnames.push(refname),
+                None => {
+                    eprintln!("error: no reflog for '{}'", name);
+                    return 1;
+                },
+            }
+        }
+        refnames
+    };
 
+    for refname in refnames {
+        if let Err(e) = expire_reflog(repo, &refname, expire) {
+            eprintln!("error: failed to expire reflog for '{}': {}", refname, e);
+            return 1;
+        }
+    }
     0
 }
 
-fn do_commit_test() -> i32 {
-    let ts = Timestamp::now();
-    println!("{}", ts);
-    let ts = Timestamp::from_datetime(Utc::now());
-    println!("{}", ts);
+fn do_reflog_delete(repo: &Repository, subcommand_args: &[String]) -> i32 {
+    let mut selectors = Vec::new();
+    for arg in subcommand_args {
+        let (name, n) = match parse_reflog_selector(arg) {
+            Some(selector) => selector,
+            None => {
+                eprintln!("error: not a reflog: {}", arg);
+                return 1;
+            },
+        };
+        match dwim_log(repo, name) {
+            Some(refname) => selectors.push((refname, n)),
+            None => {
+                eprintln!("error: no reflog for '{}'", name);
+                return 1;
+            },
+        }
+    }
+    if selectors.is_empty() {
+        eprintln!("error: no reflog specified to delete");
+        return 1;
+    }
+
+    // 後ろのエントリから削除しないと番号がずれる
+    selectors.sort_by_key(|selector| std::cmp::Reverse(selector.1));
+    for (refname, n) in selectors {
+        if let Err(e) = delete_reflog_entry(repo, &refname, n) {
+            eprintln!("error: failed to delete reflog entry: {}", e);
+            return 1;
+        }
+    }
     0
 }
 
-fn do_reflog_test() -> i32 {
-    println!("add!!!");
+fn do_reflog(subcommand_args: Vec<String>) -> i32 {
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    match subcommand_args.first().map(|arg| arg.as_str()) {
+        Some("show") => do_reflog_show(&repo, &subcommand_args[1..]),
+        Some("expire") => do_reflog_expire(&repo, &subcommand_args[1..]),
+        Some("delete") => do_reflog_delete(&repo, &subcommand_args[1..]),
+        _ => do_reflog_show(&repo, &subcommand_args),
+    }
+}
+
+fn split_options(subcommand_args: &[String]) -> (Vec<&str>, Vec<String>) {
+    let mut options = Vec::new();
+    let mut paths = Vec::new();
+    let mut only_paths = false;
+    for arg in subcommand_args {
+        if only_paths {
+            paths.push(arg.to_string());
+        } else if arg == "--" {
+            only_paths = true;
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            options.push(arg.as_str());
+        } else {
+            paths.push(arg.to_string());
+        }
+    }
+    (options, paths)
+}
+
+fn do_add(subcommand_args: Vec<String>) -> i32 {
+    let (options, paths) = split_options(&subcommand_args);
+    let mut update = false;
+    let mut all = false;
+    let mut force = false;
+    let mut dry_run = false;
+    let mut verbose = false;
+    for option in options {
+        match option {
+            "-u" | "--update" => update = true,
+            "-A" | "--all" => all = true,
+            "-f" | "--force" => force = true,
+            "-n" | "--dry-run" => dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            _ => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+        }
+    }
+    if paths.is_empty() && !update && !all {
+        println!("Nothing specified, nothing added.");
+        return 0;
+    }
+
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let pathspec = match Pathspec::parse(&repo, &paths) {
+        Ok(pathspec) => pathspec,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    let (mut index, ignore) = match (Index::read(&repo), IgnoreRules::load(&repo)) {
+        (Ok(index), Ok(ignore)) => (index, ignore),
+        _ => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
 
-    let ref_log = RefLog {
-        prev_hash: String::from("0000000000000000000000000000000000000000"),
-        hash: String::from("335584cfc68b36a5f2332c10b32a0cf6a441cad8"),
-        author: String::from("Shuhei"),
-        email: String::from("sh7916@gmail.com"),
-        timestamp: 1631017871,
-        timezone: 540,
-        kind: RefLogKind::COMMIT,
-        description: String::from("Initial commit"),
+    let mut matched = vec![false; pathspec.items().len()];
+    let mut mark_matched = |path: &str| {
+        for (item, matched) in pathspec.items().iter().zip(matched.iter_mut()) {
+            if Pathspec::matches_item(item, path) {
+                *matched = true;
+            }
+        }
     };
-    append_reflog("hoge", ref_log);
 
+    let mut tracked: Vec<String> = index.entries().iter().map(|entry| entry.path.clone()).collect();
+    tracked.dedup();
+    for path in tracked.iter().filter(|path| pathspec.matches(path)) {
+        mark_matched(path);
+        let metadata = match worktree::symlink_metadata(&repo, path) {
+            Ok(Some(metadata)) if !metadata.is_dir() => metadata,
+            Ok(_) => {
+                if verbose || dry_run {
+                    println!("remove '{}'", path);
+                }
+                if !dry_run {
+                    index.remove(path);
+                }
+                continue;
+            },
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                return 1;
+            },
+        };
+        if let Some(entry) = index.entry(path, 0) {
+            if worktree::is_stat_clean(entry, &metadata) {
+                continue;
+            }
+        }
+        if verbose || dry_run {
+            println!("add '{}'", path);
+        }
+        if dry_run {
+            continue;
+        }
+        match worktree::stage_file(&repo, path, &metadata) {
+            Ok(entry) => index.add(entry),
+            Err(e) => {
+                eprintln!("error: unable to index file '{}': {}", path, e);
+                return 1;
+            },
+        }
+    }
+
+    if !update {
+        let files = match worktree::walk(&repo, &ignore, true) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("error: failed to scan the work tree: {}", e);
+                return 1;
+            },
+        };
+        let mut ignored_paths = Vec::new();
+        for file in files.iter().filter(|file| pathspec.matches(&file.path)) {
+            if index.entry(&file.path, 0).is_some() {
+                continue;
+            }
+            if file.ignored && !force {
+                // 明示的に指定された無視対象のファイルだけをエラーとして報告する
+                if pathspec.items().iter().any(|item| item == &file.path) {
+                    ignored_paths.push(file.path.clone());
+                }
+                continue;
+            }
+            mark_matched(&file.path);
+            if verbose || dry_run {
+                println!("add '{}'", file.path);
+            }
+            if dry_run {
+                continue;
+            }
+            match worktree::stage_file(&repo, &file.path, &file.metadata) {
+                Ok(entry) => index.add(entry),
+                Err(e) => {
+                    eprintln!("error: unable to index file '{}': {}", file.path, e);
+                    return 1;
+                },
+            }
+        }
+
+        if !ignored_paths.is_empty() {
+            eprintln!("The following paths are ignored by one of your .gitignore files:");
+            for path in ignored_paths.iter() {
+                eprintln!("{}", path);
+            }
+            eprintln!("Use -f if you really want to add them.");
+            return 1;
+        }
+    }
+
+    for (arg, matched) in paths.iter().zip(matched.iter()) {
+        if !matched {
+            eprintln!("error: pathspec '{}' did not match any files", arg);
+            return 1;
+        }
+    }
+
+    if dry_run {
+        return 0;
+    }
+    if let Err(e) = index.write(&repo) {
+        eprintln!("error: failed to write the index: {}", e);
+        return 1;
+    }
     0
 }
 
+fn do_rm(subcommand_args: Vec<String>) -> i32 {
+    let (options, paths) = split_options(&subcommand_args);
+    let mut cached = false;
+    let mut recursive = false;
+    let mut force = false;
+    let mut quiet = false;
+    let mut dry_run = false;
+    let mut ignore_unmatch = false;
+    for option in options {
+        match option {
+            "--cached" => cached = true,
+            "-r" => recursive = true,
+            "-f" | "--force" => force = true,
+            "-q" | "--quiet" => quiet = true,
+            "-n" | "--dry-run" => dry_run = true,
+            "--ignore-unmatch" => ignore_unmatch = true,
+            _ => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("Usage: rm [--cached] [-r] [-f] [-q] [-n] <pathspec>...");
+        return 1;
+    }
+
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let pathspec = match Pathspec::parse(&repo, &paths) {
+        Ok(pathspec) => pathspec,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    let mut index = match Index::read(&repo) {
+        Ok(index) => index,
+        Err(_) => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
+
+    let mut targets: Vec<String> = Vec::new();
+    for (arg, item) in paths.iter().zip(pathspec.items().iter()) {
+        let matched: Vec<&str> = index.entries().iter()
+            .map(|entry| entry.path.as_str())
+            .filter(|path| Pathspec::matches_item(item, path))
+            .collect();
+        if matched.is_empty() && !ignore_unmatch {
+            eprintln!("error: pathspec '{}' did not match any files", arg);
+            return 1;
+        }
+        if !recursive && matched.iter().any(|path| path != item) && !item.contains(['*', '?', '[']) {
+            eprintln!("error: not removing '{}' recursively without -r", arg);
+            return 1;
+        }
+        targets.extend(matched.iter().map(|path| path.to_string()));
+    }
+    targets.sort();
+    targets.dedup();
+
+    if !cached && !force {
+        let mut modified = Vec::new();
+        for path in targets.iter() {
+            let entry = match index.entry(path, 0) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if let Ok(Some(metadata)) = worktree::symlink_metadata(&repo, path) {
+                if worktree::is_modified(&repo, entry, &metadata).unwrap_or(true) {
+                    modified.push(path.clone());
+                }
+            }
+        }
+        if !modified.is_empty() {
+            eprintln!("error: the following {} local modifications:", if modified.len() == 1 { "file has" } else { "files have" });
+            for path in modified.iter() {
+                eprintln!("    {}", path);
+            }
+            eprintln!("(use --cached to keep the file, or -f to force removal)");
+            return 1;
+        }
+    }
+
+    for path in targets.iter() {
+        if !quiet {
+            println!("rm '{}'", path);
+        }
+        if dry_run {
+            continue;
+        }
+        index.remove(path);
+        if !cached {
+            if let Err(e) = worktree::remove_file(&repo, path) {
+                eprintln!("error: failed to remove '{}': {}", path, e);
+                return 1;
+            }
+        }
+    }
+
+    if dry_run {
+        return 0;
+    }
+    if let Err(e) = index.write(&repo) {
+        eprintln!("error: failed to write the index: {}", e);
+        return 1;
+    }
+    0
+}
+
+fn do_mv(subcommand_args: Vec<String>) -> i32 {
+    let (options, mut paths) = split_options(&subcommand_args);
+    let mut force = false;
+    let mut skip_errors = false;
+    let mut dry_run = false;
+    let mut verbose = false;
+    for option in options {
+        match option {
+            "-f" | "--force" => force = true,
+            "-k" => skip_errors = true,
+            "-n" | "--dry-run" => dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            _ => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+        }
+    }
+    if paths.len() < 2 {
+        eprintln!("Usage: mv [-f] [-k] [-n] [-v] <source>... <destination>");
+        return 1;
+    }
+    let destination = paths.pop().unwrap();
+
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let work_tree = match repo.require_work_tree() {
+        Ok(work_tree) => work_tree.to_path_buf(),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    // git mv は glob を展開せず、引数をそのままのパスとして扱う
+    let sources = paths.iter().map(|path| repo.to_repo_path(Path::new(path))).collect::<io::Result<Vec<String>>>();
+    let (sources, destination) = match (sources, repo.to_repo_path(Path::new(&destination))) {
+        (Ok(sources), Ok(destination)) => (sources, destination),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    let mut index = match Index::read(&repo) {
+        Ok(index) => index,
+        Err(_) => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
+
+    let destination_is_dir = work_tree.join(&destination).is_dir();
+    if sources.len() > 1 && !destination_is_dir {
+        eprintln!("error: destination '{}' is not a directory", destination);
+        return 1;
+    }
+
+    for source in &sources {
+        let target = if destination_is_dir {
+            let name = source.rsplit('/').next().unwrap_or(source);
+            if destination.is_empty() { name.to_string() } else { format!("{}/{}", destination, name) }
+        } else {
+            destination.clone()
+        };
+
+        let target_path = work_tree.join(&target);
+        let entries: Vec<IndexEntry> = index.entries().iter()
+            .filter(|entry| {
+                !source.is_empty() && (entry.path == *source || entry.path.strip_prefix(source.as_str()).is_some_and(|rest| rest.starts_with('/')))
+            })
+            .cloned()
+            .collect();
+        let error = if entries.is_empty() {
+            Some("not under version control")
+        } else if source == &target || target.starts_with(&format!("{}/", source)) {
+            Some("can not move directory into itself")
+        } else if target_path.exists() && (!force || target_path.is_dir()) {
+            Some("destination exists")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            if skip_errors {
+                continue;
+            }
+            eprintln!("error: {}, source={}, destination={}", error, source, target);
+            return 1;
+        }
+
+        if verbose || dry_run {
+            println!("Renaming {} to {}", source, target);
+        }
+        if dry_run {
+            continue;
+        }
+
+        if let Some(parent) = target_path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        }
+        if let Err(e) = std::fs::rename(work_tree.join(source), &target_path) {
+            eprintln!("error: renaming '{}' failed: {}", source, e);
+            return 1;
+        }
+
+        for entry in entries {
+            index.remove(&entry.path);
+            let path = format!("{}{}", target, &entry.path[source.len()..]);
+            let stat = match worktree::symlink_metadata(&repo, &path) {
+                Ok(Some(metadata)) => StatData::from_metadata(&metadata),
+                _ => entry.stat,
+            };
+            index.add(IndexEntry { path, stat, ..entry });
+        }
+    }
+
+    if dry_run {
+        return 0;
+    }
+    if let Err(e) = index.write(&repo) {
+        eprintln!("error: failed to write the index: {}", e);
+        return 1;
+    }
+    0
+}
+
+fn do_commit(subcommand_args: Vec<String>) -> i32 {
+    let mut messages: Vec<String> = Vec::new();
+    let mut message_file: Option<String> = None;
+    let mut amend = false;
+    let mut allow_empty = false;
+    let mut quiet = false;
+
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-m" | "--message" => match iter.next() {
+                Some(message) => messages.push(message.to_string()),
+                None => {
+                    eprintln!("error: switch `m' requires a value");
+                    return 1;
+                },
+            },
+            "-F" | "--file" => match iter.next() {
+                Some(file) => message_file = Some(file.to_string()),
+                None => {
+                    eprintln!("error: switch `F' requires a value");
+                    return 1;
+                },
+            },
+            "--amend" => amend = true,
+            "--allow-empty" => allow_empty = true,
+            "-q" | "--quiet" => quiet = true,
+            _ => {
+                if let Some(message) = arg.strip_prefix("-m") {
+                    messages.push(message.to_string());
+                } else if let Some(message) = arg.strip_prefix("--message=") {
+                    messages.push(message.to_string());
+                } else if let Some(file) = arg.strip_prefix("--file=") {
+                    message_file = Some(file.to_string());
+                } else {
+                    eprintln!("error: unknown option: {}", arg);
+                    return 1;
+                }
+            },
+        }
+    }
+    if !messages.is_empty() && message_file.is_some() {
+        eprintln!("error: options '-m' and '-F' cannot be used together");
+        return 1;
+    }
+
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let config = match Config::load(Some(&repo)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: failed to read config: {}", e);
+            return 1;
+        },
+    };
+    let index = match Index::read(&repo) {
+        Ok(index) => index,
+        Err(_) => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
+
+    let (branch, head) = match resolve_symbolic(&repo, "HEAD") {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("error: failed to resolve HEAD: {}", e);
+            return 1;
+        },
+    };
+    let head_commit = match head {
+        Some(hash) => match ObjectReader::read_object(&repo, &hash.to_string()) {
+            Ok(Object::Commit(commit)) => Some(commit),
+            _ => {
+                eprintln!("error: HEAD does not point to a commit");
+                return 1;
+            },
+        },
+        None => None,
+    };
+    if amend && head_commit.is_none() {
+        eprintln!("error: You have nothing to amend.");
+        return 1;
+    }
+
+    let tree_hash = match tree::write_index_tree(&repo, index.entries(), "", false) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let parents = match (&head_commit, amend) {
+        (Some(commit), true) => commit.parents.clone(),
+        (Some(_), false) => vec![head.unwrap()],
+        (None, _) => Vec::new(),
+    };
+    if !amend && !allow_empty {
+        let unchanged = match &head_commit {
+            Some(commit) => commit.tree_hash == tree_hash,
+            None => index.entries().is_empty(),
+        };
+        if unchanged {
+            println!("nothing to commit, working tree clean");
+            return 1;
+        }
+    }
+
+    let mut encoding = None;
+    let message = if let Some(file) = message_file {
+        let content = if file == "-" {
+            let mut content = String::new();
+            stdin().read_to_string(&mut content).map(|_| content)
+        } else {
+            std::fs::read_to_string(&file)
+        };
+        match content {
+            Ok(content) => cleanup_message(&content, false).into_bytes(),
+            Err(e) => {
+                eprintln!("error: could not read log file '{}': {}", file, e);
+                return 1;
+            },
+        }
+    } else if !messages.is_empty() {
+        cleanup_message(&messages.join("\n\n"), false).into_bytes()
+    } else if let (Some(commit), true) = (&head_commit, amend) {
+        // 元のメッセージを (UTF-8 でなくても) そのまま使い、エンコーディングも引き継ぐ
+        encoding = commit.encoding.clone();
+        commit.message.clone()
+    } else {
+        Vec::new()
+    };
+    if message.is_empty() {
+        eprintln!("Aborting commit due to empty commit message.");
+        return 1;
+    }
+
+    let (committer, commit_timestamp) = match get_ident(&config, IdentRole::Committer) {
+        Ok(ident) => ident,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    let (author, author_timestamp) = match (&head_commit, amend) {
+        (Some(commit), true) => (commit.author.clone(), commit.author_timestamp),
+        _ => match get_ident(&config, IdentRole::Author) {
+            Ok(ident) => ident,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        },
+    };
+
+    let is_root = parents.is_empty();
+    let commit = CommitObject {
+        tree_hash,
+        parents,
+        author,
+        author_timestamp,
+        committer: committer.clone(),
+        commit_timestamp,
+        encoding,
+        mergetags: Vec::new(),
+        gpgsig: None,
+        extra_headers: Vec::new(),
+        message,
+    };
+    let subject = commit.subject();
+    let hash = match ObjectWriter::write(&repo, commit) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("error: failed to write commit object: {}", e);
+            return 1;
+        },
+    };
+
+    let kind = if amend {
+        RefLogKind::CommitAmend
+    } else if is_root {
+        RefLogKind::CommitInitial
+    } else {
+        RefLogKind::Commit
+    };
+    let log = RefLogMessage {
+        committer,
+        timestamp: commit_timestamp,
+        kind,
+        description: subject.clone(),
+    };
+    if let Err(e) = update_ref(&repo, "HEAD", &hash, Some(head.unwrap_or_else(Hash::zero)), Some(&log)) {
+        eprintln!("error: {}", e);
+        return 1;
+    }
+
+    if !quiet {
+        let branch_name = branch.strip_prefix("refs/heads/").unwrap_or("detached HEAD");
+        let root = if is_root { " (root-commit)" } else { "" };
+        println!("[{}{} {}] {}", branch_name, root, &hash.to_string()[..7], subject);
+    }
+    0
+}
+
+fn do_tag(subcommand_args: Vec<String>) -> i32 {
+    let mut annotate = false;
+    let mut force = false;
+    let mut delete = false;
+    let mut list = false;
+    let mut messages: Vec<String> = Vec::new();
+    let mut message_file: Option<String> = None;
+    let mut args: Vec<String> = Vec::new();
+
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-a" | "--annotate" => annotate = true,
+            "-f" | "--force" => force = true,
+            "-d" | "--delete" => delete = true,
+            "-l" | "--list" => list = true,
+            "-m" | "--message" | "-F" | "--file" => {
+                let value = match iter.next() {
+                    Some(value) => value.to_string(),
+                    None => {
+                        eprintln!("error: switch `{}' requires a value", arg.trim_start_matches('-'));
+                        return 1;
+                    },
+                };
+                if arg == "-m" || arg == "--message" {
+                    messages.push(value);
+                } else {
+                    message_file = Some(value);
+                }
+            },
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                eprintln!("error: unknown option: {}", arg);
+                return 1;
+            },
+            _ => args.push(arg.to_string()),
+        }
+    }
+
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    if delete {
+        let mut status = 0;
+        for name in args.iter() {
+            let refname = format!("refs/tags/{}", name);
+            match resolve_ref(&repo, &refname) {
+                Ok(Some(hash)) => match delete_ref(&repo, &refname, Some(hash)) {
+                    Ok(()) => println!("Deleted tag '{}' (was {})", name, &hash.to_string()[..7]),
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        status = 1;
+                    },
+                },
+                _ => {
+                    eprintln!("error: tag '{}' not found.", name);
+                    status = 1;
+                },
+            }
+        }
+        return status;
+    }
+
+    if list || args.is_empty() {
+        let tags = match list_refs(&repo, "refs/tags/") {
+            Ok(tags) => tags,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        };
+        for tag in tags.iter() {
+            let name = &tag.name["refs/tags/".len()..];
+            if args.is_empty() || args.iter().any(|pattern| wildmatch(pattern, name, false)) {
+                println!("{}", name);
+            }
+        }
+        return 0;
+    }
+
+    if args.len() > 2 {
+        eprintln!("Usage: tag [-a] [-f] [-m <msg> | -F <file>] <tagname> [<commit>]");
+        return 1;
+    }
+    let name = &args[0];
+    let refname = format!("refs/tags/{}", name);
+    if !is_valid_refname(&refname) {
+        eprintln!("error: '{}' is not a valid tag name.", name);
+        return 1;
+    }
+    let target = args.get(1).map_or("HEAD", |arg| arg.as_str());
+    let target = match resolve_revision(&repo, target) {
+        Ok(hash) => hash,
+        Err(_) => {
+            eprintln!("error: Failed to resolve '{}' as a valid ref.", target);
+            return 1;
+        },
+    };
+
+    let previous = match resolve_ref(&repo, &refname) {
+        Ok(previous) => previous,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    if previous.is_some() && !force {
+        eprintln!("error: tag '{}' already exists", name);
+        return 1;
+    }
+
+    let annotate = annotate || !messages.is_empty() || message_file.is_some();
+    let hash = if annotate {
+        let message = match message_file {
+            Some(file) => match std::fs::read_to_string(&file) {
+                Ok(content) => cleanup_message(&content, true),
+                Err(e) => {
+                    eprintln!("error: could not open or read '{}': {}", file, e);
+                    return 1;
+                },
+            },
+            None => cleanup_message(&messages.join("\n\n"), true),
+        };
+        if message.is_empty() {
+            eprintln!("error: no tag message?");
+            return 1;
+        }
+
+        let object_type = match ObjectReader::new(&repo, &target.to_string()) {
+            Ok(reader) => reader.object_type(),
+            Err(e) => {
+                eprintln!("error: failed to read object {}: {}", target, e);
+                return 1;
+            },
+        };
+        let tagger = match Config::load(Some(&repo)).and_then(|config| get_ident(&config, IdentRole::Committer)) {
+            Ok(ident) => ident,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        };
+        let tag = TagObject {
+            object: target,
+            object_type,
+            tag: name.to_string(),
+            tagger: Some(tagger),
+            message,
+            signature: None,
+        };
+        match ObjectWriter::write(&repo, tag) {
+            Ok(hash) => hash,
+            Err(e) => {
+                eprintln!("error: unable to write tag file: {}", e);
+                return 1;
+            },
+        }
+    } else {
+        target
+    };
+
+    if let Err(e) = update_ref(&repo, &refname, &hash, Some(previous.unwrap_or_else(Hash::zero)), None) {
+        eprintln!("error: {}", e);
+        return 1;
+    }
+    if let Some(previous) = previous {
+        if previous != hash {
+            println!("Updated tag '{}' (was {})", name, &previous.to_string()[..7]);
+        }
+    }
+    0
+}
+
+// --window=<n> / --depth=<n> を解釈する。該当しないオプションなら false を返す
+fn parse_pack_option(option: &str, options: &mut PackOptions) -> Result<bool, String> {
+    let (target, value) = if let Some(value) = option.strip_prefix("--window=") {
+        (&mut options.window, value)
+    } else if let Some(value) = option.strip_prefix("--depth=") {
+        (&mut options.depth, value)
+    } else {
+        return Ok(false);
+    };
+    *target = value.parse().map_err(|_| format!("invalid value for {}", option))?;
+    Ok(true)
+}
+
+// core.bigFileThreshold を読む
+fn big_file_threshold(repo: &Repository) -> io::Result<u64> {
+    let threshold = Config::load(Some(repo))?.get_int("core.bigfilethreshold")?;
+    Ok(threshold.map_or(DEFAULT_BIG_FILE_THRESHOLD, |threshold| threshold.max(0) as u64))
+}
+
+fn do_pack_objects(subcommand_args: Vec<String>) -> i32 {
+    let (options, args) = split_options(&subcommand_args);
+    let mut pack_options = PackOptions::default();
+    for option in options {
+        match parse_pack_option(option, &mut pack_options) {
+            Ok(true) => {},
+            Ok(false) => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        }
+    }
+    if args.len() != 1 {
+        eprintln!("Usage: pack-objects [--window=<n>] [--depth=<n>] <base-name> < <object-list>");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    match big_file_threshold(&repo) {
+        Ok(threshold) => pack_options.big_file_threshold = threshold,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    }
+
+    // 1 行に "<hash>" または "<hash> <path>"
+    let mut input = String::new();
+    if stdin().read_to_string(&mut input).is_err() {
+        eprintln!("error: failed to read stdin");
+        return 1;
+    }
+    let mut objects = Vec::new();
+    for line in input.lines().filter(|line| !line.is_empty()) {
+        let (hash, path) = match line.split_once(' ') {
+            Some((hash, path)) => (hash, Some(path.to_string())),
+            None => (line, None),
+        };
+        match hash.parse() {
+            Ok(hash) => objects.push(PackObject { hash, path }),
+            Err(_) => {
+                eprintln!("error: expected object ID, got garbage:\n {}", line);
+                return 1;
+            },
+        }
+    }
+
+    match write_pack(&repo, &objects, &pack_options, Path::new(&args[0])) {
+        Ok(checksum) => {
+            println!("{}", checksum);
+            0
+        },
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+fn do_repack(subcommand_args: Vec<String>) -> i32 {
+    let (options, args) = split_options(&subcommand_args);
+    let mut all = false;
+    let mut delete = false;
+    let mut pack_options = PackOptions::default();
+    for option in options {
+        match option {
+            "-a" => all = true,
+            "-d" => delete = true,
+            "-ad" | "-da" => {
+                all = true;
+                delete = true;
+            },
+            "-q" | "--quiet" => {},
+            _ => match parse_pack_option(option, &mut pack_options) {
+                Ok(true) => {},
+                Ok(false) => {
+                    eprintln!("error: unknown option: {}", option);
+                    return 1;
+                },
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    return 1;
+                },
+            },
+        }
+    }
+    if !args.is_empty() {
+        eprintln!("Usage: repack [-a] [-d] [--window=<n>] [--depth=<n>]");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    match big_file_threshold(&repo) {
+        Ok(threshold) => pack_options.big_file_threshold = threshold,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    }
+
+    match repack(&repo, all, delete, &pack_options) {
+        Ok(Some(_)) => 0,
+        Ok(None) => {
+            println!("Nothing new to pack.");
+            0
+        },
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+fn do_prune(subcommand_args: Vec<String>) -> i32 {
+    let (options, args) = split_options(&subcommand_args);
+    // git と同様に、--expire がなければ日時に関係なく到達不可能なものをすべて消す
+    let mut expire = i64::MAX;
+    let mut dry_run = false;
+    let mut verbose = false;
+    for option in options {
+        match option {
+            "-n" | "--dry-run" => dry_run = true,
+            "-v" | "--verbose" => verbose = true,
+            _ => match option.strip_prefix("--expire=") {
+                Some(date) => match parse_approxidate(date, Timestamp::now().epoch()) {
+                    Ok(date) => expire = date,
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        return 1;
+                    },
+                },
+                None => {
+                    eprintln!("error: unknown option: {}", option);
+                    return 1;
+                },
+            },
+        }
+    }
+    if !args.is_empty() {
+        eprintln!("Usage: prune [-n] [-v] [--expire=<date>]");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let pruned = match prune(&repo, expire, dry_run) {
+        Ok(pruned) => pruned,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    if dry_run || verbose {
+        for (hash, obj_type) in pruned {
+            match obj_type {
+                Some(obj_type) => println!("{} {}", hash, obj_type),
+                None => println!("{} unknown", hash),
+            }
+        }
+    }
+    0
+}
+
+fn do_gc(subcommand_args: Vec<String>) -> i32 {
+    let (options, args) = split_options(&subcommand_args);
+    let mut auto = false;
+    let mut quiet = false;
+    let mut aggressive = false;
+    let mut prune_date: Option<Option<String>> = None;
+    for option in options {
+        match option {
+            "--auto" => auto = true,
+            "-q" | "--quiet" => quiet = true,
+            "--aggressive" => aggressive = true,
+            "--no-prune" => prune_date = Some(None),
+            _ => match option.strip_prefix("--prune=") {
+                Some(date) => prune_date = Some(Some(date.to_string())),
+                None => {
+                    eprintln!("error: unknown option: {}", option);
+                    return 1;
+                },
+            },
+        }
+    }
+    if !args.is_empty() {
+        eprintln!("Usage: gc [--auto] [--aggressive] [--quiet] [--prune=<date> | --no-prune]");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let config = match Config::load(Some(&repo)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let limit = |key: &str, default: usize| config.get(key).and_then(|value| value.parse().ok()).unwrap_or(default);
+    if auto {
+        let needed = too_many_loose_objects(&repo, limit("gc.auto", 6700))
+            .and_then(|loose| Ok(loose || too_many_packs(&repo, limit("gc.autopacklimit", 50))?));
+        match needed {
+            Ok(true) => {},
+            Ok(false) => return 0,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        }
+        if !quiet {
+            eprintln!("Auto packing the repository for optimum performance.");
+        }
+    }
+
+    let now = Timestamp::now().epoch();
+    let prune_date = prune_date.unwrap_or_else(|| Some(config.get("gc.pruneexpire").unwrap_or("2.weeks.ago").to_string()));
+    let reflog_date = config.get("gc.reflogexpire").unwrap_or("90.days.ago");
+    let dates = prune_date.as_deref().map(|date| parse_approxidate(date, now)).transpose()
+        .and_then(|prune_expire| Ok((prune_expire, parse_approxidate(reflog_date, now)?)));
+    let (prune_expire, reflog_expire) = match dates {
+        Ok(dates) => dates,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let mut pack_options = PackOptions::default();
+    if aggressive {
+        pack_options.window = 250;
+    }
+    match big_file_threshold(&repo) {
+        Ok(threshold) => pack_options.big_file_threshold = threshold,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    }
+    let options = GcOptions { reflog_expire, prune_expire, pack_options };
+    match gc(&repo, &options) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+fn do_fsck(subcommand_args: Vec<String>) -> i32 {
+    let (options, args) = split_options(&subcommand_args);
+    let mut show_dangling = true;
+    let mut show_unreachable = false;
+    let mut porcelain = false;
+    for option in options {
+        match option {
+            "--porcelain" => porcelain = true,
+            "--dangling" => show_dangling = true,
+            "--no-dangling" => show_dangling = false,
+            "--unreachable" => show_unreachable = true,
+            _ => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+        }
+    }
+    if !args.is_empty() {
+        eprintln!("Usage: fsck [--unreachable] [--[no-]dangling] [--porcelain]");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let problems = match fsck(&repo) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    // --porcelain では 1 行に "<種類>\t<型>\t<ID>\t<詳細>" の形式で出力する
+    let print = |problem: &FsckProblem| match porcelain {
+        true => println!("{}", problem.machine_line()),
+        false => println!("{}", problem),
+    };
+    let mut status = 0;
+    for problem in problems.iter() {
+        if problem.is_error() {
+            print(problem);
+            status = 1;
+        } else if let FsckProblem::Unreachable { dangling, .. } = problem {
+            if show_unreachable || (show_dangling && *dangling) {
+                print(problem);
+            }
+        }
+    }
+    status
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RevParseOutput {
+    Full,
+    Short(usize),
+    AbbrevRef,
+    SymbolicFullName,
+}
+
+fn print_revision(repo: &Repository, spec: &str, prefix: &str, output: RevParseOutput) -> io::Result<()> {
+    let hash = resolve_revision(repo, spec)?;
+    match output {
+        RevParseOutput::Full => println!("{}{}", prefix, hash),
+        RevParseOutput::Short(len) => println!("{}{}", prefix, find_unique_abbrev(repo, &hash, len)?),
+        // ref でないものは何も表示しない
+        RevParseOutput::AbbrevRef | RevParseOutput::SymbolicFullName => {
+            if let Some(refname) = resolve_ref_name(repo, spec)? {
+                let name = if output == RevParseOutput::AbbrevRef { shorten_refname(&refname) } else { &refname };
+                println!("{}{}", prefix, name);
+            }
+        },
+    }
+    Ok(())
+}
+
+fn do_rev_parse(subcommand_args: Vec<String>) -> i32 {
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let mut verify = false;
+    let mut quiet = false;
+    let mut output = RevParseOutput::Full;
+    let mut revisions = Vec::new();
+    for arg in subcommand_args.iter() {
+        match arg.as_str() {
+            "--verify" => verify = true,
+            "-q" | "--quiet" => quiet = true,
+            "--short" => output = RevParseOutput::Short(DEFAULT_ABBREV),
+            "--abbrev-ref" => output = RevParseOutput::AbbrevRef,
+            "--symbolic-full-name" => output = RevParseOutput::SymbolicFullName,
+            "--git-dir" => println!("{}", repo.git_dir().display()),
+            "--git-common-dir" => println!("{}", repo.common_dir().display()),
+            "--is-bare-repository" => println!("{}", repo.is_bare()),
+            "--show-toplevel" => match repo.work_tree() {
+                Some(work_tree) => println!("{}", work_tree.display()),
+                None => {
+                    eprintln!("error: this operation must be run in a work tree");
+                    return 1;
+                },
+            },
+            _ => {
+                if let Some(len) = arg.strip_prefix("--short=") {
+                    match len.parse() {
+                        Ok(len) => output = RevParseOutput::Short(len),
+                        Err(_) => {
+                            eprintln!("error: invalid --short value: {}", len);
+                            return 1;
+                        },
+                    }
+                } else if arg.starts_with('-') {
+                    eprintln!("error: unknown option: {}", arg);
+                    return 1;
+                } else {
+                    revisions.push(arg.as_str());
+                }
+            },
+        }
+    }
+
+    if verify {
+        let result = match revisions.as_slice() {
+            [spec] => print_revision(&repo, spec, "", output),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Needed a single revision")),
+        };
+        return match result {
+            Ok(()) => 0,
+            Err(_) if quiet => 1,
+            Err(_) => {
+                eprintln!("error: Needed a single revision");
+                1
+            },
+        };
+    }
+
+    for spec in revisions {
+        // "A..B" は B と ^A に、"^A" は除外するリビジョンとして表示する
+        let result = match spec.find("..") {
+            Some(pos) if !spec.starts_with(':') => {
+                let (from, to) = (&spec[..pos], &spec[pos + 2..]);
+                let from = if from.is_empty() { "HEAD" } else { from };
+                let to = if to.is_empty() { "HEAD" } else { to };
+                print_revision(&repo, to, "", output).and_then(|_| print_revision(&repo, from, "^", output))
+            },
+            _ => match spec.strip_prefix('^') {
+                Some(rest) => print_revision(&repo, rest, "^", output),
+                None => print_revision(&repo, spec, "", output),
+            },
+        };
+        if let Err(e) = result {
+            if !quiet {
+                eprintln!("error: {}", e);
+            }
+            return 1;
+        }
+    }
+    0
+}
+
+// cat-file -p でのツリーの表示 (モードは 6 桁にそろえる)
+fn print_tree(out: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    for entry in TreeObject::parse(body)?.entries() {
+        writeln!(out, "{:06o} {} {}\t{}", entry.mode.0, entry.mode.object_type(), entry.hash, entry.name)?;
+    }
+    Ok(())
+}
+
+fn expand_batch_format(format: &str, hash: &Hash, obj_type: ObjectType, size: u64, rest: &str) -> io::Result<String> {
+    let mut out = String::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find("%(") {
+        out.push_str(&remaining[..start]);
+        let end = remaining[start..].find(')')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unterminated format element: {}", &remaining[start..])))?;
+        match &remaining[start + 2..start + end] {
+            "objectname" => out.push_str(&hash.to_string()),
+            "objecttype" => out.push_str(obj_type.as_str()),
+            "objectsize" => out.push_str(&size.to_string()),
+            "rest" => out.push_str(rest),
+            atom => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown format element: {}", atom))),
+        }
+        remaining = &remaining[start + end + 1..];
+    }
+    out.push_str(remaining);
+    Ok(out)
+}
+
+// 標準入力から 1 行に 1 つずつオブジェクトを読み、format に従って (print_contents なら中身も) 出力する
+fn cat_file_batch(repo: &Repository, format: &str, print_contents: bool, buffer: bool) -> io::Result<()> {
+    // 書式に %(rest) があるときだけ、最初の空白より後ろを rest として扱う
+    let split_rest = format.contains("%(rest)");
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    for line in io::BufRead::lines(stdin().lock()) {
+        let line = line?;
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(pos) if split_rest => (&line[..pos], line[pos..].trim_start()),
+            _ => (line.as_str(), ""),
+        };
+        let found = resolve_revision(repo, name).and_then(|hash| Ok((hash, ObjectReader::new(repo, &hash.to_string())?)));
+        match found {
+            Ok((hash, mut reader)) => {
+                let line = expand_batch_format(format, &hash, reader.object_type(), reader.size(), rest)?;
+                writeln!(out, "{}", line)?;
+                if print_contents {
+                    io::copy(&mut reader, &mut out)?;
+                    writeln!(out)?;
+                }
+            },
+            Err(e) if AmbiguousObjectName::is_cause_of(&e) => writeln!(out, "{} ambiguous", name)?,
+            Err(_) => writeln!(out, "{} missing", name)?,
+        }
+        // 対話的に使えるよう、--buffer がなければオブジェクトごとに書き出す
+        if !buffer {
+            out.flush()?;
+        }
+    }
+    out.flush()
+}
+
+fn do_cat_file(subcommand_args: Vec<String>) -> i32 {
+    const USAGE: &str = "Usage: cat-file (-t | -s | -e | -p | <type>) <object>\n   or: cat-file (--batch | --batch-check)[=<format>] [--buffer]";
+    const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+    let mut mode = None;
+    let mut batch = None;
+    let mut buffer = false;
+    let mut args = Vec::new();
+    for arg in subcommand_args.iter() {
+        match arg.as_str() {
+            "-t" | "-s" | "-e" | "-p" => mode = Some(arg.as_str()),
+            "--buffer" => buffer = true,
+            "--batch" => batch = Some((DEFAULT_BATCH_FORMAT, true)),
+            "--batch-check" => batch = Some((DEFAULT_BATCH_FORMAT, false)),
+            _ => {
+                if let Some(format) = arg.strip_prefix("--batch=") {
+                    batch = Some((format, true));
+                } else if let Some(format) = arg.strip_prefix("--batch-check=") {
+                    batch = Some((format, false));
+                } else if arg.starts_with('-') {
+                    eprintln!("error: unknown option: {}", arg);
+                    return 1;
+                } else {
+                    args.push(arg.as_str());
+                }
+            },
+        }
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    if let Some((format, print_contents)) = batch {
+        if mode.is_some() || !args.is_empty() {
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+        return match cat_file_batch(&repo, format, print_contents, buffer) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                1
+            },
+        };
+    }
+
+    // "cat-file <type> <object>" はタグやコミットをたどって指定の型の中身を出力する
+    let (expected_type, name) = match (mode, args.as_slice()) {
+        (Some(_), [name]) => (None, *name),
+        (None, [obj_type, name]) => match obj_type.parse::<ObjectType>() {
+            Ok(obj_type) => (Some(obj_type), *name),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 1;
+        },
+    };
+    let result = resolve_revision(&repo, name).and_then(|hash| match expected_type {
+        Some(obj_type) => revision::peel_to_type(&repo, hash, obj_type),
+        None => Ok(hash),
+    });
+    let hash = match result {
+        Ok(hash) => hash,
+        Err(_) if mode == Some("-e") => return 1,
+        Err(e) => {
+            eprintln!("error: Not a valid object name {}: {}", name, e);
+            return 1;
+        },
+    };
+    let mut reader = match ObjectReader::new(&repo, &hash.to_string()) {
+        Ok(reader) => reader,
+        Err(_) if mode == Some("-e") => return 1,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = match mode {
+        Some("-t") => writeln!(out, "{}", reader.object_type()),
+        Some("-s") => writeln!(out, "{}", reader.size()),
+        Some("-e") => Ok(()),
+        Some("-p") if reader.object_type() == ObjectType::Tree => {
+            let mut body = Vec::new();
+            reader.read_to_end(&mut body).and_then(|_| print_tree(&mut out, &body))
+        },
+        _ => io::copy(&mut reader, &mut out).map(|_| ()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+// カレントディレクトリ (ワークツリー基準で prefix) から見たパス
+fn display_path(prefix: &str, path: &str) -> String {
+    let mut base = prefix;
+    let mut up = String::new();
+    while !base.is_empty() {
+        if let Some(rest) = path.strip_prefix(base).and_then(|rest| rest.strip_prefix('/')) {
+            return format!("{}{}", up, rest);
+        }
+        if path == base {
+            return if up.is_empty() { "./".to_string() } else { up };
+        }
+        base = base.rsplit_once('/').map_or("", |(parent, _)| parent);
+        up.push_str("../");
+    }
+    format!("{}{}", up, path)
+}
+
+// カレントディレクトリ以下のパスを表す prefix (ワークツリーのないリポジトリでは空)
+fn current_prefix(repo: &Repository) -> io::Result<String> {
+    match repo.work_tree() {
+        Some(_) => repo.to_repo_path(Path::new(".")),
+        None => Ok(String::new()),
+    }
+}
+
+struct LsTreeOptions {
+    recursive: bool,
+    show_trees: bool,
+    long: bool,
+    name_only: bool,
+    terminator: char,
+}
+
+enum TreeInterest {
+    Skip,
+    Matched,
+    // 指定されたパスを含むディレクトリ
+    Leading,
+}
+
+// ls-tree のパスは、末尾が "/" ならディレクトリの中身を表す
+fn tree_interest(items: &[(String, bool)], path: &str) -> TreeInterest {
+    if items.is_empty() {
+        return TreeInterest::Matched;
+    }
+    let mut interest = TreeInterest::Skip;
+    for (item, contents_only) in items {
+        let under = path.len() > item.len() && path.starts_with(item.as_str()) && path.as_bytes()[item.len()] == b'/';
+        if item.is_empty() || under || (!contents_only && Pathspec::matches_item(item, path)) {
+            return TreeInterest::Matched;
+        }
+        if item == path || (item.len() > path.len() && item.starts_with(path) && item.as_bytes()[path.len()] == b'/') {
+            interest = TreeInterest::Leading;
+        }
+    }
+    interest
+}
+
+fn list_tree(
+    repo: &Repository,
+    tree: &Hash,
+    dir: &str,
+    items: &[(String, bool)],
+    prefix: &str,
+    options: &LsTreeOptions,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let tree = match ObjectReader::read_object(repo, &tree.to_string())? {
+        Object::Tree(tree) => tree,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a tree", tree))),
+    };
+    for entry in tree.entries() {
+        let path = if dir.is_empty() { entry.name.clone() } else { format!("{}/{}", dir, entry.name) };
+        let (show, descend) = match tree_interest(items, &path) {
+            TreeInterest::Skip => continue,
+            TreeInterest::Matched if entry.mode.is_tree() && options.recursive => (options.show_trees, true),
+            TreeInterest::Matched => (true, false),
+            TreeInterest::Leading if entry.mode.is_tree() => (options.show_trees, true),
+            TreeInterest::Leading => continue,
+        };
+
+        if show {
+            let name = display_path(prefix, &path);
+            if options.name_only {
+                write!(out, "{}{}", name, options.terminator)?;
+            } else if options.long {
+                let size = match entry.mode.object_type() {
+                    ObjectType::Blob => ObjectReader::new(repo, &entry.hash.to_string())?.size().to_string(),
+                    _ => "-".to_string(),
+                };
+                write!(out, "{:06o} {} {} {:>7}\t{}{}", entry.mode.0, entry.mode.object_type(), entry.hash, size, name, options.terminator)?;
+            } else {
+                write!(out, "{:06o} {} {}\t{}{}", entry.mode.0, entry.mode.object_type(), entry.hash, name, options.terminator)?;
+            }
+        }
+        if descend {
+            list_tree(repo, &entry.hash, &path, items, prefix, options, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn do_ls_tree(subcommand_args: Vec<String>) -> i32 {
+    let (flags, args) = split_options(&subcommand_args);
+    let mut options = LsTreeOptions { recursive: false, show_trees: false, long: false, name_only: false, terminator: '\n' };
+    for flag in flags {
+        match flag {
+            "-r" => options.recursive = true,
+            "-t" => options.show_trees = true,
+            "-l" | "--long" => options.long = true,
+            "--name-only" | "--name-status" => options.name_only = true,
+            "-z" => options.terminator = '\0',
+            _ => {
+                eprintln!("error: unknown option: {}", flag);
+                return 1;
+            },
+        }
+    }
+    if args.is_empty() {
+        eprintln!("Usage: ls-tree [-r] [-t] [-l] [--name-only] [-z] <tree-ish> [<path>...]");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let result = current_prefix(&repo).and_then(|prefix| {
+        let tree = revision::peel_to_type(&repo, resolve_revision(&repo, &args[0])?, ObjectType::Tree)?;
+        let mut items = Vec::new();
+        for arg in args[1..].iter() {
+            let item = if repo.is_bare() { arg.trim_end_matches('/').to_string() } else { repo.to_repo_path(Path::new(arg))? };
+            // "." や ".." のようにディレクトリそのものを指す場合も中身を表示する
+            items.push((item, arg.ends_with('/') || Path::new(arg).file_name().is_none()));
+        }
+        // パスの指定がなければカレントディレクトリの中身を表示する
+        if items.is_empty() && !prefix.is_empty() {
+            items.push((prefix.clone(), true));
+        }
+        let stdout = io::stdout();
+        let mut out = io::BufWriter::new(stdout.lock());
+        list_tree(&repo, &tree, "", &items, &prefix, &options, &mut out)?;
+        out.flush()
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+// git と同様に、--exclude* や --exclude-standard が指定されたときだけ無視のルールを使う
+fn ls_files_ignore_rules(repo: &Repository, excludes: &[&str]) -> io::Result<IgnoreRules> {
+    let mut ignore = IgnoreRules::new(repo);
+    for option in excludes {
+        if *option == "--exclude-standard" {
+            ignore.add_standard(repo)?;
+        } else if let Some(pattern) = option.strip_prefix("--exclude=") {
+            ignore.add_pattern(pattern);
+        } else if let Some(path) = option.strip_prefix("--exclude-from=") {
+            ignore.add_exclude_file(Path::new(path))?;
+        } else if let Some(name) = option.strip_prefix("--exclude-per-directory=") {
+            ignore.set_per_directory(name);
+        }
+    }
+    Ok(ignore)
+}
+
+fn do_ls_files(subcommand_args: Vec<String>) -> i32 {
+    let (options, paths) = split_options(&subcommand_args);
+    let mut cached = false;
+    let mut stage = false;
+    let mut modified = false;
+    let mut others = false;
+    let mut ignored = false;
+    let mut deleted = false;
+    let mut terminator = '\n';
+    // 無視のルールは指定された順に加える
+    let mut excludes = Vec::new();
+    for option in options {
+        match option {
+            "-c" | "--cached" => cached = true,
+            "-s" | "--stage" => stage = true,
+            "-m" | "--modified" => modified = true,
+            "-o" | "--others" => others = true,
+            "-i" | "--ignored" => ignored = true,
+            "-d" | "--deleted" => deleted = true,
+            "-z" => terminator = '\0',
+            _ if option == "--exclude-standard" || option.starts_with("--exclude=")
+                || option.starts_with("--exclude-from=") || option.starts_with("--exclude-per-directory=") => excludes.push(option),
+            _ => {
+                eprintln!("error: unknown option: {}", option);
+                return 1;
+            },
+        }
+    }
+    // 何も指定がなければ index のファイルを表示する
+    if !(stage || modified || others || deleted) {
+        cached = true;
+    }
+    if ignored && !others && !cached {
+        eprintln!("error: ls-files -i must be used with either -o or -c");
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let (prefix, pathspec) = match current_prefix(&repo).and_then(|prefix| Ok((prefix, Pathspec::parse(&repo, &paths)?))) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    let index = match Index::read(&repo) {
+        Ok(index) => index,
+        Err(_) => {
+            eprintln!("error: failed to read the index");
+            return 1;
+        },
+    };
+    let ignore = match ls_files_ignore_rules(&repo, &excludes) {
+        Ok(ignore) => ignore,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    if ignored && ignore.is_empty() {
+        eprintln!("error: ls-files --ignored needs some exclude pattern");
+        return 1;
+    }
+    // パスの指定がなければカレントディレクトリ以下に限る
+    let selected = |path: &str| {
+        if pathspec.is_empty() {
+            Pathspec::matches_item(&prefix, path)
+        } else {
+            pathspec.matches(path)
+        }
+    };
+
+    let tracked: HashSet<&str> = index.entries().iter().map(|entry| entry.path.as_str()).collect();
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let result = (|| -> io::Result<()> {
+        if others {
+            for file in worktree::walk(&repo, &ignore, ignored)? {
+                if file.ignored == ignored && !tracked.contains(file.path.as_str()) && selected(&file.path) {
+                    write!(out, "{}{}", display_path(&prefix, &file.path), terminator)?;
+                }
+            }
+        }
+        for entry in index.entries().iter().filter(|entry| selected(&entry.path)) {
+            if ignored && !ignore.is_ignored(&entry.path, false) {
+                continue;
+            }
+            let path = display_path(&prefix, &entry.path);
+            if cached || stage {
+                if stage {
+                    write!(out, "{:06o} {} {}\t{}{}", entry.mode.0, entry.hash, entry.stage, path, terminator)?;
+                } else {
+                    write!(out, "{}{}", path, terminator)?;
+                }
+            }
+            if !modified && !deleted {
+                continue;
+            }
+            // 削除されたファイルは変更されたものとしても扱う
+            let metadata = worktree::symlink_metadata(&repo, &entry.path)?.filter(|metadata| !metadata.is_dir());
+            if deleted && metadata.is_none() {
+                write!(out, "{}{}", path, terminator)?;
+            }
+            let is_modified = match &metadata {
+                Some(metadata) => worktree::is_modified(&repo, entry, metadata)?,
+                None => true,
+            };
+            if modified && is_modified {
+                write!(out, "{}{}", path, terminator)?;
+            }
+        }
+        out.flush()
+    })();
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+// ref の値として渡された文字列を解決する (空文字列は「存在しないこと」を表すゼロハッシュ)
+fn resolve_ref_value(repo: &Repository, value: &str) -> io::Result<Hash> {
+    if value.is_empty() {
+        return Ok(Hash::zero());
+    }
+    resolve_revision(repo, value)
+}
+
+fn reflog_message(repo: &Repository, message: &str) -> io::Result<RefLogMessage> {
+    let config = Config::load(Some(repo))?;
+    let (committer, timestamp) = get_ident(&config, IdentRole::Committer)?;
+    Ok(RefLogMessage { committer, timestamp, kind: RefLogKind::Other(message.to_string()), description: String::new() })
+}
+
+// update-ref --stdin の 1 行 ("<command> SP <ref> [SP <value>]...") を transaction に加える
+fn add_ref_update(repo: &Repository, transaction: &mut RefTransaction, command: &str, args: &str) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let fields: Vec<&str> = if args.is_empty() { Vec::new() } else { args.split(' ').collect() };
+    let (required, optional) = match command {
+        "update" => (2, 1),
+        "create" => (2, 0),
+        "delete" | "verify" => (1, 1),
+        _ => return Err(invalid(format!("unknown command: {} {}", command, args))),
+    };
+    let name = match fields.first() {
+        Some(name) => *name,
+        None => return Err(invalid(format!("{}: missing <ref>", command))),
+    };
+    if fields.len() < required {
+        return Err(invalid(format!("{} {}: missing <newvalue>", command, name)));
+    }
+    if fields.len() > required + optional {
+        return Err(invalid(format!("{} {}: extra input: {}", command, name, fields[required + optional..].join(" "))));
+    }
+    let value = |i: usize, label: &str| -> io::Result<Option<Hash>> {
+        match fields.get(i) {
+            Some(value) => resolve_ref_value(repo, value)
+                .map(Some)
+                .map_err(|_| invalid(format!("{} {}: invalid <{}>: {}", command, name, label, value))),
+            None => Ok(None),
+        }
+    };
+
+    match command {
+        "update" => transaction.update(name, value(1, "newvalue")?.unwrap(), value(2, "oldvalue")?),
+        "create" => transaction.create(name, value(1, "newvalue")?.unwrap()),
+        "delete" => transaction.delete(name, value(1, "oldvalue")?),
+        // 値がなければ ref が存在しないことを確かめる
+        _ => transaction.verify(name, value(1, "oldvalue")?.unwrap_or_else(Hash::zero)),
+    }
+}
+
+// 標準入力のコマンドを transaction にまとめる。start で始めたものは commit がなければ破棄する
+fn update_refs_from_stdin(repo: &Repository, log: &RefLogMessage) -> io::Result<()> {
+    let mut transaction = RefTransaction::new();
+    let mut started = false;
+    let stdout = io::stdout();
+    for line in io::BufRead::lines(stdin().lock()) {
+        let line = line?;
+        let (command, args) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match command {
+            "start" => {
+                transaction = RefTransaction::new();
+                started = true;
+            },
+            "prepare" => transaction.prepare(repo)?,
+            "commit" => {
+                std::mem::take(&mut transaction).commit(repo, Some(log))?;
+                started = false;
+            },
+            "abort" => {
+                std::mem::take(&mut transaction).abort();
+                started = false;
+            },
+            _ => {
+                add_ref_update(repo, &mut transaction, command, args)?;
+                continue;
+            },
+        }
+        let mut out = stdout.lock();
+        writeln!(out, "{}: ok", command)?;
+        out.flush()?;
+    }
+    if started {
+        transaction.abort();
+        return Ok(());
+    }
+    transaction.commit(repo, Some(log))
+}
+
+fn do_update_ref(subcommand_args: Vec<String>) -> i32 {
+    const USAGE: &str = "Usage: update-ref [-m <reason>] (-d <ref> [<old>] | <ref> <new> [<old>] | --stdin)";
+    let mut message = String::new();
+    let mut delete = false;
+    let mut use_stdin = false;
+    let mut args = Vec::new();
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-m" => match iter.next() {
+                Some(value) => message = value.to_string(),
+                None => {
+                    eprintln!("error: switch `m' requires a value");
+                    return 1;
+                },
+            },
+            "-d" => delete = true,
+            "--stdin" => use_stdin = true,
+            "--" => args.extend(iter.by_ref().map(|arg| arg.as_str())),
+            _ if arg.starts_with('-') => {
+                eprintln!("error: unknown option: {}", arg);
+                return 1;
+            },
+            _ => args.push(arg.as_str()),
+        }
+    }
+    let valid = match (use_stdin, delete) {
+        (true, false) => args.is_empty(),
+        (true, true) => false,
+        (false, true) => (1..=2).contains(&args.len()),
+        (false, false) => (2..=3).contains(&args.len()),
+    };
+    if !valid {
+        eprintln!("{}", USAGE);
+        return 1;
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    let log = match reflog_message(&repo, &message) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+
+    let result = if use_stdin {
+        update_refs_from_stdin(&repo, &log)
+    } else {
+        let mut transaction = RefTransaction::new();
+        let values: io::Result<Vec<Hash>> = args[1..].iter().map(|value| resolve_ref_value(&repo, value)).collect();
+        values.and_then(|values| {
+            if delete {
+                transaction.delete(args[0], values.first().copied())?;
+            } else {
+                transaction.update(args[0], values[0], values.get(1).copied())?;
+            }
+            transaction.commit(&repo, Some(&log))
+        })
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+fn do_symbolic_ref(subcommand_args: Vec<String>) -> i32 {
+    const USAGE: &str = "Usage: symbolic-ref [-m <reason>] <name> <ref>\n   or: symbolic-ref [-q] [--short] <name>\n   or: symbolic-ref --delete [-q] <name>";
+    let mut message = None;
+    let mut quiet = false;
+    let mut short = false;
+    let mut delete = false;
+    let mut args = Vec::new();
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-m" => match iter.next() {
+                Some(value) => message = Some(value.as_str()),
+                None => {
+                    eprintln!("error: switch `m' requires a value");
+                    return 1;
+                },
+            },
+            "-q" | "--quiet" => quiet = true,
+            "--short" => short = true,
+            "-d" | "--delete" => delete = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("error: unknown option: {}", arg);
+                return 1;
+            },
+            _ => args.push(arg.as_str()),
+        }
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+    if let Some(name) = args.first() {
+        if *name != "HEAD" && !is_valid_refname(name) {
+            eprintln!("error: invalid ref name: {}", name);
+            return 1;
+        }
+    }
+
+    match args.as_slice() {
+        [name] if delete => {
+            if *name == "HEAD" {
+                eprintln!("error: deleting '{}' is not allowed", name);
+                return 1;
+            }
+            match read_ref(&repo, name) {
+                Ok(Some(RefTarget::Symbolic(_))) => {},
+                _ => {
+                    if !quiet {
+                        eprintln!("error: Cannot delete {}, not a symbolic ref", name);
+                    }
+                    return 1;
+                },
+            }
+            match delete_ref(&repo, name, None) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    1
+                },
+            }
+        },
+        [name] => match read_ref(&repo, name) {
+            Ok(Some(RefTarget::Symbolic(target))) => {
+                println!("{}", if short { shorten_refname(&target) } else { &target });
+                0
+            },
+            _ => {
+                if !quiet {
+                    eprintln!("error: ref {} is not a symbolic ref", name);
+                }
+                1
+            },
+        },
+        [name, target] if !delete => {
+            if *name == "HEAD" && !target.starts_with("refs/") {
+                eprintln!("error: Refusing to point HEAD outside of refs/");
+                return 1;
+            }
+            let log = match message.map(|message| reflog_message(&repo, message)).transpose() {
+                Ok(log) => log,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    return 1;
+                },
+            };
+            match update_symbolic_ref(&repo, name, target, log.as_ref()) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    1
+                },
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            1
+        },
+    }
+}
+
+// show-ref のパターンは ref 名の末尾の "/" 区切りの要素と一致すればよい
+fn show_ref_matches(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| {
+        name == pattern || (name.ends_with(pattern.as_str()) && name.as_bytes()[name.len() - pattern.len() - 1] == b'/')
+    })
+}
+
+fn do_show_ref(subcommand_args: Vec<String>) -> i32 {
+    let (options, patterns) = split_options(&subcommand_args);
+    let mut heads = false;
+    let mut tags = false;
+    let mut verify = false;
+    let mut quiet = false;
+    let mut show_head = false;
+    let mut dereference = false;
+    let mut hash_only = None;
+    for option in options {
+        match option {
+            "--heads" | "--branches" => heads = true,
+            "--tags" => tags = true,
+            "--verify" => verify = true,
+            "-q" | "--quiet" => quiet = true,
+            "--head" => show_head = true,
+            "-d" | "--dereference" => dereference = true,
+            "-s" | "--hash" => hash_only = Some(None),
+            _ => match option.strip_prefix("--hash=").map(|len| len.parse::<usize>()) {
+                Some(Ok(len)) => hash_only = Some(Some(len)),
+                _ => {
+                    eprintln!("error: unknown option: {}", option);
+                    return 1;
+                },
+            },
+        }
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let format_hash = |hash: &Hash| match hash_only {
+        Some(Some(len)) => find_unique_abbrev(&repo, hash, len).unwrap_or_else(|_| hash.to_string()),
+        _ => hash.to_string(),
+    };
+    let show = |name: &str, hash: &Hash, peeled: Option<Hash>| {
+        if quiet {
+            return;
+        }
+        match hash_only {
+            Some(_) => println!("{}", format_hash(hash)),
+            None => println!("{} {}", hash, name),
+        }
+        if dereference {
+            let peeled = peeled.or_else(|| peel_tags(&repo, *hash).ok().filter(|peeled| peeled != hash));
+            if let Some(peeled) = peeled {
+                match hash_only {
+                    Some(_) => println!("{}", format_hash(&peeled)),
+                    None => println!("{} {}^{{}}", peeled, name),
+                }
+            }
+        }
+    };
+
+    // --verify では完全な ref 名を要求する
+    if verify {
+        if patterns.is_empty() {
+            eprintln!("error: --verify requires a reference");
+            return 1;
+        }
+        for name in patterns.iter() {
+            let hash = if name == "HEAD" || name.starts_with("refs/") { resolve_ref(&repo, name).ok().flatten() } else { None };
+            match hash {
+                Some(hash) => show(name, &hash, None),
+                None => {
+                    if !quiet {
+                        eprintln!("error: '{}' - not a valid ref", name);
+                    }
+                    return 1;
+                },
+            }
+        }
+        return 0;
+    }
+
+    let mut found = false;
+    if show_head {
+        if let Ok(Some(hash)) = resolve_ref(&repo, "HEAD") {
+            show("HEAD", &hash, None);
+            found = true;
+        }
+    }
+    let refs = match list_refs(&repo, "refs/") {
+        Ok(refs) => refs,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    for r in refs {
+        let selected = (!heads && !tags) || (heads && r.name.starts_with("refs/heads/")) || (tags && r.name.starts_with("refs/tags/"));
+        if selected && show_ref_matches(&patterns, &r.name) {
+            show(&r.name, &r.hash, r.peeled);
+            found = true;
+        }
+    }
+    if found { 0 } else { 1 }
+}
+
+fn do_for_each_ref(subcommand_args: Vec<String>) -> i32 {
+    let mut format = DEFAULT_FORMAT.to_string();
+    let mut sort_keys = Vec::new();
+    let mut count = None;
+    let mut filter_args: Vec<(&str, String)> = Vec::new();
+    let mut patterns = Vec::new();
+    let mut iter = subcommand_args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let (option, value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match option {
+            "--format" | "--sort" | "--count" | "--points-at" => {
+                let value = match value.or_else(|| iter.next().cloned()) {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("error: option `{}' requires a value", &option[2..]);
+                        return 1;
+                    },
+                };
+                match option {
+                    "--format" => format = value,
+                    "--sort" => sort_keys.push(value),
+                    "--count" => match value.parse::<usize>() {
+                        Ok(n) => count = Some(n).filter(|&n| n > 0),
+                        Err(_) => {
+                            eprintln!("error: option `count' expects a numerical value");
+                            return 1;
+                        },
+                    },
+                    _ => filter_args.push((option, value)),
+                }
+            },
+            // コミットを省略したら HEAD とみなす
+            "--contains" | "--no-contains" | "--merged" | "--no-merged" => {
+                let value = match value {
+                    Some(value) => value,
+                    None => match iter.peek() {
+                        Some(next) if !next.starts_with('-') => iter.next().unwrap().to_string(),
+                        _ => "HEAD".to_string(),
+                    },
+                };
+                filter_args.push((option, value));
+            },
+            "--" => patterns.extend(iter.by_ref().cloned()),
+            _ if arg.starts_with('-') => {
+                eprintln!("error: unknown option: {}", arg);
+                return 1;
+            },
+            _ => patterns.push(arg.to_string()),
+        }
+    }
+    let repo = match open_repository() {
+        Some(repo) => repo,
+        None => return 1,
+    };
+
+    let format = match RefFormat::parse(&format) {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        },
+    };
+    // 後に指定したキーほど優先する
+    let mut keys = Vec::new();
+    for key in sort_keys.iter().rev() {
+        match SortKey::parse(key) {
+            Ok(key) => keys.push(key),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            },
+        }
+    }
+
+    let mut filter = RefFilter { patterns, ..RefFilter::default() };
+    for (option, value) in filter_args {
+        let hash = match option {
+            "--points-at" => resolve_revision(&repo, &value),
+            _ => resolve_revision(&repo, &value).and_then(|hash| revision::peel_to_type(&repo, hash, ObjectType::Commit)),
+        };
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(_) => {
+                eprintln!("error: malformed object name {}", value);
+                return 1;
+            },
+        };
+        match option {
+            "--points-at" => filter.points_at.push(hash),
+            "--contains" => filter.contains.push(hash),
+            "--no-contains" => filter.no_contains.push(hash),
+            "--merged" => filter.merged.push(hash),
+            _ => filter.no_merged.push(hash),
+        }
+    }
+
+    let result = Config::load(Some(&repo)).and_then(|config| {
+        let refs = filter_refs(&repo, &filter)?;
+        let mut contexts = refs.iter().map(|r| RefContext::new(&repo, &config, r)).collect::<io::Result<Vec<_>>>()?;
+        sort_refs(&mut contexts, &keys)?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for context in contexts.iter().take(count.unwrap_or(usize::MAX)) {
+            writeln!(out, "{}", context.format(&format)?)?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ConfigType {
+    Bool,
+    Int,
+    BoolOrInt,
+    Path,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ConfigAction {
+    Get,
+    GetAll,
+    GetRegexp,
+    List,
+    Set,
+    Add,
+    ReplaceAll,
+    Unset,
+    UnsetAll,
+}
+
+enum ConfigLocation {
+    Scope(ConfigScope),
+    File(PathBuf),
+}
+
+// --type に従って値を整える
+fn format_config_value(key: &str, value: Option<&str>, value_type: Option<ConfigType>) -> io::Result<String> {
+    let missing = || invalid_data(format!("missing value for '{}'", key));
+    let bad_bool = || invalid_data(format!("bad boolean config value '{}' for '{}'", value.unwrap_or(""), key));
+    match value_type {
+        None => Ok(value.unwrap_or("").to_string()),
+        Some(ConfigType::Bool) => parse_bool(value).map(|value| value.to_string()).ok_or_else(bad_bool),
+        Some(ConfigType::Int) => {
+            let value = value.ok_or_else(missing)?;
+            parse_int(value).map(|n| n.to_string()).ok_or_else(|| invalid_data(format!("bad numeric config value '{}' for '{}': invalid unit", value, key)))
+        },
+        Some(ConfigType::BoolOrInt) => match value.and_then(parse_int) {
+            Some(n) => Ok(n.to_string()),
+            None => parse_bool(value).map(|value| value.to_string()).ok_or_else(bad_bool),
+        },
+        Some(ConfigType::Path) => Ok(expand_path(value.ok_or_else(missing)?)?.to_string_lossy().into_owned()),
+    }
+}
+
+// git ディレクトリの中のファイルは作業ツリーの先頭 (git ディレクトリの中にいればカレントディレクトリ) からの相対パスで示す
+fn display_config_origin(path: &Path, repo: Option<&Repository>) -> String {
+    if let (Some(repo), Ok(cwd)) = (repo, std::env::current_dir()) {
+        let base = match repo.work_tree() {
+            Some(work_tree) if !cwd.starts_with(repo.git_dir()) => work_tree.to_path_buf(),
+            _ => cwd,
+        };
+        if path.starts_with(repo.git_dir()) {
+            if let Ok(relative) = path.strip_prefix(&base) {
+                return relative.display().to_string();
+            }
+        }
+    }
+    path.display().to_string()
+}
+
+fn read_config(
+    repo: Option<&Repository>,
+    location: Option<&ConfigLocation>,
+    action: ConfigAction,
+    args: &[String],
+    value_type: Option<ConfigType>,
+    (show_origin, show_scope, includes): (bool, bool, bool),
+) -> io::Result<i32> {
+    let config = match location {
+        None => Config::load(repo)?,
+        Some(ConfigLocation::Scope(scope)) => Config::load_scope(repo, *scope, includes)?,
+        Some(ConfigLocation::File(path)) => Config::load_file(path, None, repo, includes)?,
+    };
+    let value_pattern = args.get(1).map(|pattern| ValuePattern::parse(pattern)).transpose()?;
+    let key_matches: Box<dyn Fn(&str) -> bool> = match action {
+        ConfigAction::List => Box::new(|_| true),
+        ConfigAction::GetRegexp => {
+            // 最初と最後の要素は大文字小文字を区別しないので小文字にしておく
+            let pattern = &args[0];
+            let first = pattern.find('.').unwrap_or(pattern.len());
+            let last = pattern.rfind('.').map_or(0, |i| i + 1).max(first);
+            let pattern = format!("{}{}{}", pattern[..first].to_ascii_lowercase(), &pattern[first..last], pattern[last..].to_ascii_lowercase());
+            let regex = Regex::new(&pattern)?;
+            Box::new(move |key| regex.is_match(key))
+        },
+        _ => {
+            let key = normalize_key(&args[0])?;
+            Box::new(move |entry_key| entry_key == key)
+        },
+    };
+    let entries: Vec<&ConfigEntry> = config.entries().iter()
+        .filter(|entry| key_matches(&entry.key) && value_pattern.as_ref().is_none_or(|pattern| pattern.matches(entry.value.as_deref())))
+        .collect();
+    if entries.is_empty() && action != ConfigAction::List {
+        return Ok(1);
+    }
+    let entries = if action == ConfigAction::Get { &entries[entries.len() - 1..] } else { &entries[..] };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for entry in entries {
+        if show_scope {
+            write!(out, "{}\t", entry.scope.map_or("command".to_string(), |scope| scope.to_string()))?;
+        }
+        if show_origin {
+            match &entry.origin {
+                Some(path) => write!(out, "file:{}\t", display_config_origin(path, repo))?,
+                None => write!(out, "command line:\t")?,
+            }
+        }
+        let value = format_config_value(&entry.key, entry.value.as_deref(), value_type)?;
+        let has_value = entry.value.is_some() || value_type.is_some();
+        match action {
+            ConfigAction::List if has_value => writeln!(out, "{}={}", entry.key, value)?,
+            ConfigAction::GetRegexp if has_value => writeln!(out, "{} {}", entry.key, value)?,
+            ConfigAction::List | ConfigAction::GetRegexp => writeln!(out, "{}", entry.key)?,
+            _ => writeln!(out, "{}", value)?,
+        }
+    }
+    Ok(0)
+}
+
+fn write_config(path: &Path, action: ConfigAction, args: &[String], value_type: Option<ConfigType>) -> io::Result<i32> {
+    let key = &args[0];
+    normalize_key(key)?;
+    let value = match (action, args.get(1)) {
+        (ConfigAction::Unset, _) | (ConfigAction::UnsetAll, _) => None,
+        // --type=path 以外は正規化した値を書く
+        (_, Some(value)) => match value_type {
+            None | Some(ConfigType::Path) => Some(value.to_string()),
+            Some(_) => Some(format_config_value(key, Some(value), value_type)?),
+        },
+        (_, None) => None,
+    };
+    let pattern_arg = match action {
+        ConfigAction::Unset | ConfigAction::UnsetAll => args.get(1),
+        _ => args.get(2),
+    };
+    let pattern = pattern_arg.map(|pattern| ValuePattern::parse(pattern)).transpose()?;
+
+    let mut file = ConfigFile::open(path)?;
+    let count = file.count(key, pattern.as_ref())?;
+    match action {
+        ConfigAction::Set if count > 1 => {
+            eprintln!("warning: {} has multiple values", key);
+            eprintln!("error: cannot overwrite multiple values with a single value");
+            eprintln!("       Use a regexp, --add or --replace-all to change {}.", key);
+            return Ok(5);
+        },
+        ConfigAction::Unset | ConfigAction::UnsetAll if count == 0 => return Ok(5),
+        ConfigAction::Unset if count > 1 => {
+            eprintln!("warning: {} has multiple values", key);
+            return Ok(5);
+        },
+        ConfigAction::Add => file.add(key, value.as_deref().unwrap_or(""))?,
+        ConfigAction::Set | ConfigAction::ReplaceAll => file.set(key, value.as_deref().unwrap_or(""), pattern.as_ref())?,
+        _ => {
+            file.unset(key, pattern.as_ref())?;
+        },
+    }
+    file.commit()?;
+    Ok(0)
+}
+
+fn parse_config_type(name: &str) -> Option<ConfigType> {
+    match name {
+        "bool" => Some(ConfigType::Bool),
+        "int" => Some(ConfigType::Int),
+        "bool-or-int" => Some(ConfigType::BoolOrInt),
+        "path" => Some(ConfigType::Path),
+        _ => None,
+    }
+}
+
+fn do_config(subcommand_args: Vec<String>) -> i32 {
+    let mut actions = Vec::new();
+    let mut location = None;
+    let mut value_type = None;
+    let mut show_origin = false;
+    let mut show_scope = false;
+    // -f やスコープで読むファイルを限ったときは、include は --includes があるときだけ読む
+    let mut includes = false;
+    let mut args = Vec::new();
+    let mut iter = subcommand_args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--get" => actions.push(ConfigAction::Get),
+            "--get-all" => actions.push(ConfigAction::GetAll),
+            "--get-regexp" => actions.push(ConfigAction::GetRegexp),
+            "-l" | "--list" => actions.push(ConfigAction::List),
+            "--add" => actions.push(ConfigAction::Add),
+            "--replace-all" => actions.push(ConfigAction::ReplaceAll),
+            "--unset" => actions.push(ConfigAction::Unset),
+            "--unset-all" => actions.push(ConfigAction::UnsetAll),
+            "--system" => location = Some(ConfigLocation::Scope(ConfigScope::System)),
+            "--global" => location = Some(ConfigLocation::Scope(ConfigScope::Global)),
+            "--local" => location = Some(ConfigLocation::Scope(ConfigScope::Local)),
+            "--worktree" => location = Some(ConfigLocation::Scope(ConfigScope::Worktree)),
+            "-f" | "--file" => match iter.next() {
+                Some(path) => location = Some(ConfigLocation::File(PathBuf::from(path))),
+                None => {
+                    eprintln!("error: switch `file' requires a value");
+                    return 1;
+                },
+            },
+            "--show-origin" => show_origin = true,
+            "--show-scope" => show_scope = true,
+            "--includes" => includes = true,
+            "--no-includes" => includes = false,
+            "--bool" | "--int" | "--bool-or-int" | "--path" => value_type = parse_config_type(&arg[2..]),
+            "--" => args.extend(iter.by_ref().cloned()),
+            _ => {
+                if let Some(path) = arg.strip_prefix("--file=") {
+                    location = Some(ConfigLocation::File(PathBuf::from(path)));
+                } else if let Some(name) = arg.strip_prefix("--type=") {
+                    value_type = parse_config_type(name);
+                    if value_type.is_none() {
+                        eprintln!("error: unrecognized --type argument, {}", name);
+                        return 1;
+                    }
+                } else if arg.starts_with('-') && arg.len() > 1 {
+                    eprintln!("error: unknown option: {}", arg);
+                    return 1;
+                } else {
+                    args.push(arg.to_string());
+                }
+            },
+        }
+    }
+    actions.dedup();
+    if actions.len() > 1 {
+        eprintln!("error: only one action at a time");
+        return 1;
+    }
+
+    // 動作の指定がなければ引数の数で決める
+    let action = match actions.first() {
+        Some(&action) => action,
+        None if args.len() == 1 => ConfigAction::Get,
+        None if args.len() >= 2 => ConfigAction::Set,
+        None => {
+            eprintln!("Usage: config [<options>] (--get | --get-all | --get-regexp | --list | --add | --unset | --unset-all | --replace-all) [<args>]");
+            return 1;
+        },
+    };
+    let (min, max) = match action {
+        ConfigAction::List => (0, 0),
+        ConfigAction::Get | ConfigAction::GetAll | ConfigAction::GetRegexp | ConfigAction::Unset | ConfigAction::UnsetAll => (1, 2),
+        ConfigAction::Add => (2, 2),
+        ConfigAction::Set | ConfigAction::ReplaceAll => (2, 3),
+    };
+    if args.len() < min || args.len() > max {
+        match min == max {
+            true => eprintln!("error: wrong number of arguments, should be {}", min),
+            false => eprintln!("error: wrong number of arguments, should be from {} to {}", min, max),
+        }
+        return 1;
+    }
+
+    let repo = Repository::discover().ok();
+    let result = match action {
+        ConfigAction::Get | ConfigAction::GetAll | ConfigAction::GetRegexp | ConfigAction::List => {
+            read_config(repo.as_ref(), location.as_ref(), action, &args, value_type, (show_origin, show_scope, includes))
+        },
+        _ => {
+            let path = match location {
+                Some(ConfigLocation::File(path)) => Ok(path),
+                Some(ConfigLocation::Scope(scope)) => scope_path(repo.as_ref(), scope),
+                None => scope_path(repo.as_ref(), ConfigScope::Local),
+            };
+            path.and_then(|path| write_config(&path, action, &args, value_type))
+        },
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        },
+    }
+}
+
 fn main() {
     let mut args: Vec<String> = args().collect();
 
@@ -241,14 +3160,34 @@ fn main() {
     let subcommand_args = args;
 
     let exit_code: i32 = match subcommand.as_str() {
-        "init"         => do_init(),
+        "init"         => do_init(subcommand_args),
         "write-object" => do_write_object(),
         "read-object"  => do_read_object(subcommand_args),
         "write-blob"   => do_write_blob(),
-        "write-tree"   => do_write_tree(),
-        "tree-test"    => do_tree_test(),
-        "commit-test"  => do_commit_test(),
-        "reflog-test"  => do_reflog_test(),
+        "hash-object"  => do_hash_object(subcommand_args),
+        "write-tree"   => do_write_tree(subcommand_args),
+        "mktree"       => do_mktree(subcommand_args),
+        "commit-tree"  => do_commit_tree(subcommand_args),
+        "update-ref"   => do_update_ref(subcommand_args),
+        "symbolic-ref" => do_symbolic_ref(subcommand_args),
+        "show-ref"     => do_show_ref(subcommand_args),
+        "for-each-ref" => do_for_each_ref(subcommand_args),
+        "config"       => do_config(subcommand_args),
+        "reflog"       => do_reflog(subcommand_args),
+        "add"          => do_add(subcommand_args),
+        "rm"           => do_rm(subcommand_args),
+        "mv"           => do_mv(subcommand_args),
+        "commit"       => do_commit(subcommand_args),
+        "tag"          => do_tag(subcommand_args),
+        "pack-objects" => do_pack_objects(subcommand_args),
+        "repack"       => do_repack(subcommand_args),
+        "prune"        => do_prune(subcommand_args),
+        "gc"           => do_gc(subcommand_args),
+        "fsck"         => do_fsck(subcommand_args),
+        "rev-parse"    => do_rev_parse(subcommand_args),
+        "cat-file"     => do_cat_file(subcommand_args),
+        "ls-tree"      => do_ls_tree(subcommand_args),
+        "ls-files"     => do_ls_files(subcommand_args),
         _ => {
             eprintln!("unknown subcommand: {:}", subcommand);
             1